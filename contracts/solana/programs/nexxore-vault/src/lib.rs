@@ -1,22 +1,238 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::keccak::hashv;
+use anchor_lang::solana_program::program::invoke;
+use anchor_spl::associated_token::{get_associated_token_address, AssociatedToken};
+use anchor_spl::token::spl_token::native_mint;
+use anchor_spl::token::{self, Burn, CloseAccount, Mint, MintTo, Token, TokenAccount, Transfer};
+use pyth_sdk_solana::state::SolanaPriceAccount;
 
+#[cfg(not(feature = "migration-target"))]
 declare_id!("NexxVau1t111111111111111111111111111111111");
+// Id of the second deployment the tests migrate positions into
+#[cfg(feature = "migration-target")]
+declare_id!("NexxVau1tV211111111111111111111111111111111");
+
+pub const BPS_DENOMINATOR: u64 = 10_000;
+pub const SECONDS_PER_DAY: i64 = 86_400;
+pub const SECONDS_PER_YEAR: i64 = 365 * SECONDS_PER_DAY;
+/// Fixed-point scale for price-per-share values
+pub const PPS_SCALE: u64 = 1_000_000_000;
+/// Extra decimals shares carry over the underlying by default, so small
+/// deposits into a low-decimal token lose less to rounding
+pub const SHARE_DECIMALS: u32 = 3;
+/// Shares minted per unit of assets into an empty vault at the default
+/// `initial_pps` of `PPS_SCALE` and default share decimals
+pub const SHARE_SCALE: u64 = 10u64.pow(SHARE_DECIMALS);
+/// Most extra decimals `initialize` accepts for shares over the underlying
+pub const MAX_EXTRA_SHARE_DECIMALS: u8 = 9;
+pub const PPS_HISTORY_LEN: usize = 16;
+/// Most deposit fee tiers a vault may configure
+pub const MAX_FEE_TIERS: usize = 3;
+/// Open withdrawal requests a user may hold in a new vault
+pub const DEFAULT_MAX_REQUESTS_PER_USER: u8 = 4;
+/// Bytes in `ImportBitmap`, one bit per importable v1 position
+pub const IMPORT_BITMAP_BYTES: usize = 1024;
+/// Fixed-point scale for `Vault::reward_per_share_stored`
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+/// Fixed-point scale for `Vault::supply_index`; the index starts at 1.0
+pub const INDEX_SCALE: u128 = 1_000_000_000_000_000_000;
+
+// Bits of `Vault::paused_ops`; a set bit disables the instruction
+pub const OP_DEPOSIT: u16 = 1 << 0;
+pub const OP_WITHDRAW: u16 = 1 << 1;
+pub const OP_MIGRATE: u16 = 1 << 2;
+pub const OP_REQUEST_WITHDRAWAL: u16 = 1 << 3;
+
+// Values of `Vault::rounding`
+/// Round minted shares and redeemed assets down (the default)
+pub const ROUNDING_FAVOR_VAULT: u8 = 0;
+/// Round minted shares and redeemed assets up, for subsidized vaults
+pub const ROUNDING_FAVOR_USER: u8 = 1;
+
+// Values of `Vault::daily_limit_mode`
+/// The daily withdrawal limit counts every withdrawal (the default)
+pub const DAILY_LIMIT_GROSS: u8 = 0;
+/// The daily withdrawal limit counts withdrawals less same-day deposits
+pub const DAILY_LIMIT_NET: u8 = 1;
+
+// Values of `Vault::event_level`
+/// Structured events only, no `msg!` logs on deposit and withdrawal paths
+pub const EVENT_LEVEL_SILENT: u8 = 0;
+/// Events and logs (the default)
+pub const EVENT_LEVEL_NORMAL: u8 = 1;
+/// Events and logs, plus a `VaultBalances` event after each deposit and withdrawal
+pub const EVENT_LEVEL_VERBOSE: u8 = 2;
 
 #[program]
 pub mod nexxore_vault {
     use super::*;
 
-    /// Initialize the vault
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
+    /// Initialize the vault with one of the `ROUNDING_*` modes. The first
+    /// deposit is priced at `initial_pps` (scaled by `PPS_SCALE`; `PPS_SCALE`
+    /// itself keeps the usual 1.0). The bootstrap limits apply for the first
+    /// `bootstrap_seconds` (0 = no bootstrap period). Shares get
+    /// `share_decimals`, at least the underlying's, or `SHARE_DECIMALS` more
+    /// than the underlying when `None`.
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        rounding: u8,
+        initial_pps: u64,
+        bootstrap_seconds: i64,
+        share_decimals: Option<u8>,
+    ) -> Result<()> {
+        require!(
+            rounding == ROUNDING_FAVOR_VAULT || rounding == ROUNDING_FAVOR_USER,
+            VaultError::InvalidParameter
+        );
+        require!(initial_pps > 0, VaultError::InvalidParameter);
+        require!(bootstrap_seconds >= 0, VaultError::InvalidParameter);
+        let asset_decimals = ctx.accounts.token_mint.decimals;
+        let share_decimals =
+            share_decimals.unwrap_or(asset_decimals.saturating_add(SHARE_DECIMALS as u8));
+        require!(
+            share_decimals >= asset_decimals
+                && share_decimals - asset_decimals <= MAX_EXTRA_SHARE_DECIMALS,
+            VaultError::InvalidParameter
+        );
+        let now = Clock::get()?.unix_timestamp;
         let vault = &mut ctx.accounts.vault;
         vault.authority = ctx.accounts.authority.key();
+        vault.keeper = ctx.accounts.authority.key();
+        vault.pending_keeper = Pubkey::default();
         vault.token_mint = ctx.accounts.token_mint.key();
         vault.vault_token_account = ctx.accounts.vault_token_account.key();
         vault.total_assets = 0;
         vault.total_shares = 0;
+        vault.deployed_assets = 0;
+        vault.penalty_pool = 0;
         vault.paused = false;
         vault.bump = ctx.bumps.vault;
+        vault.auto_unpause_at = 0;
+        vault.paused_ops = 0;
+        vault.deposit_cooldown = 0;
+        vault.min_lock = 0;
+        vault.max_lock = 0;
+        vault.lock_bonus_bps_per_day = 0;
+        vault.max_exit_fee_bps = 0;
+        vault.exit_fee_decay_seconds = 0;
+        vault.bootstrap_lock_seconds = 0;
+        vault.bonus_end_time = 0;
+        vault.bonus_bps = 0;
+        vault.min_position_shares = 0;
+        vault.oracle = None;
+        vault.max_oracle_deviation_bps = 0;
+        vault.current_epoch = 0;
+        vault.epoch_started_at = Clock::get()?.unix_timestamp;
+        vault.epoch_deposits = 0;
+        vault.epoch_withdrawals = 0;
+        vault.whitelist_root = [0; 32];
+        vault.withdrawal_cooldown = 0;
+        vault.next_withdrawal_request_id = 0;
+        vault.total_pending_withdrawals = 0;
+        vault.max_pending_withdrawals = 0;
+        vault.reward_rate = 0;
+        vault.reward_per_share_stored = 0;
+        vault.rewards_updated_at = vault.epoch_started_at;
+        vault.strategy_token_account = Pubkey::default();
+        vault.auto_pull_enabled = false;
+        vault.requested_pull = 0;
+        vault.rounding = rounding;
+        vault.reward_mint = Pubkey::default();
+        vault.reward_token_account = Pubkey::default();
+        vault.min_vault_assets = 0;
+        vault.is_shutdown = false;
+        vault.min_pps = 0;
+        vault.max_pps = 0;
+        vault.fee_recipient = Pubkey::default();
+        vault.auto_allocate_bps = 0;
+        vault.reserve_bps = 0;
+        vault.params_locked = false;
+        vault.max_shares_per_slot = 0;
+        vault.slot_shares_minted = 0;
+        vault.last_mint_slot = 0;
+        vault.risk_operator = Pubkey::default();
+        vault.interest_rate_bps = 0;
+        vault.supply_index = INDEX_SCALE;
+        vault.interest_updated_at = vault.epoch_started_at;
+        vault.require_approved_destination = false;
+        vault.match_pool = 0;
+        vault.insurance_fund = 0;
+        vault.receipt_mint = Pubkey::default();
+        vault.receipt_shares = 0;
+        vault.created_at = now;
+        vault.max_pps_staleness = 0;
+        vault.pps_last_update = now;
+        vault.deposit_throttle_base = 0;
+        vault.deposit_throttle_per_day = 0;
+        vault.match_bps = 0;
+        vault.management_fee_bps = 0;
+        vault.last_fee_accrual = vault.epoch_started_at;
+        vault.unclaimed_fee_shares = 0;
+        vault.quote_oracle = None;
+        vault.max_oracle_staleness = 0;
+        vault.min_harvest_interval = 0;
+        vault.last_harvest = 0;
+        vault.max_total_assets = 0;
+        vault.allow_partial_deposit = false;
+        vault.reward_end = 0;
+        vault.pending_authority = Pubkey::default();
+        vault.guardian = Pubkey::default();
+        vault.snapshot_id = 0;
+        vault.hook_program = None;
+        vault.next_claimable_seq = 0;
+        vault.cap_start = 0;
+        vault.cap_max = 0;
+        vault.cap_start_time = 0;
+        vault.cap_ramp_seconds = 0;
+        vault.initial_pps = initial_pps;
+        vault.vested_owed = 0;
+        vault.loss_window_until = 0;
+        vault.loss_window_seconds = 0;
+        vault.loss_window_penalty_bps = 0;
+        vault.migration_root = [0; 32];
+        vault.migration_mode = false;
+        vault.unimported_shares = 0;
+        vault.max_requests_per_user = DEFAULT_MAX_REQUESTS_PER_USER;
+        vault.lock_rate_at_request = false;
+        vault.dex_program = None;
+        vault.migration_program = None;
+        vault.max_user_share_bps = 0;
+        vault.event_level = EVENT_LEVEL_NORMAL;
+        vault.cumulative_deposits = 0;
+        vault.cumulative_withdrawals = 0;
+        vault.daily_withdrawal_limit = 0;
+        vault.daily_limit_mode = DAILY_LIMIT_GROSS;
+        vault.flow_day = 0;
+        vault.day_deposits = 0;
+        vault.day_withdrawals = 0;
+        vault.shutdown_scheduled_at = 0;
+        vault.vote_boost_bps = 0;
+        vault.vote_time_boost_cap = 0;
+        vault.bootstrap_until = if bootstrap_seconds > 0 {
+            now.checked_add(bootstrap_seconds)
+                .ok_or(VaultError::MathOverflow)?
+        } else {
+            0
+        };
+        vault.bootstrap_max_withdraw = 0;
+        vault.bootstrap_min_lock = 0;
+        vault.bootstrap_allocation_disabled = false;
+        vault.max_nav_change_bps = 0;
+        vault.min_nav_interval = 0;
+        vault.last_nav_update = 0;
+        vault.block_same_slot = false;
+        vault.deposit_fee_tiers = [FeeTier::default(); MAX_FEE_TIERS];
+        vault.deposit_fee_tier_count = 0;
+        vault.gov_mint = Pubkey::default();
+        vault.max_discount_bps = 0;
+        vault.discount_threshold = 0;
+        vault.max_pps_drop_bps = 0;
+        vault.last_pps = initial_pps;
+        vault.breaker_tripped = false;
+        vault.share_decimals = share_decimals;
+        vault.share_scale = 10u64.pow((share_decimals - asset_decimals) as u32);
 
         msg!("Vault initialized!");
         msg!("Authority: {}", vault.authority);
@@ -27,273 +243,7325 @@ pub mod nexxore_vault {
 
     /// Deposit tokens and receive shares
     pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        require!(amount > 0, VaultError::ZeroAmount);
-        require!(!ctx.accounts.vault.paused, VaultError::VaultPaused);
+        let now = Clock::get()?.unix_timestamp;
+        process_deposit(
+            ctx.accounts,
+            ctx.remaining_accounts,
+            amount,
+            now,
+            None,
+            false,
+        )?;
+        Ok(())
+    }
 
-        let vault = &mut ctx.accounts.vault;
+    /// Deposit taking the full `amount`, filling up to the cap and sending
+    /// the part that does not fit straight back to the user
+    pub fn deposit_or_refund(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        process_deposit(
+            ctx.accounts,
+            ctx.remaining_accounts,
+            amount,
+            now,
+            None,
+            true,
+        )?;
+        Ok(())
+    }
 
-        // Calculate shares to mint
-        let shares = if vault.total_shares == 0 {
-            amount
-        } else {
-            amount
-                .checked_mul(vault.total_shares)
-                .ok_or(VaultError::MathOverflow)?
-                .checked_div(vault.total_assets)
-                .ok_or(VaultError::MathOverflow)?
-        };
+    /// Deposit into a whitelisted vault with a merkle proof of the depositor's address
+    pub fn deposit_with_proof(
+        ctx: Context<Deposit>,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        process_deposit(
+            ctx.accounts,
+            ctx.remaining_accounts,
+            amount,
+            now,
+            Some(&proof),
+            false,
+        )?;
+        Ok(())
+    }
 
-        // Transfer tokens from user to vault
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_token_account.to_account_info(),
-            to: ctx.accounts.vault_token_account.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
+    /// Swap `amount_in` of another token into the underlying through the
+    /// registered DEX, then deposit everything the swap returned. The first
+    /// `swap_account_count` remaining accounts and `swap_data` make up the DEX
+    /// instruction, which the user's signature authorizes; the rest go to the
+    /// hook. Reverts unless the swap returns at least `min_out` and the
+    /// deposit mints at least `min_shares_out`.
+    pub fn swap_and_deposit<'info>(
+        ctx: Context<'_, '_, '_, 'info, SwapAndDeposit<'info>>,
+        amount_in: u64,
+        min_out: u64,
+        min_shares_out: u64,
+        swap_data: Vec<u8>,
+        swap_account_count: u8,
+    ) -> Result<()> {
+        require!(amount_in > 0, VaultError::ZeroAmount);
+        require!(
+            swap_account_count as usize <= ctx.remaining_accounts.len(),
+            VaultError::InvalidParameter
+        );
+        let (swap_accounts, hook_accounts) =
+            ctx.remaining_accounts.split_at(swap_account_count as usize);
+        let accounts = ctx.accounts;
+
+        let source_before = accounts.source_token_account.amount;
+        let received_before = accounts.deposit.user_token_account.amount;
+        let swap_ix = Instruction {
+            program_id: accounts.dex_program.key(),
+            accounts: swap_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: account.key(),
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data: swap_data,
         };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
+        let mut swap_infos = swap_accounts.to_vec();
+        swap_infos.push(accounts.dex_program.to_account_info());
+        invoke(&swap_ix, &swap_infos)?;
+
+        accounts.source_token_account.reload()?;
+        accounts.deposit.user_token_account.reload()?;
+        let spent = source_before.saturating_sub(accounts.source_token_account.amount);
+        require!(spent <= amount_in, VaultError::SwapInputExceeded);
+        let received = accounts
+            .deposit
+            .user_token_account
+            .amount
+            .saturating_sub(received_before);
+        require!(
+            received > 0 && received >= min_out,
+            VaultError::SlippageExceeded
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let shares = process_deposit(
+            &mut accounts.deposit,
+            hook_accounts,
+            received,
+            now,
+            None,
+            false,
+        )?;
+        require!(shares >= min_shares_out, VaultError::SlippageExceeded);
+
+        msg!(
+            "Swapped {} for {} and deposited it for {} shares",
+            spent,
+            received,
+            shares
+        );
+        Ok(())
+    }
+
+    /// Deposit tokens and lock the resulting position for `lock_seconds`
+    pub fn deposit_and_lock(ctx: Context<Deposit>, amount: u64, lock_seconds: i64) -> Result<()> {
+        let vault = &ctx.accounts.vault;
+        require!(
+            lock_seconds > 0 && lock_seconds >= vault.min_lock && lock_seconds <= vault.max_lock,
+            VaultError::InvalidLockDuration
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let shares = process_deposit(
+            ctx.accounts,
+            ctx.remaining_accounts,
+            amount,
+            now,
+            None,
+            false,
+        )?;
+
+        // Bonus shares scale with whole days locked
+        let vault = &mut ctx.accounts.vault;
+        let lock_days = (lock_seconds / SECONDS_PER_DAY) as u128;
+        let bonus_shares = u64::try_from(
+            (shares as u128)
+                .checked_mul(vault.lock_bonus_bps_per_day as u128)
+                .ok_or(VaultError::MathOverflow)?
+                .checked_mul(lock_days)
+                .ok_or(VaultError::MathOverflow)?
+                / BPS_DENOMINATOR as u128,
+        )
+        .map_err(|_| VaultError::MathOverflow)?;
 
-        // Update vault state
-        vault.total_assets = vault
-            .total_assets
-            .checked_add(amount)
-            .ok_or(VaultError::MathOverflow)?;
         vault.total_shares = vault
             .total_shares
-            .checked_add(shares)
+            .checked_add(bonus_shares)
             .ok_or(VaultError::MathOverflow)?;
 
-        // Update user shares
         let user_shares = &mut ctx.accounts.user_shares;
         user_shares.shares = user_shares
             .shares
-            .checked_add(shares)
+            .checked_add(bonus_shares)
+            .ok_or(VaultError::MathOverflow)?;
+
+        // Never shorten an existing lock
+        let unlock_at = now
+            .checked_add(lock_seconds)
             .ok_or(VaultError::MathOverflow)?;
+        user_shares.unlock_at = user_shares.unlock_at.max(unlock_at);
 
-        emit!(DepositEvent {
+        emit!(Locked {
             user: ctx.accounts.user.key(),
-            amount,
-            shares,
-            timestamp: Clock::get()?.unix_timestamp,
+            unlock_at: user_shares.unlock_at,
+            bonus_shares,
+            timestamp: now,
         });
 
-        msg!("Deposited {} tokens, minted {} shares", amount, shares);
+        msg!(
+            "Locked position until {}, bonus {} shares",
+            user_shares.unlock_at,
+            bonus_shares
+        );
 
         Ok(())
     }
 
-    /// Withdraw assets by burning shares
+    /// Withdraw assets by burning shares. With auto-pull enabled, a withdrawal
+    /// larger than idle liquidity pays what idle allows and queues the rest in
+    /// `withdrawal_request`, claimable once the keeper returns funds.
     pub fn withdraw(ctx: Context<Withdraw>, shares: u64) -> Result<()> {
-        require!(shares > 0, VaultError::ZeroAmount);
+        let accounts = ctx.accounts;
+        check_oracle_deviation(&accounts.vault, accounts.oracle.as_ref())?;
+        let fee_discount_bps =
+            gov_discount_bps(&accounts.vault, accounts.gov_token_account.as_ref())?;
 
-        let vault = &mut ctx.accounts.vault;
-        let user_shares = &mut ctx.accounts.user_shares;
+        // Price the shares at the current rate before splitting off a pull
+        let now = Clock::get()?.unix_timestamp;
+        accounts.vault.accrue_interest(now)?;
+        accounts.vault.accrue_management_fee(now)?;
+        let idle = accounts
+            .vault_token_account
+            .amount
+            .saturating_sub(accounts.vault.earmarked_balance());
+        let assets = accounts.vault.redeem_value(shares)?;
+        if accounts.vault.auto_pull_enabled && assets > idle {
+            let paid = withdraw_with_pull(accounts, shares, assets, idle, fee_discount_bps)?;
+            return invoke_hook(
+                &accounts.vault,
+                ctx.remaining_accounts,
+                b"global:on_withdraw",
+                accounts.user.key(),
+                paid,
+                shares,
+            );
+        }
 
-        require!(user_shares.shares >= shares, VaultError::InsufficientShares);
+        process_withdraw(
+            &mut accounts.vault,
+            &mut accounts.user_shares,
+            &mut accounts.vault_token_account,
+            accounts.user_token_account.to_account_info(),
+            &accounts.token_program,
+            accounts.user.key(),
+            shares,
+            fee_discount_bps,
+            ctx.remaining_accounts,
+        )?;
+        Ok(())
+    }
 
-        // Calculate assets to return
-        let assets = shares
-            .checked_mul(vault.total_assets)
-            .ok_or(VaultError::MathOverflow)?
-            .checked_div(vault.total_shares)
-            .ok_or(VaultError::MathOverflow)?;
+    /// Withdraw to any token account of the vault mint. While
+    /// `require_approved_destination` is set, accounts the user does not own
+    /// must have an `approved_destination` record.
+    pub fn withdraw_to(ctx: Context<WithdrawTo>, shares: u64) -> Result<()> {
+        let accounts = ctx.accounts;
+        if accounts.vault.require_approved_destination
+            && accounts.destination.owner != accounts.user.key()
+        {
+            require!(
+                accounts.approved_destination.is_some(),
+                VaultError::DestinationNotApproved
+            );
+        }
+        check_oracle_deviation(&accounts.vault, accounts.oracle.as_ref())?;
+        let fee_discount_bps =
+            gov_discount_bps(&accounts.vault, accounts.gov_token_account.as_ref())?;
+        process_withdraw(
+            &mut accounts.vault,
+            &mut accounts.user_shares,
+            &mut accounts.vault_token_account,
+            accounts.destination.to_account_info(),
+            &accounts.token_program,
+            accounts.user.key(),
+            shares,
+            fee_discount_bps,
+            ctx.remaining_accounts,
+        )?;
+        Ok(())
+    }
 
-        require!(assets <= vault.total_assets, VaultError::InsufficientAssets);
+    /// Withdraw to `recipient`'s associated token account, creating it at the
+    /// user's expense first if it does not exist yet
+    pub fn withdraw_to_ata(ctx: Context<WithdrawToAta>, shares: u64) -> Result<()> {
+        let accounts = ctx.accounts;
+        if accounts.vault.require_approved_destination
+            && accounts.recipient.key() != accounts.user.key()
+        {
+            require!(
+                accounts.approved_destination.is_some(),
+                VaultError::DestinationNotApproved
+            );
+        }
+        check_oracle_deviation(&accounts.vault, accounts.oracle.as_ref())?;
+        let fee_discount_bps =
+            gov_discount_bps(&accounts.vault, accounts.gov_token_account.as_ref())?;
+        process_withdraw(
+            &mut accounts.vault,
+            &mut accounts.user_shares,
+            &mut accounts.vault_token_account,
+            accounts.recipient_token_account.to_account_info(),
+            &accounts.token_program,
+            accounts.user.key(),
+            shares,
+            fee_discount_bps,
+            ctx.remaining_accounts,
+        )?;
+        Ok(())
+    }
 
-        // Update state before transfer
-        vault.total_assets = vault
-            .total_assets
-            .checked_sub(assets)
-            .ok_or(VaultError::MathOverflow)?;
-        vault.total_shares = vault
-            .total_shares
-            .checked_sub(shares)
-            .ok_or(VaultError::MathOverflow)?;
-        user_shares.shares = user_shares
-            .shares
-            .checked_sub(shares)
-            .ok_or(VaultError::MathOverflow)?;
+    /// Withdraw from a wrapped-SOL vault and deliver native SOL to `recipient`.
+    /// The wSOL passes through a temporary account that is closed to
+    /// `recipient`, so its rent, paid by the user, goes there as well. While
+    /// `require_approved_destination` is set, a recipient other than the user
+    /// must have its wSOL associated token account approved.
+    pub fn withdraw_sol_to(
+        ctx: Context<WithdrawSol>,
+        shares: u64,
+        recipient: Pubkey,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        require!(
+            accounts.vault.token_mint == native_mint::ID,
+            VaultError::NotNativeMint
+        );
+        if accounts.vault.require_approved_destination && recipient != accounts.user.key() {
+            require!(
+                accounts.approved_destination.is_some(),
+                VaultError::DestinationNotApproved
+            );
+        }
+        check_oracle_deviation(&accounts.vault, accounts.oracle.as_ref())?;
+        let fee_discount_bps =
+            gov_discount_bps(&accounts.vault, accounts.gov_token_account.as_ref())?;
+        let assets_out = process_withdraw(
+            &mut accounts.vault,
+            &mut accounts.user_shares,
+            &mut accounts.vault_token_account,
+            accounts.unwrap_account.to_account_info(),
+            &accounts.token_program,
+            accounts.user.key(),
+            shares,
+            fee_discount_bps,
+            ctx.remaining_accounts,
+        )?;
 
-        // Transfer tokens from vault to user using PDA signer
+        let vault = &accounts.vault;
         let seeds = &[
             b"vault",
             vault.token_mint.as_ref(),
             &[vault.bump],
         ];
         let signer = &[&seeds[..]];
-
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.vault_token_account.to_account_info(),
-            to: ctx.accounts.user_token_account.to_account_info(),
+        let cpi_accounts = CloseAccount {
+            account: accounts.unwrap_account.to_account_info(),
+            destination: accounts.recipient_wallet.to_account_info(),
             authority: vault.to_account_info(),
         };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, assets)?;
+        let cpi_ctx = CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+        token::close_account(cpi_ctx)?;
 
-        emit!(WithdrawEvent {
-            user: ctx.accounts.user.key(),
-            assets,
+        msg!("Unwrapped {} lamports to {}", assets_out, recipient);
+        Ok(())
+    }
+
+    /// Let `delegate` withdraw to the owner's account up to `allowance` assets
+    pub fn approve_withdraw_delegate(
+        ctx: Context<ApproveWithdrawDelegate>,
+        delegate: Pubkey,
+        allowance: u64,
+    ) -> Result<()> {
+        let withdraw_delegate = &mut ctx.accounts.withdraw_delegate;
+        withdraw_delegate.owner = ctx.accounts.owner.key();
+        withdraw_delegate.delegate = delegate;
+        withdraw_delegate.allowance = allowance;
+        msg!("Approved {} to withdraw up to {}", delegate, allowance);
+        Ok(())
+    }
+
+    /// Withdraw on behalf of an owner as their approved delegate
+    pub fn withdraw_delegated(ctx: Context<WithdrawDelegated>, shares: u64) -> Result<()> {
+        let accounts = ctx.accounts;
+        check_oracle_deviation(&accounts.vault, accounts.oracle.as_ref())?;
+        let fee_discount_bps =
+            gov_discount_bps(&accounts.vault, accounts.gov_token_account.as_ref())?;
+        let assets_out = process_withdraw(
+            &mut accounts.vault,
+            &mut accounts.user_shares,
+            &mut accounts.vault_token_account,
+            accounts.owner_token_account.to_account_info(),
+            &accounts.token_program,
+            accounts.owner.key(),
             shares,
-            timestamp: Clock::get()?.unix_timestamp,
-        });
+            fee_discount_bps,
+            ctx.remaining_accounts,
+        )?;
 
-        msg!("Withdrew {} assets, burned {} shares", assets, shares);
+        let withdraw_delegate = &mut accounts.withdraw_delegate;
+        withdraw_delegate.allowance = withdraw_delegate
+            .allowance
+            .checked_sub(assets_out)
+            .ok_or(VaultError::AllowanceExceeded)?;
 
+        msg!(
+            "Delegate {} withdrew {}, remaining allowance {}",
+            accounts.delegate.key(),
+            assets_out,
+            withdraw_delegate.allowance
+        );
         Ok(())
     }
 
-    /// Pause deposits (admin only)
-    pub fn pause(ctx: Context<AdminAction>) -> Result<()> {
+    /// Queue `shares` for withdrawal after the cooldown. They leave the user's
+    /// balance now and keep accruing at the vault rate until claimed, unless
+    /// `lock_rate_at_request` fixes their value at request time.
+    pub fn request_withdrawal(ctx: Context<RequestWithdrawal>, shares: u64) -> Result<()> {
+        require!(shares > 0, VaultError::ZeroAmount);
+        let now = Clock::get()?.unix_timestamp;
         let vault = &mut ctx.accounts.vault;
-        require!(!vault.paused, VaultError::AlreadyPaused);
-        vault.paused = true;
-        msg!("Vault paused");
+        vault.require_op_enabled(OP_REQUEST_WITHDRAWAL)?;
+
+        let first_deposit_at =
+            debit_user_shares(vault, &mut ctx.accounts.user_shares, shares, now)?;
+        ctx.accounts
+            .user_shares
+            .open_request(vault.max_requests_per_user)?;
+        vault.accrue_interest(now)?;
+        vault.accrue_management_fee(now)?;
+
+        let claimable_at = now
+            .checked_add(vault.withdrawal_cooldown)
+            .ok_or(VaultError::MathOverflow)?;
+        let request = &mut ctx.accounts.withdrawal_request;
+        open_withdrawal_request(
+            vault,
+            request,
+            ctx.accounts.user.key(),
+            shares,
+            first_deposit_at,
+            now,
+            claimable_at,
+        )?;
+
+        emit!(WithdrawalRequested {
+            user: request.owner,
+            request_id: request.id,
+            shares,
+            claimable_at: request.claimable_at,
+            timestamp: now,
+        });
+
+        if vault.logs_enabled() {
+            msg!(
+                "Requested withdrawal #{} of {} shares, claimable at {}",
+                request.id,
+                shares,
+                request.claimable_at
+            );
+        }
         Ok(())
     }
 
-    /// Unpause deposits (admin only)
-    pub fn unpause(ctx: Context<AdminAction>) -> Result<()> {
+    /// Burn `shares` now at the current rate, less the exit fee, and pay the
+    /// assets out linearly over `vesting_seconds` through `claim_vested`
+    pub fn start_vested_withdrawal(
+        ctx: Context<StartVestedWithdrawal>,
+        shares: u64,
+        vesting_seconds: i64,
+    ) -> Result<()> {
+        require!(shares > 0, VaultError::ZeroAmount);
+        require!(vesting_seconds > 0, VaultError::InvalidParameter);
+        let vested_exit = &mut ctx.accounts.vested_exit;
+        require!(
+            vested_exit.claimed == vested_exit.total,
+            VaultError::VestedExitActive
+        );
+
+        let fee_discount_bps =
+            gov_discount_bps(&ctx.accounts.vault, ctx.accounts.gov_token_account.as_ref())?;
+        let now = Clock::get()?.unix_timestamp;
         let vault = &mut ctx.accounts.vault;
-        require!(vault.paused, VaultError::NotPaused);
-        vault.paused = false;
-        msg!("Vault unpaused");
-        Ok(())
-    }
-}
+        vault.require_op_enabled(OP_WITHDRAW)?;
+        end_expired_bootstrap(vault, now);
+        ctx.accounts
+            .user_shares
+            .record_action(false, Clock::get()?.slot, vault.block_same_slot)?;
+        let first_deposit_at =
+            debit_user_shares(vault, &mut ctx.accounts.user_shares, shares, now)?;
+        vault.accrue_interest(now)?;
+        vault.accrue_management_fee(now)?;
+        let (assets, fee, loss_penalty) = validate_redeem(
+            vault,
+            shares,
+            Some(first_deposit_at),
+            None,
+            fee_discount_bps,
+            now,
+        )?;
+        let owed = apply_redeem(vault, shares, assets, fee, loss_penalty)?;
+        require!(owed > 0, VaultError::ZeroAmount);
+        vault.check_bootstrap_withdraw(owed, now)?;
+        vault.vested_owed = vault
+            .vested_owed
+            .checked_add(owed)
+            .ok_or(VaultError::MathOverflow)?;
 
-// ============ Accounts ============
+        vested_exit.owner = ctx.accounts.user.key();
+        vested_exit.total = owed;
+        vested_exit.claimed = 0;
+        vested_exit.start = now;
+        vested_exit.duration = vesting_seconds;
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + Vault::INIT_SPACE,
-        seeds = [b"vault", token_mint.key().as_ref()],
-        bump
-    )]
-    pub vault: Account<'info, Vault>,
+        emit!(VestedExitStarted {
+            user: vested_exit.owner,
+            shares,
+            assets: owed,
+            fee,
+            vesting_seconds,
+            timestamp: now,
+        });
 
-    pub token_mint: Account<'info, Mint>,
+        msg!(
+            "Burned {} shares for {} assets vesting over {}s",
+            shares,
+            owed,
+            vesting_seconds
+        );
+        Ok(())
+    }
 
-    #[account(
-        constraint = vault_token_account.mint == token_mint.key(),
-        constraint = vault_token_account.owner == vault.key(),
-    )]
-    pub vault_token_account: Account<'info, TokenAccount>,
+    /// Pay out the part of the vested exit unlocked since the last claim
+    pub fn claim_vested(ctx: Context<ClaimVested>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vested_exit = &mut ctx.accounts.vested_exit;
+        let amount = vested_exit
+            .unlocked_at(now)?
+            .checked_sub(vested_exit.claimed)
+            .ok_or(VaultError::Underflow)?;
+        require!(amount > 0, VaultError::ZeroAmount);
+        vested_exit.claimed = vested_exit
+            .claimed
+            .checked_add(amount)
+            .ok_or(VaultError::MathOverflow)?;
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        let vault = &mut ctx.accounts.vault;
+        vault.vested_owed = vault
+            .vested_owed
+            .checked_sub(amount)
+            .ok_or(VaultError::Underflow)?;
+        let effects = state_fingerprint(vault);
+        transfer_from_vault(
+            vault,
+            &ctx.accounts.vault_token_account,
+            ctx.accounts.user_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        ctx.accounts.vault_token_account.reload()?;
+        check_solvency(vault, &ctx.accounts.vault_token_account)?;
+        debug_assert_unchanged(&effects, vault);
 
-    pub system_program: Program<'info, System>,
-}
+        let remaining = vested_exit.total - vested_exit.claimed;
+        emit!(VestedClaimed {
+            user: vested_exit.owner,
+            amount,
+            remaining,
+            timestamp: now,
+        });
 
-#[derive(Accounts)]
-pub struct Deposit<'info> {
-    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
-    pub vault: Account<'info, Vault>,
+        msg!("Claimed {} vested assets, {} remaining", amount, remaining);
+        Ok(())
+    }
 
-    #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + UserShares::INIT_SPACE,
-        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
-        bump
-    )]
-    pub user_shares: Account<'info, UserShares>,
+    /// Redeem a matured withdrawal request at the current exchange rate
+    pub fn claim_withdrawal(ctx: Context<ClaimWithdrawal>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let accounts = ctx.accounts;
+        let fee_discount_bps =
+            gov_discount_bps(&accounts.vault, accounts.gov_token_account.as_ref())?;
+        let vault = &mut accounts.vault;
+        vault.require_op_enabled(OP_WITHDRAW)?;
+        end_expired_bootstrap(vault, now);
 
-    #[account(
-        mut,
-        constraint = user_token_account.mint == vault.token_mint,
-        constraint = user_token_account.owner == user.key(),
-    )]
-    pub user_token_account: Account<'info, TokenAccount>,
+        let request = &accounts.withdrawal_request;
+        require!(now >= request.claimable_at, VaultError::WithdrawalNotReady);
 
-    #[account(
-        mut,
-        constraint = vault_token_account.key() == vault.vault_token_account,
-    )]
-    pub vault_token_account: Account<'info, TokenAccount>,
+        // Request ids are issued in order, so they double as queue positions.
+        // Only the head may claim unless idle covers every pending request.
+        if request.id != vault.next_claimable_seq {
+            let idle = accounts
+                .vault_token_account
+                .amount
+                .saturating_sub(vault.earmarked_balance());
+            let pending_assets = vault.convert_to_assets(vault.total_pending_withdrawals)?;
+            require!(idle >= pending_assets, VaultError::ClaimOutOfOrder);
+        }
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+        vault.total_pending_withdrawals = vault
+            .total_pending_withdrawals
+            .checked_sub(request.shares)
+            .ok_or(VaultError::Underflow)?;
+        vault.accrue_rewards(now)?;
 
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
+        let (assets_out, fee, loss_penalty) = redeem_shares(
+            vault,
+            &mut accounts.vault_token_account,
+            accounts.user_token_account.to_account_info(),
+            &accounts.token_program,
+            request.shares,
+            Some(request.first_deposit_at),
+            request.locked_assets,
+            fee_discount_bps,
+            now,
+        )?;
+        vault.check_bootstrap_withdraw(assets_out, now)?;
+        if request.id == vault.next_claimable_seq {
+            vault.next_claimable_seq += 1;
+        }
+        accounts.user_shares.open_requests = accounts
+            .user_shares
+            .open_requests
+            .checked_sub(1)
+            .ok_or(VaultError::Underflow)?;
 
-#[derive(Accounts)]
-pub struct Withdraw<'info> {
-    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
-    pub vault: Account<'info, Vault>,
+        emit!(WithdrawEvent {
+            user: request.owner,
+            assets: assets_out,
+            shares: request.shares,
+            fee,
+            loss_penalty,
+            timestamp: now,
+        });
+        emit!(ClaimServed {
+            user: request.owner,
+            seq: request.id,
+            next_claimable_seq: vault.next_claimable_seq,
+            timestamp: now,
+        });
+        emit_verbose_balances(vault, accounts.vault_token_account.amount, now)?;
 
-    #[account(
-        mut,
-        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
-        bump
-    )]
-    pub user_shares: Account<'info, UserShares>,
+        if vault.logs_enabled() {
+            msg!(
+                "Claimed withdrawal #{}: {} assets (fee {}) for {} shares",
+                request.id,
+                assets_out,
+                fee,
+                request.shares
+            );
+        }
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        constraint = user_token_account.mint == vault.token_mint,
-        constraint = user_token_account.owner == user.key(),
-    )]
-    pub user_token_account: Account<'info, TokenAccount>,
+    /// Move the claim queue past a head request that no longer exists, i.e.
+    /// one claimed out of turn or cancelled while not at the head (permissionless)
+    pub fn skip_closed_request(ctx: Context<SkipClosedRequest>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            vault.next_claimable_seq < vault.next_withdrawal_request_id
+                && ctx.accounts.head_request.data_is_empty(),
+            VaultError::QueueHeadPending
+        );
+        vault.next_claimable_seq += 1;
 
-    #[account(
-        mut,
-        constraint = vault_token_account.key() == vault.vault_token_account,
-    )]
-    pub vault_token_account: Account<'info, TokenAccount>,
+        msg!("Claim queue advanced to #{}", vault.next_claimable_seq);
+        Ok(())
+    }
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+    /// Drop a pending withdrawal request and return its shares to the user
+    pub fn cancel_withdrawal(ctx: Context<CancelWithdrawal>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        let request = &ctx.accounts.withdrawal_request;
 
-    pub token_program: Program<'info, Token>,
-}
+        vault.total_pending_withdrawals = vault
+            .total_pending_withdrawals
+            .checked_sub(request.shares)
+            .ok_or(VaultError::Underflow)?;
+        if request.id == vault.next_claimable_seq {
+            vault.next_claimable_seq += 1;
+        }
 
-#[derive(Accounts)]
-pub struct AdminAction<'info> {
-    #[account(
-        mut,
-        seeds = [b"vault", vault.token_mint.as_ref()],
-        bump = vault.bump,
-        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
-    )]
-    pub vault: Account<'info, Vault>,
+        let user_shares = &mut ctx.accounts.user_shares;
+        settle_rewards(vault, user_shares, now)?;
+        user_shares.open_requests = user_shares
+            .open_requests
+            .checked_sub(1)
+            .ok_or(VaultError::Underflow)?;
+        if user_shares.shares == 0 {
+            user_shares.first_deposit_at = request.first_deposit_at;
+        }
+        user_shares.shares = user_shares
+            .shares
+            .checked_add(request.shares)
+            .ok_or(VaultError::MathOverflow)?;
 
-    pub authority: Signer<'info>,
-}
+        emit!(WithdrawalCancelled {
+            user: request.owner,
+            request_id: request.id,
+            shares: request.shares,
+            timestamp: now,
+        });
 
-// ============ State ============
+        msg!(
+            "Cancelled withdrawal #{}, returned {} shares",
+            request.id,
+            request.shares
+        );
+        Ok(())
+    }
 
-#[account]
-#[derive(InitSpace)]
-pub struct Vault {
-    pub authority: Pubkey,
-    pub token_mint: Pubkey,
-    pub vault_token_account: Pubkey,
-    pub total_assets: u64,
-    pub total_shares: u64,
-    pub paused: bool,
-    pub bump: u8,
-}
+    /// Configure the request/claim withdrawal queue (admin only). `max_pending`
+    /// caps the shares awaiting claim across all requests (0 = unlimited).
+    pub fn set_withdrawal_queue(
+        ctx: Context<AdminAction>,
+        cooldown: i64,
+        max_pending: u64,
+    ) -> Result<()> {
+        require!(cooldown >= 0, VaultError::InvalidParameter);
+        let vault = &mut ctx.accounts.vault;
+        let tighter_cap = max_pending != 0
+            && (vault.max_pending_withdrawals == 0 || max_pending < vault.max_pending_withdrawals);
+        vault.require_terms_not_worsened(cooldown > vault.withdrawal_cooldown || tighter_cap)?;
+        vault.withdrawal_cooldown = cooldown;
+        vault.max_pending_withdrawals = max_pending;
+        msg!(
+            "Withdrawal cooldown set to {}s, max pending {} shares",
+            cooldown,
+            max_pending
+        );
+        Ok(())
+    }
 
-#[account]
-#[derive(InitSpace)]
-pub struct UserShares {
-    pub shares: u64,
-}
+    /// Cap the withdrawal requests one user may have open at once (admin
+    /// only, 0 = unlimited). Requests already open are unaffected.
+    pub fn set_max_requests_per_user(
+        ctx: Context<AdminAction>,
+        max_requests_per_user: u8,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.require_terms_not_worsened(
+            max_requests_per_user != 0
+                && (vault.max_requests_per_user == 0
+                    || max_requests_per_user < vault.max_requests_per_user),
+        )?;
+        vault.max_requests_per_user = max_requests_per_user;
+        msg!(
+            "Max open requests per user set to {}",
+            max_requests_per_user
+        );
+        Ok(())
+    }
 
-// ============ Events ============
+    /// Choose whether new withdrawal requests fix their asset value when made
+    /// or float until claimed (admin only). Open requests keep their policy.
+    pub fn set_lock_rate_at_request(ctx: Context<AdminAction>, enabled: bool) -> Result<()> {
+        ctx.accounts.vault.lock_rate_at_request = enabled;
+        msg!("Lock rate at request: {}", enabled);
+        Ok(())
+    }
 
-#[event]
-pub struct DepositEvent {
-    pub user: Pubkey,
+    /// Replace the deposit fee tiers (admin only). Each tier charges its
+    /// `fee_bps` on deposits of at least its `threshold`; thresholds must be
+    /// strictly ascending. Deposits below the first tier pay no fee.
+    /// Once params are locked, no deposit size may come to pay more.
+    pub fn set_deposit_fee_tiers(ctx: Context<AdminAction>, tiers: Vec<FeeTier>) -> Result<()> {
+        require!(tiers.len() <= MAX_FEE_TIERS, VaultError::InvalidFeeTiers);
+        require!(
+            tiers
+                .windows(2)
+                .all(|pair| pair[0].threshold < pair[1].threshold),
+            VaultError::InvalidFeeTiers
+        );
+        require!(
+            tiers
+                .iter()
+                .all(|tier| tier.fee_bps as u64 <= BPS_DENOMINATOR),
+            VaultError::InvalidFeeTiers
+        );
+        let vault = &mut ctx.accounts.vault;
+        // Both schedules are step functions, so comparing them at every
+        // threshold of either covers all deposit sizes
+        let new_fee_bps = |amount: u64| {
+            tiers
+                .iter()
+                .rev()
+                .find(|tier| amount >= tier.threshold)
+                .map_or(0, |tier| tier.fee_bps)
+        };
+        let raises_fee = tiers
+            .iter()
+            .chain(&vault.deposit_fee_tiers[..vault.deposit_fee_tier_count as usize])
+            .any(|tier| new_fee_bps(tier.threshold) > vault.deposit_fee_bps(tier.threshold));
+        vault.require_terms_not_worsened(raises_fee)?;
+        vault.deposit_fee_tiers = [FeeTier::default(); MAX_FEE_TIERS];
+        vault.deposit_fee_tiers[..tiers.len()].copy_from_slice(&tiers);
+        vault.deposit_fee_tier_count = tiers.len() as u8;
+        msg!("Set {} deposit fee tiers", tiers.len());
+        Ok(())
+    }
+
+    /// Discount deposit and exit fees for holders of `gov_mint` (admin only).
+    /// The discount grows linearly with the balance of the gov token account
+    /// passed in, reaching `max_discount_bps` of the fee at
+    /// `discount_threshold`. A `max_discount_bps` of 0 turns it off.
+    pub fn set_fee_discount(
+        ctx: Context<AdminAction>,
+        gov_mint: Pubkey,
+        max_discount_bps: u16,
+        discount_threshold: u64,
+    ) -> Result<()> {
+        require!(
+            max_discount_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        require!(
+            max_discount_bps == 0 || discount_threshold > 0,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.require_terms_not_worsened(
+            vault.max_discount_bps > 0
+                && (gov_mint != vault.gov_mint
+                    || max_discount_bps < vault.max_discount_bps
+                    || discount_threshold > vault.discount_threshold),
+        )?;
+        vault.gov_mint = gov_mint;
+        vault.max_discount_bps = max_discount_bps;
+        vault.discount_threshold = discount_threshold;
+        msg!(
+            "Fee discount up to {} bps at {} of {}",
+            max_discount_bps,
+            discount_threshold,
+            gov_mint
+        );
+        Ok(())
+    }
+
+    /// Choose whether a user may deposit and withdraw in the same slot
+    /// (admin only). Blocking it stops flash-loan style round trips at the
+    /// cost of same-slot batches.
+    pub fn set_block_same_slot(ctx: Context<AdminAction>, enabled: bool) -> Result<()> {
+        ctx.accounts.vault.block_same_slot = enabled;
+        msg!("Block same-slot deposit and withdraw: {}", enabled);
+        Ok(())
+    }
+
+    /// Bring the caller's pending rewards up to date without moving funds
+    pub fn settle(ctx: Context<Settle>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let user_shares = &mut ctx.accounts.user_shares;
+        settle_rewards(&mut ctx.accounts.vault, user_shares, now)?;
+        msg!("Settled rewards, {} pending", user_shares.pending_rewards);
+        Ok(())
+    }
+
+    /// Compound interest into `total_assets` up to now (permissionless)
+    pub fn accrue_interest(ctx: Context<AccrueInterest>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        let interest = vault.accrue_interest(now)?;
+        vault.pps_last_update = now;
+
+        emit!(InterestAccrued {
+            interest,
+            supply_index: vault.supply_index,
+            total_assets: vault.total_assets,
+            timestamp: now,
+        });
+
+        msg!(
+            "Accrued {} interest, index {}",
+            interest,
+            vault.supply_index
+        );
+        Ok(())
+    }
+
+    /// Set the annual interest rate compounded into the supply index, in bps (admin only)
+    pub fn set_interest_rate(ctx: Context<AdminAction>, interest_rate_bps: u16) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        // Close out accrual at the old rate first
+        vault.accrue_interest(now)?;
+        vault.interest_rate_bps = interest_rate_bps;
+        msg!("Interest rate set to {} bps", interest_rate_bps);
+        Ok(())
+    }
+
+    /// Set the rewards emitted per second across all shares, with no end (admin only)
+    pub fn set_reward_rate(ctx: Context<AdminAction>, reward_rate: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        // Close out accrual at the old rate first
+        vault.accrue_rewards(now)?;
+        vault.reward_rate = reward_rate;
+        vault.reward_end = i64::MAX;
+        msg!("Reward rate set to {} per second", reward_rate);
+        Ok(())
+    }
+
+    /// Emit `reward_rate` per second for the next `duration` seconds, funding
+    /// the whole emission into the reward pool up front (admin only)
+    pub fn set_reward_schedule(
+        ctx: Context<SetRewardSchedule>,
+        reward_rate: u64,
+        duration: i64,
+    ) -> Result<()> {
+        require!(duration > 0, VaultError::InvalidParameter);
+        let now = Clock::get()?.unix_timestamp;
+        let funding = reward_rate
+            .checked_mul(duration as u64)
+            .ok_or(VaultError::MathOverflow)?;
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.reward_token_account.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, funding)?;
+
+        let vault = &mut ctx.accounts.vault;
+        // Close out accrual under the old schedule first
+        vault.accrue_rewards(now)?;
+        vault.reward_rate = reward_rate;
+        vault.reward_end = now.checked_add(duration).ok_or(VaultError::MathOverflow)?;
+
+        msg!(
+            "Emitting {} per second until {}, funded {}",
+            reward_rate,
+            vault.reward_end,
+            funding
+        );
+        Ok(())
+    }
+
+    /// Set the annual management fee, minted as shares for the fee recipient
+    /// on every deposit and withdrawal, in bps (admin only)
+    pub fn set_management_fee(ctx: Context<AdminAction>, management_fee_bps: u16) -> Result<()> {
+        require!(
+            management_fee_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        vault.require_terms_not_worsened(management_fee_bps > vault.management_fee_bps)?;
+        // Close out accrual at the old rate first
+        vault.accrue_interest(now)?;
+        vault.accrue_management_fee(now)?;
+        vault.management_fee_bps = management_fee_bps;
+        msg!("Management fee set to {} bps", management_fee_bps);
+        Ok(())
+    }
+
+    /// Restrict `withdraw_to` to approved destinations (admin only)
+    pub fn set_require_approved_destination(
+        ctx: Context<AdminAction>,
+        require_approved_destination: bool,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.require_approved_destination = require_approved_destination;
+        msg!(
+            "Approved destinations required: {}",
+            require_approved_destination
+        );
+        Ok(())
+    }
+
+    /// Allow `withdraw_to` to pay into `destination` (admin only)
+    pub fn approve_destination(ctx: Context<ApproveDestination>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let approved = &mut ctx.accounts.approved_destination;
+        approved.destination = ctx.accounts.destination.key();
+        approved.approved_at = now;
+
+        emit!(DestinationApproved {
+            vault: ctx.accounts.vault.key(),
+            destination: approved.destination,
+            timestamp: now,
+        });
+
+        msg!("Approved withdrawal destination {}", approved.destination);
+        Ok(())
+    }
+
+    /// Remove `destination` from the approved set (admin only)
+    pub fn revoke_destination(ctx: Context<RevokeDestination>) -> Result<()> {
+        let destination = ctx.accounts.approved_destination.destination;
+
+        emit!(DestinationRevoked {
+            vault: ctx.accounts.vault.key(),
+            destination,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Revoked withdrawal destination {}", destination);
+        Ok(())
+    }
+
+    /// Route protocol fees to `fee_recipient`, a token account of the vault's mint (admin only)
+    pub fn set_fee_recipient(ctx: Context<SetFeeRecipient>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_recipient = vault.fee_recipient;
+        vault.fee_recipient = ctx.accounts.fee_recipient.key();
+
+        emit!(FeeRecipientChanged {
+            old_recipient,
+            new_recipient: vault.fee_recipient,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Fee recipient set to {}", vault.fee_recipient);
+        Ok(())
+    }
+
+    /// Redeem the management fee shares accrued so far into `fee_recipient`.
+    /// These are protocol fees, so the exit fee and position lock do not
+    /// apply. Shares the recipient holds in its own position leave through
+    /// the regular withdraw path.
+    pub fn realize_fee_shares(ctx: Context<RealizeFeeShares>) -> Result<()> {
+        let accounts = ctx.accounts;
+        accounts.vault.require_op_enabled(OP_WITHDRAW)?;
+        let now = Clock::get()?.unix_timestamp;
+        accounts.vault.accrue_interest(now)?;
+        accounts.vault.accrue_management_fee(now)?;
+
+        let shares = accounts.vault.unclaimed_fee_shares;
+        require!(shares > 0, VaultError::ZeroAmount);
+        accounts.vault.unclaimed_fee_shares = 0;
+
+        let (assets, _, _) = redeem_shares(
+            &mut accounts.vault,
+            &mut accounts.vault_token_account,
+            accounts.fee_recipient.to_account_info(),
+            &accounts.token_program,
+            shares,
+            None,
+            None,
+            0,
+            now,
+        )?;
+
+        emit!(FeesRealized {
+            recipient: accounts.fee_recipient.key(),
+            shares,
+            assets,
+            timestamp: now,
+        });
+
+        msg!("Realized {} fee shares for {} assets", shares, assets);
+        Ok(())
+    }
+
+    /// Move the accrued management fee shares into the protocol-owned position
+    /// instead of paying them out, so they keep compounding with the vault
+    /// (admin only)
+    pub fn compound_fee_shares(ctx: Context<CompoundFeeShares>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        vault.accrue_interest(now)?;
+        vault.accrue_management_fee(now)?;
+        let shares = vault.unclaimed_fee_shares;
+        require!(shares > 0, VaultError::ZeroAmount);
+        vault.unclaimed_fee_shares = 0;
+
+        let protocol_position = &mut ctx.accounts.protocol_position;
+        settle_rewards(vault, protocol_position, now)?;
+        protocol_position.vault = vault.key();
+        protocol_position.shares = protocol_position
+            .shares
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+
+        emit!(ProtocolFeeMinted {
+            shares,
+            protocol_shares: protocol_position.shares,
+            timestamp: now,
+        });
+
+        msg!(
+            "Compounded {} fee shares, protocol now holds {}",
+            shares,
+            protocol_position.shares
+        );
+        Ok(())
+    }
+
+    /// Redeem the whole protocol-owned position to `destination` (admin only)
+    pub fn withdraw_protocol_fees(ctx: Context<WithdrawProtocolFees>) -> Result<()> {
+        let accounts = ctx.accounts;
+        accounts.vault.require_op_enabled(OP_WITHDRAW)?;
+        let now = Clock::get()?.unix_timestamp;
+
+        let protocol_position = &mut accounts.protocol_position;
+        let shares = protocol_position.shares;
+        require!(shares > 0, VaultError::ZeroAmount);
+        settle_rewards(&mut accounts.vault, protocol_position, now)?;
+        protocol_position.shares = 0;
+
+        let (assets, _, _) = redeem_shares(
+            &mut accounts.vault,
+            &mut accounts.vault_token_account,
+            accounts.destination.to_account_info(),
+            &accounts.token_program,
+            shares,
+            None,
+            None,
+            0,
+            now,
+        )?;
+
+        emit!(FeesRealized {
+            recipient: accounts.destination.key(),
+            shares,
+            assets,
+            timestamp: now,
+        });
+
+        msg!("Withdrew {} protocol shares for {} assets", shares, assets);
+        Ok(())
+    }
+
+    /// Set the token rewards are paid in and the vault-owned account funding them (admin only)
+    pub fn set_reward_token(ctx: Context<SetRewardToken>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.reward_mint = ctx.accounts.reward_mint.key();
+        vault.reward_token_account = ctx.accounts.reward_token_account.key();
+        msg!("Rewards paid in {}", vault.reward_mint);
+        Ok(())
+    }
+
+    /// Settle and pay out `user`'s pending rewards on their behalf (keeper only)
+    pub fn claim_reward_for(ctx: Context<ClaimRewardFor>, user: Pubkey) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        let user_shares = &mut ctx.accounts.user_shares;
+        settle_rewards(vault, user_shares, now)?;
+
+        // Pay exactly what was settled, nothing more
+        let amount = user_shares.pending_rewards;
+        require!(amount > 0, VaultError::NoPendingRewards);
+        user_shares.pending_rewards = 0;
+
+        transfer_from_vault(
+            vault,
+            &ctx.accounts.reward_token_account,
+            ctx.accounts.user_reward_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        emit!(RewardClaimed {
+            user,
+            claimed_by: ctx.accounts.keeper.key(),
+            amount,
+            timestamp: now,
+        });
+
+        msg!("Paid {} rewards to {}", amount, user);
+        Ok(())
+    }
+
+    /// Settle the caller's pending rewards and, when they are paid in the
+    /// underlying, reinvest them as backing for new shares in the caller's
+    /// position. Reinvesting is a deposit, so every deposit rule applies and
+    /// whatever a partial fill leaves over stays pending; `proof` is needed
+    /// on whitelisted vaults. Rewards in any other token are paid out like a
+    /// claim.
+    pub fn claim_and_compound(
+        ctx: Context<ClaimAndCompound>,
+        proof: Option<Vec<[u8; 32]>>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let accounts = ctx.accounts;
+        let vault = &mut accounts.vault;
+        let user_shares = &mut accounts.user_shares;
+        settle_rewards(vault, user_shares, now)?;
+        let pending = user_shares.pending_rewards;
+        require!(pending > 0, VaultError::NoPendingRewards);
+        let user = accounts.user.key();
+
+        if vault.reward_mint != vault.token_mint {
+            let user_reward_token_account = accounts
+                .user_reward_token_account
+                .as_ref()
+                .ok_or(VaultError::RewardAccountRequired)?;
+            user_shares.pending_rewards = 0;
+            transfer_from_vault(
+                vault,
+                &accounts.reward_token_account,
+                user_reward_token_account.to_account_info(),
+                &accounts.token_program,
+                pending,
+            )?;
+            emit!(RewardClaimed {
+                user,
+                claimed_by: user,
+                amount: pending,
+                timestamp: now,
+            });
+            msg!("Paid {} rewards to {}", pending, user);
+            return Ok(());
+        }
+
+        check_vault_token_account(vault, &accounts.vault_token_account)?;
+        clear_expired_pause(vault, now);
+        end_expired_bootstrap(vault, now);
+        let (amount, cap_room, is_bootstrap) = check_deposit(
+            vault,
+            &user,
+            user_shares.last_deposit_at,
+            proof.as_deref(),
+            pending,
+            now,
+            false,
+        )?;
+        let (shares, _, matched) =
+            vault.price_deposit(amount, cap_room, now, Clock::get()?.slot)?;
+        user_shares.pending_rewards = pending - amount;
+
+        transfer_from_vault(
+            vault,
+            &accounts.reward_token_account,
+            accounts.vault_token_account.to_account_info(),
+            &accounts.token_program,
+            amount,
+        )?;
+        vault.total_assets = vault
+            .total_assets
+            .checked_add(amount)
+            .and_then(|total| total.checked_add(matched))
+            .ok_or(VaultError::MathOverflow)?;
+        vault.match_pool = vault
+            .match_pool
+            .checked_sub(matched)
+            .ok_or(VaultError::Underflow)?;
+        vault.total_shares = vault
+            .total_shares
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+        user_shares.shares = user_shares
+            .shares
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+        user_shares.lifetime_deposited = user_shares
+            .lifetime_deposited
+            .checked_add(amount)
+            .ok_or(VaultError::MathOverflow)?;
+        vault.check_concentration(user_shares.shares, is_bootstrap)?;
+        accounts.vault_token_account.reload()?;
+        check_solvency(vault, &accounts.vault_token_account)?;
+
+        emit!(RewardClaimed {
+            user,
+            claimed_by: user,
+            amount,
+            timestamp: now,
+        });
+        emit!(Compounded {
+            user,
+            amount,
+            shares,
+            timestamp: now,
+        });
+
+        msg!("Compounded {} rewards into {} shares", amount, shares);
+        Ok(())
+    }
+
+    /// Move `shares` out of the caller's position into a new one for
+    /// `new_owner`, which keeps the caller's holding period for the exit fee
+    /// and takes a proportional part of `lifetime_deposited`. On a whitelisted
+    /// vault `proof` must admit `new_owner`, as on a deposit.
+    pub fn split_position(
+        ctx: Context<SplitPosition>,
+        shares: u64,
+        new_owner: Pubkey,
+        proof: Option<Vec<[u8; 32]>>,
+    ) -> Result<()> {
+        require!(shares > 0, VaultError::ZeroAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        check_whitelist(vault, &new_owner, proof.as_deref())?;
+        require!(
+            shares >= vault.min_position_shares,
+            VaultError::DustPositionRemaining
+        );
+
+        let source_shares = &mut ctx.accounts.source_shares;
+        let last_deposit_at = source_shares.last_deposit_at;
+        require!(
+            source_shares.shares >= shares,
+            VaultError::InsufficientShares
+        );
+        let moved_deposits = mul_div(
+            source_shares.lifetime_deposited,
+            shares,
+            source_shares.shares,
+            false,
+        )?;
+        let first_deposit_at = debit_user_shares(vault, source_shares, shares, now)?;
+        source_shares.lifetime_deposited = source_shares
+            .lifetime_deposited
+            .checked_sub(moved_deposits)
+            .ok_or(VaultError::Underflow)?;
+
+        let new_shares = &mut ctx.accounts.new_shares;
+        settle_rewards(vault, new_shares, now)?;
+        new_shares.vault = vault.key();
+        new_shares.shares = shares;
+        new_shares.first_deposit_at = first_deposit_at;
+        new_shares.last_deposit_at = last_deposit_at;
+        new_shares.lifetime_deposited = moved_deposits;
+
+        emit!(PositionSplit {
+            owner: ctx.accounts.user.key(),
+            new_owner,
+            shares,
+            timestamp: now,
+        });
+
+        msg!("Split {} shares to {}", shares, new_owner);
+        Ok(())
+    }
+
+    /// Create the vault's receipt mint, an SPL token with the share decimals
+    /// and the vault PDA as mint authority (admin only)
+    pub fn enable_receipts(ctx: Context<EnableReceipts>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.receipt_mint = ctx.accounts.receipt_mint.key();
+        msg!("Receipt mint {}", vault.receipt_mint);
+        Ok(())
+    }
+
+    /// Move `shares` out of the user's position into receipt tokens, one per
+    /// share. Receipt shares stay in `total_shares` and keep their value, but
+    /// earn no rewards until redeemed and emissions skip them meanwhile. The
+    /// unlock and minimum position checks of a withdrawal apply.
+    pub fn mint_receipt(ctx: Context<MintReceipt>, shares: u64) -> Result<()> {
+        require!(shares > 0, VaultError::ZeroAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        debit_user_shares(vault, &mut ctx.accounts.user_shares, shares, now)?;
+        vault.receipt_shares = vault
+            .receipt_shares
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+
+        let seeds = &[b"vault", vault.token_mint.as_ref(), &[vault.bump]];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.receipt_mint.to_account_info(),
+            to: ctx.accounts.user_receipt_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+        token::mint_to(cpi_ctx, shares)?;
+
+        emit!(ReceiptMinted {
+            user: ctx.accounts.user.key(),
+            shares,
+            timestamp: now,
+        });
+        msg!("Minted {} receipt tokens", shares);
+        Ok(())
+    }
+
+    /// Burn `shares` receipt tokens and credit the shares back to the
+    /// holder's position. A holder without shares starts a fresh position, so
+    /// the exit fee decays from now. Receipts change hands freely, so the
+    /// whitelist (`proof`) and the concentration limit apply as on a deposit.
+    pub fn redeem_receipt(
+        ctx: Context<RedeemReceipt>,
+        shares: u64,
+        proof: Option<Vec<[u8; 32]>>,
+    ) -> Result<()> {
+        require!(shares > 0, VaultError::ZeroAmount);
+        let now = Clock::get()?.unix_timestamp;
+        check_whitelist(
+            &ctx.accounts.vault,
+            &ctx.accounts.user.key(),
+            proof.as_deref(),
+        )?;
+
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.receipt_mint.to_account_info(),
+            from: ctx.accounts.user_receipt_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::burn(cpi_ctx, shares)?;
+
+        let vault = &mut ctx.accounts.vault;
+        let user_shares = &mut ctx.accounts.user_shares;
+        // Settle before the receipt shares rejoin the reward-earning supply
+        settle_rewards(vault, user_shares, now)?;
+        vault.receipt_shares = vault
+            .receipt_shares
+            .checked_sub(shares)
+            .ok_or(VaultError::Underflow)?;
+        user_shares.vault = vault.key();
+        if user_shares.shares == 0 {
+            user_shares.first_deposit_at = now;
+        }
+        user_shares.shares = user_shares
+            .shares
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+        vault.check_concentration(user_shares.shares, false)?;
+
+        emit!(ReceiptRedeemed {
+            user: ctx.accounts.user.key(),
+            shares,
+            timestamp: now,
+        });
+        msg!("Redeemed {} receipt tokens", shares);
+        Ok(())
+    }
+
+    /// Mint `shares` to `user`, backed by assets the authority transfers in at
+    /// the current rate, rounded up in the vault's favor (authority only)
+    pub fn grant_shares(ctx: Context<GrantShares>, user: Pubkey, shares: u64) -> Result<()> {
+        require!(shares > 0, VaultError::ZeroAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        // Price the grant at the up-to-date rate
+        vault.accrue_interest(now)?;
+        vault.accrue_management_fee(now)?;
+        let backing = if vault.total_shares == 0 {
+            mul_div(
+                shares,
+                vault.initial_pps,
+                vault.share_scale * PPS_SCALE,
+                true,
+            )?
+        } else {
+            mul_div(shares, vault.total_assets, vault.total_shares, true)?
+        };
+
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.authority_token_account.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, backing)?;
+
+        ctx.accounts.vault_token_account.reload()?;
+        let received = ctx
+            .accounts
+            .vault_token_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(VaultError::Underflow)?;
+        require!(received >= backing, VaultError::GrantNotBacked);
+
+        let user_shares = &mut ctx.accounts.user_shares;
+        settle_rewards(vault, user_shares, now)?;
+        vault.total_assets = vault
+            .total_assets
+            .checked_add(received)
+            .ok_or(VaultError::MathOverflow)?;
+        vault.total_shares = vault
+            .total_shares
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+        user_shares.shares = user_shares
+            .shares
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+        if user_shares.first_deposit_at == 0 {
+            user_shares.first_deposit_at = now;
+        }
+        user_shares.vault = vault.key();
+        check_solvency(vault, &ctx.accounts.vault_token_account)?;
+
+        emit!(SharesGranted {
+            user,
+            shares,
+            assets: received,
+            timestamp: now,
+        });
+
+        msg!(
+            "Granted {} shares to {}, backed by {}",
+            shares,
+            user,
+            received
+        );
+        Ok(())
+    }
+
+    /// Move a user's entire position into a vault of the deployment set with
+    /// `set_migration_program`. The exit is a regular withdrawal, fees,
+    /// penalties and limits included, and the entry a regular deposit.
+    pub fn migrate_position(ctx: Context<MigratePosition>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let source_vault = &ctx.accounts.source_vault;
+        source_vault.require_op_enabled(OP_MIGRATE)?;
+        check_oracle_deviation(source_vault, ctx.accounts.source_oracle.as_ref())?;
+
+        let destination_mint = {
+            let data = ctx.accounts.destination_vault.try_borrow_data()?;
+            Vault::try_deserialize(&mut &data[..])?.token_mint
+        };
+        require!(
+            destination_mint == source_vault.token_mint,
+            VaultError::MintMismatch
+        );
+
+        // Exit the source vault through the regular withdraw path, so every
+        // exit rule and fee applies
+        let shares_burned = ctx.accounts.source_user_shares.shares;
+        let assets = process_withdraw(
+            &mut ctx.accounts.source_vault,
+            &mut ctx.accounts.source_user_shares,
+            &mut ctx.accounts.source_vault_token_account,
+            ctx.accounts.user_token_account.to_account_info(),
+            &ctx.accounts.token_program,
+            ctx.accounts.user.key(),
+            shares_burned,
+            0,
+            ctx.remaining_accounts,
+        )?;
+        require!(assets > 0, VaultError::ZeroAmount);
+
+        // Enter the destination vault through its own deposit instruction,
+        // so all of its deposit rules apply
+        let shares_before = read_user_shares(&ctx.accounts.destination_user_shares)?;
+
+        let mut data = Vec::with_capacity(16);
+        data.extend_from_slice(&hash(b"global:deposit").to_bytes()[..8]);
+        data.extend_from_slice(&assets.to_le_bytes());
+        let deposit_ix = Instruction {
+            program_id: ctx.accounts.destination_program.key(),
+            accounts: vec![
+                AccountMeta::new(ctx.accounts.destination_vault.key(), false),
+                AccountMeta::new(ctx.accounts.destination_user_shares.key(), false),
+                AccountMeta::new(ctx.accounts.user_token_account.key(), false),
+                AccountMeta::new(ctx.accounts.destination_vault_token_account.key(), false),
+                AccountMeta::new(ctx.accounts.user.key(), true),
+                // The program id stands in for the optional oracle, strategy
+                // and gov token accounts, which are left out
+                AccountMeta::new_readonly(ctx.accounts.destination_program.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.destination_program.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.destination_program.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.token_program.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.system_program.key(), false),
+            ],
+            data,
+        };
+        invoke(
+            &deposit_ix,
+            &[
+                ctx.accounts.destination_vault.to_account_info(),
+                ctx.accounts.destination_user_shares.to_account_info(),
+                ctx.accounts.user_token_account.to_account_info(),
+                ctx.accounts
+                    .destination_vault_token_account
+                    .to_account_info(),
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.destination_program.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        let shares_minted = read_user_shares(&ctx.accounts.destination_user_shares)?
+            .checked_sub(shares_before)
+            .ok_or(VaultError::Underflow)?;
+
+        emit!(PositionMigrated {
+            user: ctx.accounts.user.key(),
+            source_vault: ctx.accounts.source_vault.key(),
+            destination_vault: ctx.accounts.destination_vault.key(),
+            assets,
+            shares_burned,
+            shares_minted,
+            timestamp: now,
+        });
+
+        msg!(
+            "Migrated {} assets: burned {} shares, minted {} shares",
+            assets,
+            shares_burned,
+            shares_minted
+        );
+
+        Ok(())
+    }
+
+    /// Pause deposits (admin or risk operator), optionally resuming after
+    /// `duration` seconds
+    pub fn pause(ctx: Context<PauseAction>, duration: Option<i64>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        require!(!vault.deposits_paused(now), VaultError::AlreadyPaused);
+        vault.paused = true;
+        vault.auto_unpause_at = match duration {
+            Some(duration) => {
+                require!(duration > 0, VaultError::InvalidParameter);
+                now.checked_add(duration).ok_or(VaultError::MathOverflow)?
+            }
+            None => 0,
+        };
+        msg!("Vault paused");
+        Ok(())
+    }
+
+    /// Unpause deposits (admin or risk operator)
+    pub fn unpause(ctx: Context<PauseAction>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.paused, VaultError::NotPaused);
+        // A tripped breaker is cleared through `recover_and_unpause`
+        require!(!vault.breaker_tripped, VaultError::BreakerTripped);
+        vault.paused = false;
+        vault.auto_unpause_at = 0;
+        msg!("Vault unpaused");
+        Ok(())
+    }
+
+    /// Permanently stop deposits and lift the asset floor so every holder can
+    /// exit (admin only)
+    pub fn shutdown(ctx: Context<AdminAction>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require!(!vault.is_shutdown, VaultError::VaultIsShutdown);
+        vault.is_shutdown = true;
+
+        emit!(VaultShutdown {
+            vault: vault.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Vault shut down");
+        Ok(())
+    }
+
+    /// Announce a shutdown for `shutdown_at`, closing the vault to new
+    /// deposits from now on while withdrawals stay open (admin only).
+    /// `shutdown` carries it out; 0 cancels the schedule.
+    pub fn schedule_shutdown(ctx: Context<AdminAction>, shutdown_at: i64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        require!(!vault.is_shutdown, VaultError::VaultIsShutdown);
+        require!(
+            shutdown_at == 0 || shutdown_at > now,
+            VaultError::InvalidParameter
+        );
+        vault.shutdown_scheduled_at = shutdown_at;
+
+        emit!(ShutdownScheduled {
+            vault: vault.key(),
+            shutdown_at,
+            timestamp: now,
+        });
+
+        msg!("Shutdown scheduled for {}", shutdown_at);
+        Ok(())
+    }
+
+    /// Set the assets withdrawals must leave in the vault until shutdown (admin only)
+    pub fn set_min_vault_assets(ctx: Context<AdminAction>, min_vault_assets: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.require_terms_not_worsened(min_vault_assets > vault.min_vault_assets)?;
+        vault.min_vault_assets = min_vault_assets;
+        msg!("Minimum vault assets set to {}", min_vault_assets);
+        Ok(())
+    }
+
+    /// Bracket the PPS range deposits and withdrawals accept, scaled by
+    /// `PPS_SCALE`; zero disables either bound (admin only)
+    pub fn set_pps_bounds(ctx: Context<AdminAction>, min_pps: u64, max_pps: u64) -> Result<()> {
+        require!(
+            max_pps == 0 || min_pps <= max_pps,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.min_pps = min_pps;
+        vault.max_pps = max_pps;
+        msg!("PPS bounds set to [{}, {}]", min_pps, max_pps);
+        Ok(())
+    }
+
+    /// Permanently stop the authority from worsening holders' terms (admin only).
+    /// Afterwards these fail with `ParamsLocked` when they would hurt holders:
+    /// - `set_exit_fee` raising the fee or lengthening its decay
+    /// - `set_withdrawal_queue` lengthening the cooldown or tightening the cap
+    /// - `set_min_vault_assets` raising the floor
+    /// - `set_min_position_shares` raising the minimum
+    /// - `set_management_fee` raising the fee
+    /// - `set_loss_window` lengthening the window or raising its penalty
+    /// - `set_max_requests_per_user` tightening the cap
+    /// - `set_daily_withdrawal_limit` tightening the limit or switching to gross
+    /// - `set_fee_discount` shrinking the discount or changing the gov mint
+    /// - `set_deposit_fee_tiers` raising the fee at any deposit size
+    /// - `set_deposit_bonus` raising the bonus or extending it
+    ///
+    /// Relaxing any of them, pausing and operational settings stay available.
+    pub fn lock_params(ctx: Context<AdminAction>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require!(!vault.params_locked, VaultError::ParamsLocked);
+        vault.params_locked = true;
+        msg!("Economic parameters locked");
+        Ok(())
+    }
+
+    /// Cap the shares deposits may mint within one slot (0 = no cap, admin only)
+    pub fn set_max_shares_per_slot(
+        ctx: Context<AdminAction>,
+        max_shares_per_slot: u64,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.max_shares_per_slot = max_shares_per_slot;
+        msg!("Max shares per slot set to {}", max_shares_per_slot);
+        Ok(())
+    }
+
+    /// Disable individual instructions via the `OP_*` bitmask (admin only)
+    pub fn set_paused_ops(ctx: Context<AdminAction>, paused_ops: u16) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.paused_ops = paused_ops;
+        msg!("Paused ops set to {:#06x}", paused_ops);
+        Ok(())
+    }
+
+    /// Set the minimum interval between a user's deposits (admin only)
+    pub fn set_deposit_cooldown(ctx: Context<AdminAction>, deposit_cooldown: i64) -> Result<()> {
+        require!(deposit_cooldown >= 0, VaultError::InvalidParameter);
+        let vault = &mut ctx.accounts.vault;
+        vault.deposit_cooldown = deposit_cooldown;
+        msg!("Deposit cooldown set to {} seconds", deposit_cooldown);
+        Ok(())
+    }
+
+    /// Configure the allowed lock range and per-day lock bonus (admin only)
+    pub fn set_lock_config(
+        ctx: Context<AdminAction>,
+        min_lock: i64,
+        max_lock: i64,
+        lock_bonus_bps_per_day: u16,
+    ) -> Result<()> {
+        require!(
+            min_lock >= 0 && min_lock <= max_lock,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.min_lock = min_lock;
+        vault.max_lock = max_lock;
+        vault.lock_bonus_bps_per_day = lock_bonus_bps_per_day;
+        msg!(
+            "Lock range set to {}..={} seconds, bonus {} bps/day",
+            min_lock,
+            max_lock,
+            lock_bonus_bps_per_day
+        );
+        Ok(())
+    }
+
+    /// Configure the exit fee that decays linearly to zero over the holding window (admin only)
+    pub fn set_exit_fee(
+        ctx: Context<AdminAction>,
+        max_exit_fee_bps: u16,
+        exit_fee_decay_seconds: i64,
+    ) -> Result<()> {
+        require!(
+            max_exit_fee_bps as u64 <= BPS_DENOMINATOR && exit_fee_decay_seconds >= 0,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.require_terms_not_worsened(
+            max_exit_fee_bps > vault.max_exit_fee_bps
+                || exit_fee_decay_seconds > vault.exit_fee_decay_seconds,
+        )?;
+        vault.max_exit_fee_bps = max_exit_fee_bps;
+        vault.exit_fee_decay_seconds = exit_fee_decay_seconds;
+        msg!(
+            "Exit fee set to {} bps decaying over {} seconds",
+            max_exit_fee_bps,
+            exit_fee_decay_seconds
+        );
+        Ok(())
+    }
+
+    /// Set how long the first depositor into an empty vault stays locked (admin only)
+    pub fn set_bootstrap_lock(
+        ctx: Context<AdminAction>,
+        bootstrap_lock_seconds: i64,
+    ) -> Result<()> {
+        require!(bootstrap_lock_seconds >= 0, VaultError::InvalidParameter);
+        let vault = &mut ctx.accounts.vault;
+        vault.bootstrap_lock_seconds = bootstrap_lock_seconds;
+        msg!("Bootstrap lock set to {} seconds", bootstrap_lock_seconds);
+        Ok(())
+    }
+
+    /// Configure the limits that apply until `bootstrap_until` (admin only):
+    /// the most assets one withdrawal may pay out (0 = no limit), the lock
+    /// put on deposits, and whether strategy allocation is disabled
+    pub fn set_bootstrap_limits(
+        ctx: Context<AdminAction>,
+        max_withdraw: u64,
+        min_lock: i64,
+        allocation_disabled: bool,
+    ) -> Result<()> {
+        require!(min_lock >= 0, VaultError::InvalidParameter);
+        let vault = &mut ctx.accounts.vault;
+        vault.bootstrap_max_withdraw = max_withdraw;
+        vault.bootstrap_min_lock = min_lock;
+        vault.bootstrap_allocation_disabled = allocation_disabled;
+        msg!(
+            "Bootstrap limits: max withdraw {}, lock {}s, allocation {}",
+            max_withdraw,
+            min_lock,
+            if allocation_disabled {
+                "disabled"
+            } else {
+                "enabled"
+            }
+        );
+        Ok(())
+    }
+
+    /// Configure the early-depositor share bonus, at most 100% (admin only).
+    /// Once params are locked the bonus can only shrink or end sooner.
+    pub fn set_deposit_bonus(
+        ctx: Context<AdminAction>,
+        bonus_end_time: i64,
+        bonus_bps: u16,
+    ) -> Result<()> {
+        require!(
+            bonus_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.require_terms_not_worsened(
+            bonus_bps > 0 && (bonus_bps > vault.bonus_bps || bonus_end_time > vault.bonus_end_time),
+        )?;
+        vault.bonus_end_time = bonus_end_time;
+        vault.bonus_bps = bonus_bps;
+        msg!(
+            "Deposit bonus set to {} bps until {}",
+            bonus_bps,
+            bonus_end_time
+        );
+        Ok(())
+    }
+
+    /// Match deposits with `match_bps` of their amount from the sponsor pool (admin only)
+    pub fn set_match_bps(ctx: Context<AdminAction>, match_bps: u16) -> Result<()> {
+        require!(
+            match_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.match_bps = match_bps;
+        msg!("Deposit match set to {} bps", match_bps);
+        Ok(())
+    }
+
+    /// Add `amount` to the pool that matches deposits (anyone may sponsor)
+    pub fn fund_match_pool(ctx: Context<FundMatchPool>, amount: u64) -> Result<()> {
+        require!(amount > 0, VaultError::ZeroAmount);
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.sponsor_token_account.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.sponsor.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        ctx.accounts.vault_token_account.reload()?;
+        let received = ctx
+            .accounts
+            .vault_token_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(VaultError::Underflow)?;
+        let vault = &mut ctx.accounts.vault;
+        vault.match_pool = vault
+            .match_pool
+            .checked_add(received)
+            .ok_or(VaultError::MathOverflow)?;
+        msg!(
+            "Match pool funded with {}, now {}",
+            received,
+            vault.match_pool
+        );
+        Ok(())
+    }
+
+    /// Add `amount` to the insurance fund `report_loss` draws on before
+    /// writing down holders' assets (anyone may fund)
+    pub fn fund_insurance(ctx: Context<FundInsurance>, amount: u64) -> Result<()> {
+        require!(amount > 0, VaultError::ZeroAmount);
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.funder.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        ctx.accounts.vault_token_account.reload()?;
+        let received = ctx
+            .accounts
+            .vault_token_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(VaultError::Underflow)?;
+        let vault = &mut ctx.accounts.vault;
+        vault.insurance_fund = vault
+            .insurance_fund
+            .checked_add(received)
+            .ok_or(VaultError::MathOverflow)?;
+        msg!(
+            "Insurance fund topped up with {}, now {}",
+            received,
+            vault.insurance_fund
+        );
+        Ok(())
+    }
+
+    /// Send the token balance no one has a claim on (rounding dust and direct
+    /// transfers) to the fee recipient (admin only)
+    pub fn sweep_dust(ctx: Context<SweepDust>) -> Result<()> {
+        let vault = &ctx.accounts.vault;
+        check_vault_token_account(vault, &ctx.accounts.vault_token_account)?;
+        let amount = vault.surplus(ctx.accounts.vault_token_account.amount);
+        require!(amount > 0, VaultError::NoDustToSweep);
+
+        transfer_from_vault(
+            vault,
+            &ctx.accounts.vault_token_account,
+            ctx.accounts.treasury.to_account_info(),
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        ctx.accounts.vault_token_account.reload()?;
+        check_solvency(vault, &ctx.accounts.vault_token_account)?;
+
+        emit!(DustSwept {
+            amount,
+            treasury: ctx.accounts.treasury.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Swept {} dust to treasury", amount);
+        Ok(())
+    }
+
+    /// Fold accumulated exit fees into `total_assets`, raising PPS for holders (admin only)
+    pub fn distribute_penalties(ctx: Context<AdminAction>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let amount = vault.penalty_pool;
+        require!(amount > 0, VaultError::NoPenaltiesToDistribute);
+        require!(vault.total_shares > 0, VaultError::NoShareholders);
+
+        vault.total_assets = vault
+            .total_assets
+            .checked_add(amount)
+            .ok_or(VaultError::MathOverflow)?;
+        vault.penalty_pool = 0;
+
+        emit!(PenaltyDistributed {
+            amount,
+            total_assets: vault.total_assets,
+            total_shares: vault.total_shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Distributed {} in penalties to holders", amount);
+        Ok(())
+    }
+
+    /// Create the price-per-share checkpoint ring buffer (admin only)
+    pub fn init_pps_history(ctx: Context<InitPpsHistory>) -> Result<()> {
+        let history = &mut ctx.accounts.pps_history;
+        history.vault = ctx.accounts.vault.key();
+        history.head = 0;
+        history.count = 0;
+        history.checkpoints = [PpsCheckpoint::default(); PPS_HISTORY_LEN];
+        msg!("PPS history initialized");
+        Ok(())
+    }
+
+    /// Record the current price per share as a checkpoint (admin only)
+    pub fn record_pps(ctx: Context<RecordPps>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let pps = ctx.accounts.vault.price_per_share()?;
+        let history = &mut ctx.accounts.pps_history;
+        if let Some(latest) = history.latest() {
+            require!(now > latest.timestamp, VaultError::InvalidParameter);
+        }
+        history.push(PpsCheckpoint {
+            timestamp: now,
+            pps,
+        });
+        msg!("Recorded PPS {} at {}", pps, now);
+        Ok(())
+    }
+
+    /// Annualized return between the two most recent PPS checkpoints, in bps
+    pub fn current_apy_bps(ctx: Context<ViewPpsHistory>) -> Result<u32> {
+        let history = &ctx.accounts.pps_history;
+        match (history.nth_latest(1), history.latest()) {
+            (Some(from), Some(to)) => annualized_bps(&from, &to),
+            _ => Ok(0),
+        }
+    }
+
+    /// Annualized return from the checkpoint closest to `window_seconds` ago
+    /// to the current PPS, in bps. Zero without a checkpoint that old.
+    pub fn apy_over(ctx: Context<ViewPpsHistory>, window_seconds: i64) -> Result<u32> {
+        require!(window_seconds > 0, VaultError::InvalidParameter);
+        let now = Clock::get()?.unix_timestamp;
+        let history = &ctx.accounts.pps_history;
+        match history.at_or_before(now.saturating_sub(window_seconds)) {
+            Some(from) => {
+                let to = PpsCheckpoint {
+                    timestamp: now,
+                    pps: ctx.accounts.vault.price_per_share()?,
+                };
+                annualized_bps(&from, &to)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Replace the keeper immediately (admin only)
+    pub fn set_keeper(ctx: Context<AdminAction>, new_keeper: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_keeper = vault.keeper;
+        vault.keeper = new_keeper;
+        vault.pending_keeper = Pubkey::default();
+
+        emit!(KeeperChanged {
+            old_keeper,
+            new_keeper,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Keeper set to {}", new_keeper);
+        Ok(())
+    }
+
+    /// Set the risk operator, who may pause and unpause but nothing else.
+    /// `Pubkey::default()` removes the role. (admin only)
+    pub fn set_risk_operator(ctx: Context<AdminAction>, new_operator: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_operator = vault.risk_operator;
+        vault.risk_operator = new_operator;
+
+        emit!(RiskOperatorChanged {
+            old_operator,
+            new_operator,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Risk operator set to {}", new_operator);
+        Ok(())
+    }
+
+    /// Register the program called after deposits and withdrawals, or `None`
+    /// to stop calling one. While set, those instructions must pass the hook
+    /// program and its accounts as remaining accounts. (admin only)
+    pub fn set_hook_program(ctx: Context<AdminAction>, hook_program: Option<Pubkey>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_hook_program = vault.hook_program;
+        vault.hook_program = hook_program;
+
+        emit!(HookProgramChanged {
+            old_hook_program,
+            new_hook_program: hook_program,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Hook program set to {:?}", hook_program);
+        Ok(())
+    }
+
+    /// Register the DEX `swap_and_deposit` may route through (admin only,
+    /// `None` disables swaps)
+    pub fn set_dex_program(ctx: Context<AdminAction>, dex_program: Option<Pubkey>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_dex_program = vault.dex_program;
+        vault.dex_program = dex_program;
+
+        emit!(DexProgramChanged {
+            old_dex_program,
+            new_dex_program: dex_program,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("DEX program set to {:?}", dex_program);
+        Ok(())
+    }
+
+    /// Register the deployment `migrate_position` may move positions into
+    /// (admin only, `None` disables migration)
+    pub fn set_migration_program(
+        ctx: Context<AdminAction>,
+        migration_program: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            migration_program != Some(crate::ID),
+            VaultError::InvalidParameter
+        );
+        ctx.accounts.vault.migration_program = migration_program;
+        msg!("Migration program set to {:?}", migration_program);
+        Ok(())
+    }
+
+    /// Set how much deposits and withdrawals log, one of the `EVENT_LEVEL_*`
+    /// values (admin only). Their structured events are always emitted.
+    pub fn set_event_level(ctx: Context<AdminAction>, event_level: u8) -> Result<()> {
+        require!(
+            event_level <= EVENT_LEVEL_VERBOSE,
+            VaultError::InvalidParameter
+        );
+        ctx.accounts.vault.event_level = event_level;
+        msg!("Event level set to {}", event_level);
+        Ok(())
+    }
+
+    /// Snapshot every share balance under a new id. Balances are
+    /// checkpointed lazily on the next change. (admin only)
+    pub fn take_snapshot(ctx: Context<AdminAction>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.snapshot_id = vault
+            .snapshot_id
+            .checked_add(1)
+            .ok_or(VaultError::MathOverflow)?;
+
+        emit!(SnapshotTaken {
+            id: vault.snapshot_id,
+            total_shares: vault.total_shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Snapshot {} taken", vault.snapshot_id);
+        Ok(())
+    }
+
+    /// Set the guardian, who may cancel a pending authority transfer.
+    /// `Pubkey::default()` removes the role. (admin only)
+    pub fn set_guardian(ctx: Context<AdminAction>, new_guardian: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_guardian = vault.guardian;
+        vault.guardian = new_guardian;
+
+        emit!(GuardianChanged {
+            old_guardian,
+            new_guardian,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Guardian set to {}", new_guardian);
+        Ok(())
+    }
+
+    /// Nominate a new authority; the current one stays in control until it
+    /// accepts. The guardian can never be nominated. (admin only)
+    pub fn propose_authority(ctx: Context<AdminAction>, new_authority: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            new_authority != vault.guardian,
+            VaultError::InvalidParameter
+        );
+        vault.pending_authority = new_authority;
+
+        emit!(AuthorityTransferProposed {
+            authority: vault.authority,
+            pending_authority: new_authority,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Authority {} proposed", new_authority);
+        Ok(())
+    }
+
+    /// Take over the vault as the pending authority
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_authority = vault.authority;
+        vault.authority = ctx.accounts.new_authority.key();
+        vault.pending_authority = Pubkey::default();
+
+        emit!(AuthorityChanged {
+            old_authority,
+            new_authority: vault.authority,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Authority {} accepted", vault.authority);
+        Ok(())
+    }
+
+    /// Clear the pending authority, vetoing the transfer (guardian only)
+    pub fn cancel_authority_transfer(ctx: Context<CancelAuthorityTransfer>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let cancelled = vault.pending_authority;
+        vault.pending_authority = Pubkey::default();
+
+        emit!(AuthorityTransferCancelled {
+            cancelled,
+            guardian: ctx.accounts.guardian.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Authority transfer to {} cancelled", cancelled);
+        Ok(())
+    }
+
+    /// Nominate a new keeper; the current keeper stays active until it accepts (admin only)
+    pub fn propose_keeper(ctx: Context<AdminAction>, new_keeper: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.pending_keeper = new_keeper;
+        msg!("Keeper {} proposed", new_keeper);
+        Ok(())
+    }
+
+    /// Take over the keeper role as the pending keeper
+    pub fn accept_keeper(ctx: Context<AcceptKeeper>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_keeper = vault.keeper;
+        vault.keeper = ctx.accounts.new_keeper.key();
+        vault.pending_keeper = Pubkey::default();
+
+        emit!(KeeperChanged {
+            old_keeper,
+            new_keeper: vault.keeper,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Keeper {} accepted", vault.keeper);
+        Ok(())
+    }
+
+    /// Set the smallest nonzero share balance a partial withdrawal may leave (admin only)
+    pub fn set_min_position_shares(
+        ctx: Context<AdminAction>,
+        min_position_shares: u64,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.require_terms_not_worsened(min_position_shares > vault.min_position_shares)?;
+        vault.min_position_shares = min_position_shares;
+        msg!("Minimum position set to {} shares", min_position_shares);
+        Ok(())
+    }
+
+    /// Cap the assets paid out per UTC day across all withdrawals (admin only,
+    /// 0 = no limit). In `DAILY_LIMIT_NET` mode same-day deposits offset
+    /// withdrawals, so funds put back do not use up the limit.
+    pub fn set_daily_withdrawal_limit(
+        ctx: Context<AdminAction>,
+        daily_withdrawal_limit: u64,
+        daily_limit_mode: u8,
+    ) -> Result<()> {
+        require!(
+            daily_limit_mode == DAILY_LIMIT_GROSS || daily_limit_mode == DAILY_LIMIT_NET,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        let tighter_limit = daily_withdrawal_limit != 0
+            && (vault.daily_withdrawal_limit == 0
+                || daily_withdrawal_limit < vault.daily_withdrawal_limit);
+        let stricter_mode =
+            daily_limit_mode == DAILY_LIMIT_GROSS && vault.daily_limit_mode == DAILY_LIMIT_NET;
+        vault.require_terms_not_worsened(tighter_limit || stricter_mode)?;
+        vault.daily_withdrawal_limit = daily_withdrawal_limit;
+        vault.daily_limit_mode = daily_limit_mode;
+        msg!(
+            "Daily withdrawal limit set to {} (mode {})",
+            daily_withdrawal_limit,
+            daily_limit_mode
+        );
+        Ok(())
+    }
+
+    /// Cap the fraction of all shares one position may reach through a
+    /// deposit or receipt redemption (admin only, 0 = no cap). The bootstrap deposit is exempt.
+    pub fn set_max_user_share_bps(
+        ctx: Context<AdminAction>,
+        max_user_share_bps: u16,
+    ) -> Result<()> {
+        require!(
+            max_user_share_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        ctx.accounts.vault.max_user_share_bps = max_user_share_bps;
+        msg!("Max user share set to {} bps", max_user_share_bps);
+        Ok(())
+    }
+
+    /// Configure the holding-time boost on `voting_power` (admin only): a
+    /// position held for `vote_time_boost_cap` seconds or longer weighs
+    /// `vote_boost_bps` more than its shares. Either at 0 disables the boost.
+    pub fn set_vote_boost(
+        ctx: Context<AdminAction>,
+        vote_boost_bps: u16,
+        vote_time_boost_cap: i64,
+    ) -> Result<()> {
+        require!(vote_time_boost_cap >= 0, VaultError::InvalidParameter);
+        let vault = &mut ctx.accounts.vault;
+        vault.vote_boost_bps = vote_boost_bps;
+        vault.vote_time_boost_cap = vote_time_boost_cap;
+        msg!(
+            "Vote boost set to {} bps after {}s",
+            vote_boost_bps,
+            vote_time_boost_cap
+        );
+        Ok(())
+    }
+
+    /// Configure the optional oracle cross-check on the exchange rate (admin only).
+    /// The oracle is a Pyth price account quoting the share price in underlying.
+    pub fn set_oracle(
+        ctx: Context<AdminAction>,
+        oracle: Option<Pubkey>,
+        max_oracle_deviation_bps: u16,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.oracle = oracle;
+        vault.max_oracle_deviation_bps = max_oracle_deviation_bps;
+        msg!("Oracle set, max deviation {} bps", max_oracle_deviation_bps);
+        Ok(())
+    }
+
+    /// Set the Pyth price account quoting the underlying in a reference
+    /// currency, used by `tvl_in_quote` (admin only)
+    pub fn set_quote_oracle(ctx: Context<AdminAction>, quote_oracle: Option<Pubkey>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.quote_oracle = quote_oracle;
+        msg!("Quote oracle set");
+        Ok(())
+    }
+
+    /// Reject oracle prices published more than `max_oracle_staleness` seconds
+    /// ago (0 = accept any age, admin only)
+    pub fn set_max_oracle_staleness(
+        ctx: Context<AdminAction>,
+        max_oracle_staleness: i64,
+    ) -> Result<()> {
+        require!(max_oracle_staleness >= 0, VaultError::InvalidParameter);
+        let vault = &mut ctx.accounts.vault;
+        vault.max_oracle_staleness = max_oracle_staleness;
+        msg!("Max oracle staleness set to {}s", max_oracle_staleness);
+        Ok(())
+    }
+
+    /// Close the current epoch into an `EpochRecord` and start a new one (keeper only)
+    pub fn roll_epoch(ctx: Context<RollEpoch>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+
+        let record = &mut ctx.accounts.epoch_record;
+        record.vault = vault.key();
+        record.epoch = vault.current_epoch;
+        record.started_at = vault.epoch_started_at;
+        record.ended_at = now;
+        record.deposits = vault.epoch_deposits;
+        record.withdrawals = vault.epoch_withdrawals;
+        record.total_assets = vault.total_assets;
+        record.total_shares = vault.total_shares;
+
+        vault.current_epoch = vault
+            .current_epoch
+            .checked_add(1)
+            .ok_or(VaultError::MathOverflow)?;
+        vault.epoch_started_at = now;
+        vault.epoch_deposits = 0;
+        vault.epoch_withdrawals = 0;
+
+        msg!(
+            "Epoch {} closed: deposits {}, withdrawals {}",
+            record.epoch,
+            record.deposits,
+            record.withdrawals
+        );
+        Ok(())
+    }
+
+    /// Point deployed capital at `strategy_token_account` (admin only)
+    pub fn set_strategy(ctx: Context<SetStrategy>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.strategy_token_account = ctx.accounts.strategy_token_account.key();
+        msg!("Strategy set to {}", vault.strategy_token_account);
+        Ok(())
+    }
+
+    /// Let withdrawals beyond idle liquidity queue their shortfall for a
+    /// strategy pull instead of failing (admin only)
+    pub fn set_auto_pull(ctx: Context<AdminAction>, enabled: bool) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.auto_pull_enabled = enabled;
+        msg!("Auto-pull {}", if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// Move idle assets above the reserve into the strategy (keeper only)
+    pub fn allocate_to_strategy(ctx: Context<AllocateToStrategy>, amount: u64) -> Result<()> {
+        require!(amount > 0, VaultError::ZeroAmount);
+        let accounts = ctx.accounts;
+        let now = Clock::get()?.unix_timestamp;
+        end_expired_bootstrap(&mut accounts.vault, now);
+        require!(
+            !accounts.vault.allocation_blocked(now),
+            VaultError::BootstrapAllocationDisabled
+        );
+        let available = accounts
+            .vault
+            .idle_above_reserve(accounts.vault_token_account.amount)?;
+        require!(amount <= available, VaultError::InsufficientAssets);
+
+        deploy_to_strategy(
+            &mut accounts.vault,
+            &mut accounts.vault_token_account,
+            accounts.strategy_token_account.to_account_info(),
+            &accounts.token_program,
+            amount,
+        )
+    }
+
+    /// Configure automatic allocation of deposits and the idle reserve the
+    /// strategy may not touch, both in bps (admin only)
+    pub fn set_allocation_config(
+        ctx: Context<AdminAction>,
+        auto_allocate_bps: u16,
+        reserve_bps: u16,
+    ) -> Result<()> {
+        require!(
+            auto_allocate_bps as u64 <= BPS_DENOMINATOR && reserve_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.auto_allocate_bps = auto_allocate_bps;
+        vault.reserve_bps = reserve_bps;
+        msg!(
+            "Auto-allocate {} bps of deposits, reserve {} bps",
+            auto_allocate_bps,
+            reserve_bps
+        );
+        Ok(())
+    }
+
+    /// Bring deployed assets back into the vault (keeper only)
+    pub fn return_from_strategy(ctx: Context<ReturnFromStrategy>, amount: u64) -> Result<()> {
+        require!(amount > 0, VaultError::ZeroAmount);
+        let accounts = ctx.accounts;
+        recall_from_strategy(
+            &mut accounts.vault,
+            accounts.source_token_account.to_account_info(),
+            accounts.vault_token_account.to_account_info(),
+            accounts.keeper.to_account_info(),
+            &accounts.token_program,
+            amount,
+        )
+    }
+
+    /// Allocate to or recall from the strategy until `target_deployed_bps` of
+    /// total assets is deployed, as far as idle above the reserve and the
+    /// strategy balance allow (keeper only)
+    pub fn rebalance(ctx: Context<Rebalance>, target_deployed_bps: u16) -> Result<()> {
+        require!(
+            target_deployed_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        let accounts = ctx.accounts;
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut accounts.vault;
+        end_expired_bootstrap(vault, now);
+        let deployed_before = vault.deployed_assets;
+        let target = mul_div(
+            vault.total_assets,
+            target_deployed_bps as u64,
+            BPS_DENOMINATOR,
+            false,
+        )?;
+
+        if target > deployed_before {
+            require!(
+                !vault.allocation_blocked(now),
+                VaultError::BootstrapAllocationDisabled
+            );
+            let amount = (target - deployed_before)
+                .min(vault.idle_above_reserve(accounts.vault_token_account.amount)?);
+            if amount > 0 {
+                deploy_to_strategy(
+                    vault,
+                    &mut accounts.vault_token_account,
+                    accounts.strategy_token_account.to_account_info(),
+                    &accounts.token_program,
+                    amount,
+                )?;
+            }
+        } else {
+            let amount = (deployed_before - target).min(accounts.strategy_token_account.amount);
+            if amount > 0 {
+                recall_from_strategy(
+                    vault,
+                    accounts.strategy_token_account.to_account_info(),
+                    accounts.vault_token_account.to_account_info(),
+                    accounts.keeper.to_account_info(),
+                    &accounts.token_program,
+                    amount,
+                )?;
+            }
+        }
+
+        emit!(Rebalanced {
+            target_deployed_bps,
+            deployed_before,
+            deployed_after: vault.deployed_assets,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!(
+            "Rebalanced deployed assets from {} to {}",
+            deployed_before,
+            vault.deployed_assets
+        );
+        Ok(())
+    }
+
+    /// Write `loss` off the deployed assets (admin only). The insurance fund
+    /// absorbs what it can; only the rest comes out of `total_assets` and
+    /// opens the loss window, during which exits pay the loss-window penalty.
+    pub fn report_loss(ctx: Context<AdminAction>, loss: u64) -> Result<()> {
+        require!(loss > 0, VaultError::ZeroAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        vault.deployed_assets = vault
+            .deployed_assets
+            .checked_sub(loss)
+            .ok_or(VaultError::Underflow)?;
+
+        // Covered tokens stay in the vault as holders' backing
+        let covered = loss.min(vault.insurance_fund);
+        if covered > 0 {
+            vault.insurance_fund -= covered;
+            emit!(InsuranceUsed {
+                amount: covered,
+                insurance_fund: vault.insurance_fund,
+                timestamp: now,
+            });
+        }
+        let uncovered = loss - covered;
+        vault.total_assets = vault
+            .total_assets
+            .checked_sub(uncovered)
+            .ok_or(VaultError::Underflow)?;
+        if uncovered > 0 {
+            vault.loss_window_until = now
+                .checked_add(vault.loss_window_seconds)
+                .ok_or(VaultError::MathOverflow)?;
+        }
+        check_circuit_breaker(vault, now)?;
+
+        emit!(LossReported {
+            loss,
+            total_assets: vault.total_assets,
+            loss_window_until: vault.loss_window_until,
+            timestamp: now,
+        });
+
+        msg!(
+            "Reported loss of {}, penalty window until {}",
+            loss,
+            vault.loss_window_until
+        );
+        Ok(())
+    }
+
+    /// Set the loss window `report_loss` opens and the extra exit penalty
+    /// charged during it (admin only)
+    pub fn set_loss_window(
+        ctx: Context<AdminAction>,
+        window_seconds: i64,
+        penalty_bps: u16,
+    ) -> Result<()> {
+        require!(
+            window_seconds >= 0 && penalty_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.require_terms_not_worsened(
+            window_seconds > vault.loss_window_seconds
+                || penalty_bps > vault.loss_window_penalty_bps,
+        )?;
+        vault.loss_window_seconds = window_seconds;
+        vault.loss_window_penalty_bps = penalty_bps;
+        msg!(
+            "Loss window set to {}s with a {} bps penalty",
+            window_seconds,
+            penalty_bps
+        );
+        Ok(())
+    }
+
+    /// Realize strategy profit: move `profit` from the strategy into the vault
+    /// and credit it to holders, at most once per `min_harvest_interval` (keeper only)
+    pub fn harvest(ctx: Context<ReturnFromStrategy>, profit: u64) -> Result<()> {
+        require!(profit > 0, VaultError::ZeroAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.total_shares > 0, VaultError::NoShareholders);
+        let next_harvest = vault
+            .last_harvest
+            .checked_add(vault.min_harvest_interval)
+            .ok_or(VaultError::MathOverflow)?;
+        require!(now >= next_harvest, VaultError::HarvestTooSoon);
+        vault.last_harvest = now;
+
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.source_token_account.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.keeper.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, profit)?;
+
+        ctx.accounts.vault_token_account.reload()?;
+        let received = ctx
+            .accounts
+            .vault_token_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(VaultError::Underflow)?;
+        vault.total_assets = vault
+            .total_assets
+            .checked_add(received)
+            .ok_or(VaultError::MathOverflow)?;
+
+        emit!(Harvested {
+            profit: received,
+            total_assets: vault.total_assets,
+            timestamp: now,
+        });
+
+        msg!("Harvested {}", received);
+        Ok(())
+    }
+
+    /// Re-price shares to an externally reported NAV by setting `total_assets`
+    /// to `new_total_assets`, within `max_nav_change_bps` of the current value
+    /// and at most once per `min_nav_interval` (keeper only). The difference
+    /// is booked against the deployed assets, since idle ones are on hand.
+    pub fn update_nav(ctx: Context<UpdateNav>, new_total_assets: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        let next_update = vault
+            .last_nav_update
+            .checked_add(vault.min_nav_interval)
+            .ok_or(VaultError::MathOverflow)?;
+        require!(now >= next_update, VaultError::NavUpdateTooSoon);
+
+        let old_total_assets = vault.total_assets;
+        let change = old_total_assets.abs_diff(new_total_assets);
+        if vault.max_nav_change_bps > 0 {
+            let max_change = mul_div(
+                old_total_assets,
+                vault.max_nav_change_bps as u64,
+                BPS_DENOMINATOR,
+                false,
+            )?;
+            require!(change <= max_change, VaultError::NavChangeTooLarge);
+        }
+        vault.deployed_assets = if new_total_assets >= old_total_assets {
+            vault
+                .deployed_assets
+                .checked_add(change)
+                .ok_or(VaultError::MathOverflow)?
+        } else {
+            vault
+                .deployed_assets
+                .checked_sub(change)
+                .ok_or(VaultError::Underflow)?
+        };
+        vault.total_assets = new_total_assets;
+        vault.last_nav_update = now;
+        vault.pps_last_update = now;
+        check_circuit_breaker(vault, now)?;
+
+        emit!(NavUpdated {
+            old_total_assets,
+            new_total_assets,
+            price_per_share: vault.price_per_share()?,
+            timestamp: now,
+        });
+
+        msg!(
+            "NAV updated from {} to {}",
+            old_total_assets,
+            new_total_assets
+        );
+        Ok(())
+    }
+
+    /// Bound `update_nav`: the largest change per update in bps of total
+    /// assets (0 = unbounded) and the seconds between updates (admin only)
+    pub fn set_nav_guardrails(
+        ctx: Context<AdminAction>,
+        max_nav_change_bps: u16,
+        min_nav_interval: i64,
+    ) -> Result<()> {
+        require!(
+            max_nav_change_bps as u64 <= BPS_DENOMINATOR && min_nav_interval >= 0,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.max_nav_change_bps = max_nav_change_bps;
+        vault.min_nav_interval = min_nav_interval;
+        msg!(
+            "NAV updates limited to {} bps every {}s",
+            max_nav_change_bps,
+            min_nav_interval
+        );
+        Ok(())
+    }
+
+    /// Pause deposits when a reported loss or NAV update drops PPS more than
+    /// `max_pps_drop_bps` below the last accepted PPS (admin only, 0 = off).
+    /// The current PPS becomes the reference.
+    pub fn set_circuit_breaker(ctx: Context<AdminAction>, max_pps_drop_bps: u16) -> Result<()> {
+        require!(
+            max_pps_drop_bps as u64 <= BPS_DENOMINATOR,
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.max_pps_drop_bps = max_pps_drop_bps;
+        vault.last_pps = vault.price_per_share()?;
+        msg!("Circuit breaker set to a {} bps PPS drop", max_pps_drop_bps);
+        Ok(())
+    }
+
+    /// Recover from a tripped circuit breaker: set `total_assets` to
+    /// `corrected_total_assets`, take the resulting PPS as the new reference
+    /// and unpause deposits (admin only). As in `update_nav`, the correction
+    /// is booked against the deployed assets.
+    pub fn recover_and_unpause(
+        ctx: Context<AdminAction>,
+        corrected_total_assets: u64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.breaker_tripped, VaultError::BreakerNotTripped);
+
+        let old_total_assets = vault.total_assets;
+        let change = old_total_assets.abs_diff(corrected_total_assets);
+        vault.deployed_assets = if corrected_total_assets >= old_total_assets {
+            vault
+                .deployed_assets
+                .checked_add(change)
+                .ok_or(VaultError::MathOverflow)?
+        } else {
+            vault
+                .deployed_assets
+                .checked_sub(change)
+                .ok_or(VaultError::Underflow)?
+        };
+        vault.total_assets = corrected_total_assets;
+        vault.pps_last_update = now;
+        vault.last_pps = vault.price_per_share()?;
+        vault.breaker_tripped = false;
+        vault.paused = false;
+        vault.auto_unpause_at = 0;
+
+        emit!(Recovered {
+            old_total_assets,
+            new_total_assets: corrected_total_assets,
+            price_per_share: vault.last_pps,
+            timestamp: now,
+        });
+
+        msg!(
+            "Recovered at {} total assets, PPS {}",
+            corrected_total_assets,
+            vault.last_pps
+        );
+        Ok(())
+    }
+
+    /// Reject deposits once `max_pps_staleness` seconds have passed since
+    /// `update_nav` or `accrue_interest` last refreshed PPS (admin only, 0 = off)
+    pub fn set_max_pps_staleness(ctx: Context<AdminAction>, max_pps_staleness: i64) -> Result<()> {
+        require!(max_pps_staleness >= 0, VaultError::InvalidParameter);
+        ctx.accounts.vault.max_pps_staleness = max_pps_staleness;
+        msg!("Max PPS staleness set to {}s", max_pps_staleness);
+        Ok(())
+    }
+
+    /// Cap each deposit at `base + per_day * days_since_init`, so the size
+    /// allowed grows with the vault's age (admin only, both 0 = no throttle)
+    pub fn set_deposit_throttle(ctx: Context<AdminAction>, base: u64, per_day: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.deposit_throttle_base = base;
+        vault.deposit_throttle_per_day = per_day;
+        msg!(
+            "Deposits throttled to {} plus {} per day of vault age",
+            base,
+            per_day
+        );
+        Ok(())
+    }
+
+    /// Cap `total_assets` (0 = uncapped). With `allow_partial_deposit`, a
+    /// deposit crossing the cap is filled up to it instead of rejected. (admin only)
+    pub fn set_deposit_cap(
+        ctx: Context<AdminAction>,
+        max_total_assets: u64,
+        allow_partial_deposit: bool,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.max_total_assets = max_total_assets;
+        vault.allow_partial_deposit = allow_partial_deposit;
+        msg!(
+            "Deposit cap set to {} (partial fills {})",
+            max_total_assets,
+            allow_partial_deposit
+        );
+        Ok(())
+    }
+
+    /// Ramp the deposit cap from `cap_start` now to `cap_max` over
+    /// `ramp_seconds`; `cap_max` 0 removes the ramp (admin only)
+    pub fn set_deposit_cap_ramp(
+        ctx: Context<AdminAction>,
+        cap_start: u64,
+        cap_max: u64,
+        ramp_seconds: i64,
+    ) -> Result<()> {
+        require!(
+            ramp_seconds >= 0 && (cap_max == 0 || cap_start <= cap_max),
+            VaultError::InvalidParameter
+        );
+        let vault = &mut ctx.accounts.vault;
+        vault.cap_start = cap_start;
+        vault.cap_max = cap_max;
+        vault.cap_start_time = Clock::get()?.unix_timestamp;
+        vault.cap_ramp_seconds = ramp_seconds;
+        msg!(
+            "Deposit cap ramps from {} to {} over {}s",
+            cap_start,
+            cap_max,
+            ramp_seconds
+        );
+        Ok(())
+    }
+
+    /// Require at least `min_harvest_interval` seconds between harvests (admin only)
+    pub fn set_min_harvest_interval(
+        ctx: Context<AdminAction>,
+        min_harvest_interval: i64,
+    ) -> Result<()> {
+        require!(min_harvest_interval >= 0, VaultError::InvalidParameter);
+        let vault = &mut ctx.accounts.vault;
+        vault.min_harvest_interval = min_harvest_interval;
+        msg!("Min harvest interval set to {}s", min_harvest_interval);
+        Ok(())
+    }
+
+    /// Snapshot of vault totals and current-epoch flows
+    pub fn vault_info(ctx: Context<ViewVault>) -> Result<VaultInfo> {
+        VaultInfo::from_vault(&ctx.accounts.vault)
+    }
+
+    /// Set the merkle root of allowed depositors; all zeros disables the whitelist (admin only)
+    pub fn set_whitelist_root(ctx: Context<AdminAction>, whitelist_root: [u8; 32]) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.whitelist_root = whitelist_root;
+        msg!("Whitelist root updated");
+        Ok(())
+    }
+
+    /// Open the v1 import window: take in the v1 `assets` and mint the
+    /// `total_shares` they back, held unassigned until imported. The vault
+    /// must be fresh, so the v1 rate cannot dilute other holders (admin only)
+    pub fn start_migration(
+        ctx: Context<StartMigration>,
+        migration_root: [u8; 32],
+        total_shares: u64,
+        assets: u64,
+    ) -> Result<()> {
+        require!(total_shares > 0 && assets > 0, VaultError::InvalidParameter);
+        require!(
+            ctx.accounts.vault.total_shares == 0 && ctx.accounts.vault.total_assets == 0,
+            VaultError::MigrationTargetNotEmpty
+        );
+        let balance_before = ctx.accounts.vault_token_account.amount;
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.authority_token_account.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, assets)?;
+        ctx.accounts.vault_token_account.reload()?;
+        let received = ctx
+            .accounts
+            .vault_token_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(VaultError::Underflow)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.accrue_rewards(Clock::get()?.unix_timestamp)?;
+        vault.total_assets = vault
+            .total_assets
+            .checked_add(received)
+            .ok_or(VaultError::MathOverflow)?;
+        vault.total_shares = vault
+            .total_shares
+            .checked_add(total_shares)
+            .ok_or(VaultError::MathOverflow)?;
+        vault.unimported_shares = total_shares;
+        vault.migration_root = migration_root;
+        vault.migration_mode = true;
+        check_solvency(vault, &ctx.accounts.vault_token_account)?;
+
+        msg!(
+            "Migration opened: {} shares backed by {} assets",
+            total_shares,
+            received
+        );
+        Ok(())
+    }
+
+    /// Credit `user` the v1 position at leaf `index` of the migration root
+    /// (admin only, while the import window is open)
+    pub fn import_position(
+        ctx: Context<ImportPosition>,
+        user: Pubkey,
+        index: u32,
+        shares: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(shares > 0, VaultError::ZeroAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        let leaf = hashv(&[&index.to_le_bytes(), user.as_ref(), &shares.to_le_bytes()]).to_bytes();
+        require!(
+            verify_merkle_proof(&proof, vault.migration_root, leaf),
+            VaultError::InvalidProof
+        );
+        ctx.accounts.import_bitmap.claim(index)?;
+
+        vault.unimported_shares = vault
+            .unimported_shares
+            .checked_sub(shares)
+            .ok_or(VaultError::Underflow)?;
+        let user_shares = &mut ctx.accounts.user_shares;
+        settle_rewards(vault, user_shares, now)?;
+        user_shares.shares = user_shares
+            .shares
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+        user_shares.vault = vault.key();
+        if user_shares.first_deposit_at == 0 {
+            user_shares.first_deposit_at = now;
+        }
+
+        emit!(PositionImported {
+            user,
+            index,
+            shares,
+            timestamp: now,
+        });
+
+        msg!("Imported v1 position #{}: {} shares", index, shares);
+        Ok(())
+    }
+
+    /// Close the import window. Shares never imported are burned, so their
+    /// assets go to the holders. (admin only)
+    pub fn end_migration(ctx: Context<AdminAction>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.migration_mode, VaultError::MigrationNotActive);
+        vault.accrue_rewards(Clock::get()?.unix_timestamp)?;
+        let burned = vault.unimported_shares;
+        vault.total_shares = vault
+            .total_shares
+            .checked_sub(burned)
+            .ok_or(VaultError::Underflow)?;
+        vault.unimported_shares = 0;
+        vault.migration_mode = false;
+
+        emit!(MigrationEnded {
+            burned_shares: burned,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        msg!("Migration closed, burned {} unimported shares", burned);
+        Ok(())
+    }
+
+    /// Report which optional vault behaviors are currently enabled
+    pub fn get_features(ctx: Context<ViewVault>) -> Result<FeatureFlags> {
+        let now = Clock::get()?.unix_timestamp;
+        Ok(FeatureFlags::from_vault(&ctx.accounts.vault, now))
+    }
+
+    /// Every term a holder is subject to, in one read
+    pub fn get_policy(ctx: Context<ViewVault>) -> Result<VaultPolicy> {
+        Ok(VaultPolicy::from_vault(&ctx.accounts.vault))
+    }
+
+    /// Smallest deposit that mints at least one share at the current totals,
+    /// 1 for an empty vault. Fees and bonuses are not applied.
+    pub fn min_depositable(ctx: Context<ViewVault>) -> Result<u64> {
+        let vault = &ctx.accounts.vault;
+        if vault.total_shares == 0 {
+            return Ok(1);
+        }
+        Ok(mul_div(1, vault.total_assets, vault.total_shares, true)?.max(1))
+    }
+
+    /// Largest amount `user` could deposit right now, zero while deposits are
+    /// blocked for them. Whitelist membership and the oracle check are not
+    /// evaluated since they need a proof or a price account.
+    pub fn max_deposit(ctx: Context<ViewPosition>) -> Result<u64> {
+        let now = Clock::get()?.unix_timestamp;
+        let accounts = &ctx.accounts;
+        Ok(accounts
+            .vault
+            .max_deposit(accounts.user_shares.as_deref(), now))
+    }
+
+    /// Shares `max_deposit` would mint at the current rate, before any early bonus
+    pub fn max_mint(ctx: Context<ViewPosition>) -> Result<u64> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &ctx.accounts.vault;
+        let assets = vault.max_deposit(ctx.accounts.user_shares.as_deref(), now);
+        Ok(vault
+            .convert_to_shares(assets)?
+            .min(u64::MAX - vault.total_shares))
+    }
+
+    /// Most shares `user` could redeem right now, zero while withdrawals are
+    /// blocked for them. The oracle check is not evaluated since it needs a
+    /// price account.
+    pub fn max_redeem(ctx: Context<ViewPosition>) -> Result<u64> {
+        let now = Clock::get()?.unix_timestamp;
+        let accounts = &ctx.accounts;
+        accounts
+            .vault
+            .max_redeem(accounts.user_shares.as_deref(), now)
+    }
+
+    /// Shares `user` held when snapshot `id` was taken. Fails with
+    /// `UnknownSnapshot` for ids not yet taken or no longer recorded.
+    pub fn snapshot_share(ctx: Context<ViewPosition>, id: u64) -> Result<u64> {
+        let current = ctx.accounts.vault.snapshot_id;
+        match ctx.accounts.user_shares.as_deref() {
+            Some(position) => position.shares_at(id, current),
+            None => {
+                require!(id > 0 && id <= current, VaultError::UnknownSnapshot);
+                Ok(0)
+            }
+        }
+    }
+
+    /// Governance weight of `user`: their shares, boosted by how long they
+    /// have held them when the vault configures a vote boost
+    pub fn voting_power(ctx: Context<ViewPosition>) -> Result<u64> {
+        let now = Clock::get()?.unix_timestamp;
+        match ctx.accounts.user_shares.as_deref() {
+            Some(position) => ctx.accounts.vault.voting_power(position, now),
+            None => Ok(0),
+        }
+    }
+
+    /// Assets `max_redeem` would pay out right now, net of the exit fee
+    pub fn max_withdraw(ctx: Context<ViewPosition>) -> Result<u64> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &ctx.accounts.vault;
+        let position = match ctx.accounts.user_shares.as_deref() {
+            Some(position) => position,
+            None => return Ok(0),
+        };
+        let shares = vault.max_redeem(Some(position), now)?;
+        if shares == 0 {
+            return Ok(0);
+        }
+        Ok(WithdrawQuote::new(vault, shares, position.first_deposit_at, now)?.net_assets)
+    }
+
+    /// Assets claiming this withdrawal request would pay out right now, net
+    /// of the exit fee: its locked value, or its value at the current rate
+    pub fn pending_withdrawal_value(ctx: Context<ViewWithdrawalRequest>) -> Result<u64> {
+        let now = Clock::get()?.unix_timestamp;
+        let mut vault = ctx.accounts.vault.clone().into_inner();
+        vault.accrue_interest(now)?;
+        vault.accrue_management_fee(now)?;
+        let request = &ctx.accounts.withdrawal_request;
+        let gross_assets = match request.locked_assets {
+            Some(assets) => assets,
+            None => vault.redeem_value(request.shares)?,
+        };
+        Ok(WithdrawQuote::at(
+            &vault,
+            gross_assets,
+            request.shares,
+            request.first_deposit_at,
+            now,
+        )?
+        .net_assets)
+    }
+
+    /// Canonical vault and position PDAs for `token_mint` and `user`, so
+    /// clients need not rebuild the seeds themselves
+    pub fn derive_addresses(
+        _ctx: Context<DeriveAddresses>,
+        token_mint: Pubkey,
+        user: Pubkey,
+    ) -> Result<Addresses> {
+        Ok(Addresses::new(&token_mint, &user))
+    }
+
+    /// What depositing `amount` now would cost `user` and mint them
+    pub fn quote_deposit(ctx: Context<ViewPosition>, amount: u64) -> Result<DepositQuote> {
+        let now = Clock::get()?.unix_timestamp;
+        // Accrue on a copy so the quote uses the rate the deposit would see
+        let mut vault = ctx.accounts.vault.clone().into_inner();
+        vault.accrue_interest(now)?;
+        vault.accrue_management_fee(now)?;
+        DepositQuote::new(&vault, amount, now)
+    }
+
+    /// Run every check `deposit` of `amount` by `user` would, on a copy of
+    /// the vault, and report the shares it would mint or the error it would
+    /// fail with. Token balances and transfer fees are not checked.
+    pub fn dry_run_deposit(
+        ctx: Context<DryRunDeposit>,
+        amount: u64,
+        proof: Option<Vec<[u8; 32]>>,
+    ) -> Result<DepositResult> {
+        let now = Clock::get()?.unix_timestamp;
+        let accounts = &ctx.accounts;
+        let mut vault = accounts.vault.clone().into_inner();
+        let position = accounts.user_shares.as_deref();
+        let outcome = (|| {
+            check_oracle_deviation(&vault, accounts.oracle.as_ref())?;
+            let (amount, cap_room, is_bootstrap) = check_deposit(
+                &mut vault,
+                &accounts.user.key(),
+                position.map_or(0, |position| position.last_deposit_at),
+                proof.as_deref(),
+                amount,
+                now,
+                false,
+            )?;
+            let (fee, _) = vault.deposit_fee(amount, amount)?;
+            let fee = apply_fee_discount(
+                fee,
+                gov_discount_bps(&vault, accounts.gov_token_account.as_ref())?,
+            )?;
+            let (shares, _, _) =
+                vault.price_deposit(amount - fee, cap_room, now, Clock::get()?.slot)?;
+            vault.total_shares = vault
+                .total_shares
+                .checked_add(shares)
+                .ok_or(VaultError::MathOverflow)?;
+            let position_shares = position
+                .map_or(0, |position| position.shares)
+                .checked_add(shares)
+                .ok_or(VaultError::MathOverflow)?;
+            vault.check_concentration(position_shares, is_bootstrap)?;
+            Ok((amount, shares))
+        })();
+        Ok(DepositResult::from_outcome(outcome))
+    }
+
+    /// What redeeming `shares` now would pay `user`, after their exit fee
+    pub fn quote_withdraw(ctx: Context<ViewPosition>, shares: u64) -> Result<WithdrawQuote> {
+        let now = Clock::get()?.unix_timestamp;
+        let mut vault = ctx.accounts.vault.clone().into_inner();
+        vault.accrue_interest(now)?;
+        vault.accrue_management_fee(now)?;
+        // Without a position the holding period starts now, so the full fee applies
+        let first_deposit_at = ctx
+            .accounts
+            .user_shares
+            .as_ref()
+            .map_or(now, |position| position.first_deposit_at);
+        WithdrawQuote::new(&vault, shares, first_deposit_at, now)
+    }
+
+    /// Consistent snapshot of a user's position, valued as a withdrawal now would pay
+    pub fn position_summary(ctx: Context<ViewPosition>) -> Result<PositionSummary> {
+        let now = Clock::get()?.unix_timestamp;
+        let accounts = &ctx.accounts;
+        match accounts.user_shares.as_deref() {
+            Some(position) => PositionSummary::from_position(&accounts.vault, position, now),
+            None => Ok(PositionSummary::default()),
+        }
+    }
+
+    /// Whether depositing `amount` now would mint no shares and be rejected
+    pub fn would_round_to_zero(ctx: Context<ViewVault>, amount: u64) -> Result<bool> {
+        let now = Clock::get()?.unix_timestamp;
+        let (shares, _) = ctx.accounts.vault.deposit_shares(amount, now)?;
+        Ok(shares == 0)
+    }
+
+    /// `total_assets` valued at the quote oracle price, in quote units at the
+    /// underlying's decimals. Without a quote oracle this is `total_assets`.
+    pub fn tvl_in_quote(ctx: Context<ViewTvl>) -> Result<u64> {
+        let vault = &ctx.accounts.vault;
+        let Some(expected_oracle) = vault.quote_oracle else {
+            return Ok(vault.total_assets);
+        };
+        let oracle = ctx
+            .accounts
+            .quote_oracle
+            .as_ref()
+            .ok_or(VaultError::InvalidOracle)?;
+        require_keys_eq!(oracle.key(), expected_oracle, VaultError::InvalidOracle);
+
+        let price = oracle_price(oracle, vault.max_oracle_staleness)?;
+        mul_div(vault.total_assets, price, PPS_SCALE, false)
+    }
+
+    /// Fraction of assets deployed to strategies, in bps
+    pub fn utilization(ctx: Context<ViewVault>) -> Result<u16> {
+        ctx.accounts.vault.utilization_bps()
+    }
+
+    /// Health check for monitoring: whether idle tokens plus deployed assets
+    /// still back `total_assets` and the share and asset totals are empty
+    /// together, with the shortfall if not. Unlike the checks run inside
+    /// deposits and withdrawals this never fails on an unhealthy vault.
+    pub fn is_solvent(ctx: Context<ViewSolvency>) -> Result<SolvencyStatus> {
+        Ok(SolvencyStatus::from_vault(
+            &ctx.accounts.vault,
+            ctx.accounts.vault_token_account.amount,
+        ))
+    }
+}
+
+// ============ Helpers ============
+
+/// Shared deposit path: validates, transfers `amount` in and mints shares
+/// for the amount actually received. `proof` is required when the vault has
+/// a whitelist root. Returns the number of shares minted.
+///
+/// The inbound transfer comes before the state update because shares are
+/// priced on what actually arrived. Solana rejects a CPI back into this
+/// program from the token program, so nothing can observe the vault between
+/// the two; outgoing transfers (auto-allocation) still run only after all
+/// effects are applied.
+fn process_deposit(
+    accounts: &mut Deposit,
+    remaining_accounts: &[AccountInfo],
+    amount: u64,
+    now: i64,
+    proof: Option<&[[u8; 32]]>,
+    refund_excess: bool,
+) -> Result<u64> {
+    let requested = amount;
+    let (amount, cap_room, is_bootstrap) =
+        validate_deposit(accounts, amount, now, proof, refund_excess)?;
+    let received = if refund_excess && amount < requested {
+        transfer_in_and_refund(accounts, requested, requested - amount, now)?
+    } else {
+        transfer_deposit_in(accounts, amount)?
+    };
+    // Like the exit fee, the deposit fee stays in the token account, parked
+    // in the penalty pool
+    let (fee, fee_bps) = accounts.vault.deposit_fee(amount, received)?;
+    let fee = apply_fee_discount(
+        fee,
+        gov_discount_bps(&accounts.vault, accounts.gov_token_account.as_ref())?,
+    )?;
+    accounts.vault.penalty_pool = accounts
+        .vault
+        .penalty_pool
+        .checked_add(fee)
+        .ok_or(VaultError::MathOverflow)?;
+    let (shares, bonus_shares, matched) =
+        apply_deposit(accounts, received - fee, cap_room, is_bootstrap, now)?;
+
+    // Put part of the deposit to work right away, never dipping into the reserve
+    let vault = &mut accounts.vault;
+    if vault.auto_allocate_bps > 0 && !vault.allocation_blocked(now) {
+        let target = u64::try_from(
+            (received as u128)
+                .checked_mul(vault.auto_allocate_bps as u128)
+                .ok_or(VaultError::MathOverflow)?
+                / BPS_DENOMINATOR as u128,
+        )
+        .map_err(|_| VaultError::MathOverflow)?;
+        let amount = target.min(vault.idle_above_reserve(accounts.vault_token_account.amount)?);
+        if amount > 0 {
+            let strategy_token_account = accounts
+                .strategy_token_account
+                .as_ref()
+                .ok_or(VaultError::StrategyAccountRequired)?;
+            deploy_to_strategy(
+                vault,
+                &mut accounts.vault_token_account,
+                strategy_token_account.to_account_info(),
+                &accounts.token_program,
+                amount,
+            )?;
+        }
+    }
+
+    emit!(DepositEvent {
+        user: accounts.user.key(),
+        amount: received,
+        shares,
+        bonus_shares,
+        matched,
+        fee,
+        fee_bps,
+        timestamp: now,
+    });
+    emit_verbose_balances(vault, accounts.vault_token_account.amount, now)?;
+
+    if vault.logs_enabled() {
+        msg!("Deposited {} tokens, minted {} shares", received, shares);
+    }
+
+    invoke_hook(
+        vault,
+        remaining_accounts,
+        b"global:on_deposit",
+        accounts.user.key(),
+        received,
+        shares,
+    )?;
+    Ok(shares)
+}
+
+/// Deposit checks. Brings accruals up to date and sizes `amount` against the
+/// cap, filling only up to it when `fill_to_cap` or partial deposits are on;
+/// returns `(amount, cap_room, is_bootstrap)`.
+fn validate_deposit(
+    accounts: &mut Deposit,
+    amount: u64,
+    now: i64,
+    proof: Option<&[[u8; 32]]>,
+    fill_to_cap: bool,
+) -> Result<(u64, u64, bool)> {
+    check_vault_token_account(&accounts.vault, &accounts.vault_token_account)?;
+    check_oracle_deviation(&accounts.vault, accounts.oracle.as_ref())?;
+    clear_expired_pause(&mut accounts.vault, now);
+    end_expired_bootstrap(&mut accounts.vault, now);
+    check_deposit(
+        &mut accounts.vault,
+        &accounts.user.key(),
+        accounts.user_shares.last_deposit_at,
+        proof,
+        amount,
+        now,
+        fill_to_cap,
+    )
+}
+
+/// The account-independent part of `validate_deposit`, shared with
+/// `dry_run_deposit` so the two cannot drift apart
+fn check_deposit(
+    vault: &mut Vault,
+    user: &Pubkey,
+    last_deposit_at: i64,
+    proof: Option<&[[u8; 32]]>,
+    amount: u64,
+    now: i64,
+    fill_to_cap: bool,
+) -> Result<(u64, u64, bool)> {
+    require!(amount > 0, VaultError::ZeroAmount);
+    vault.require_op_enabled(OP_DEPOSIT)?;
+    check_whitelist(vault, user, proof)?;
+
+    require!(!vault.is_shutdown, VaultError::VaultIsShutdown);
+    require!(
+        vault.shutdown_scheduled_at == 0,
+        VaultError::ShutdownScheduled
+    );
+    require!(!vault.deposits_paused(now), VaultError::VaultPaused);
+    require!(!vault.pps_stale(now), VaultError::StalePps);
+
+    // Enforce minimum interval between a user's deposits
+    let elapsed = now
+        .checked_sub(last_deposit_at)
+        .ok_or(VaultError::Underflow)?;
+    require!(
+        elapsed >= vault.deposit_cooldown,
+        VaultError::DepositCooldownActive
+    );
+
+    let is_bootstrap = vault.total_shares == 0;
+
+    // Bring the rate up to date before sizing or pricing the deposit
+    vault.accrue_interest(now)?;
+    vault.accrue_management_fee(now)?;
+
+    // Near the cap, take only what fits if partial fills are allowed
+    let cap_room = vault.cap_room(now);
+    let amount = if amount > cap_room && (vault.allow_partial_deposit || fill_to_cap) {
+        cap_room
+    } else {
+        amount
+    };
+    require!(
+        amount > 0 && amount <= cap_room,
+        VaultError::GlobalCapExceeded
+    );
+    if let Some(throttle) = vault.deposit_throttle(now) {
+        require!(amount <= throttle, VaultError::ThrottleExceeded);
+    }
+    Ok((amount, cap_room, is_bootstrap))
+}
+
+/// Pull `amount` from the depositor. Returns what actually arrived, in case
+/// the mint takes a transfer fee.
+fn transfer_deposit_in(accounts: &mut Deposit, amount: u64) -> Result<u64> {
+    let effects = state_fingerprint(&accounts.vault);
+    let balance_before = accounts.vault_token_account.amount;
+    let cpi_accounts = Transfer {
+        from: accounts.user_token_account.to_account_info(),
+        to: accounts.vault_token_account.to_account_info(),
+        authority: accounts.user.to_account_info(),
+    };
+    let cpi_program = accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    token::transfer(cpi_ctx, amount)?;
+    debug_assert_unchanged(&effects, &accounts.vault);
+
+    accounts.vault_token_account.reload()?;
+    let received = accounts
+        .vault_token_account
+        .amount
+        .checked_sub(balance_before)
+        .ok_or(VaultError::Underflow)?;
+    require!(received > 0, VaultError::ZeroAmount);
+    Ok(received)
+}
+
+/// Pull the full `requested` amount from the depositor and send `refund` of
+/// it straight back. Returns what stays in the vault.
+fn transfer_in_and_refund(
+    accounts: &mut Deposit,
+    requested: u64,
+    refund: u64,
+    now: i64,
+) -> Result<u64> {
+    let received = transfer_deposit_in(accounts, requested)?;
+    let effects = state_fingerprint(&accounts.vault);
+    transfer_from_vault(
+        &accounts.vault,
+        &accounts.vault_token_account,
+        accounts.user_token_account.to_account_info(),
+        &accounts.token_program,
+        refund,
+    )?;
+    accounts.vault_token_account.reload()?;
+    debug_assert_unchanged(&effects, &accounts.vault);
+
+    emit!(Refunded {
+        user: accounts.user.key(),
+        amount: refund,
+        timestamp: now,
+    });
+    received
+        .checked_sub(refund)
+        .ok_or(VaultError::Underflow.into())
+}
+
+/// Deposit effects: mint shares for `received` plus any sponsor match and
+/// credit them to the depositor. Returns `(shares, bonus_shares, matched)`.
+fn apply_deposit(
+    accounts: &mut Deposit,
+    received: u64,
+    cap_room: u64,
+    is_bootstrap: bool,
+    now: i64,
+) -> Result<(u64, u64, u64)> {
+    let vault = &mut accounts.vault;
+    let slot = Clock::get()?.slot;
+    accounts
+        .user_shares
+        .record_action(true, slot, vault.block_same_slot)?;
+    let (shares, bonus_shares, matched) = vault.price_deposit(received, cap_room, now, slot)?;
+    let backing = received
+        .checked_add(matched)
+        .ok_or(VaultError::MathOverflow)?;
+
+    // Update vault state
+    vault.total_assets = vault
+        .total_assets
+        .checked_add(backing)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.match_pool = vault
+        .match_pool
+        .checked_sub(matched)
+        .ok_or(VaultError::Underflow)?;
+    vault.total_shares = vault
+        .total_shares
+        .checked_add(shares)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.epoch_deposits = vault
+        .epoch_deposits
+        .checked_add(received)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.cumulative_deposits = vault
+        .cumulative_deposits
+        .checked_add(received as u128)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.record_daily_flow(received, 0, now)?;
+    check_solvency(vault, &accounts.vault_token_account)?;
+
+    // Update user shares
+    let user_shares = &mut accounts.user_shares;
+    settle_rewards(vault, user_shares, now)?;
+    user_shares.shares = user_shares
+        .shares
+        .checked_add(shares)
+        .ok_or(VaultError::MathOverflow)?;
+    user_shares.lifetime_deposited = user_shares
+        .lifetime_deposited
+        .checked_add(received)
+        .ok_or(VaultError::MathOverflow)?;
+    user_shares.vault = vault.key();
+    user_shares.last_deposit_at = now;
+    if user_shares.first_deposit_at == 0 {
+        user_shares.first_deposit_at = now;
+    }
+    vault.check_concentration(user_shares.shares, is_bootstrap)?;
+
+    // The depositor seeding an empty vault keeps capital at risk for a while
+    if is_bootstrap && vault.bootstrap_lock_seconds > 0 {
+        let unlock_at = now
+            .checked_add(vault.bootstrap_lock_seconds)
+            .ok_or(VaultError::MathOverflow)?;
+        user_shares.unlock_at = user_shares.unlock_at.max(unlock_at);
+    }
+    if vault.bootstrap_active(now) && vault.bootstrap_min_lock > 0 {
+        let unlock_at = now
+            .checked_add(vault.bootstrap_min_lock)
+            .ok_or(VaultError::MathOverflow)?;
+        user_shares.unlock_at = user_shares.unlock_at.max(unlock_at);
+    }
+    Ok((shares, bonus_shares, matched))
+}
+
+/// Shared withdraw path: burns `shares` from `user_shares`, pays the net
+/// assets to `destination` and calls the hook. `fee_discount_bps` comes off
+/// the exit fee. Returns the assets paid out.
+#[allow(clippy::too_many_arguments)]
+fn process_withdraw<'info>(
+    vault: &mut Account<'info, Vault>,
+    user_shares: &mut Account<'info, UserShares>,
+    vault_token_account: &mut Account<'info, TokenAccount>,
+    destination: AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    owner: Pubkey,
+    shares: u64,
+    fee_discount_bps: u16,
+    remaining_accounts: &[AccountInfo],
+) -> Result<u64> {
+    require!(shares > 0, VaultError::ZeroAmount);
+    vault.require_op_enabled(OP_WITHDRAW)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    end_expired_bootstrap(vault, now);
+    user_shares.record_action(false, Clock::get()?.slot, vault.block_same_slot)?;
+
+    let first_deposit_at = debit_user_shares(vault, user_shares, shares, now)?;
+    let (assets_out, fee, loss_penalty) = redeem_shares(
+        vault,
+        vault_token_account,
+        destination,
+        token_program,
+        shares,
+        Some(first_deposit_at),
+        None,
+        fee_discount_bps,
+        now,
+    )?;
+    vault.check_bootstrap_withdraw(assets_out, now)?;
+
+    emit!(WithdrawEvent {
+        user: owner,
+        assets: assets_out,
+        shares,
+        fee,
+        loss_penalty,
+        timestamp: now,
+    });
+    emit_verbose_balances(vault, vault_token_account.amount, now)?;
+
+    if vault.logs_enabled() {
+        msg!(
+            "Withdrew {} assets (fee {}), burned {} shares",
+            assets_out,
+            fee,
+            shares
+        );
+    }
+
+    invoke_hook(
+        vault,
+        remaining_accounts,
+        b"global:on_withdraw",
+        owner,
+        assets_out,
+        shares,
+    )?;
+    Ok(assets_out)
+}
+
+/// At `EVENT_LEVEL_VERBOSE`, follow a deposit or withdrawal event with the
+/// vault's balances after it
+fn emit_verbose_balances(vault: &Vault, idle: u64, now: i64) -> Result<()> {
+    if vault.event_level == EVENT_LEVEL_VERBOSE {
+        emit!(VaultBalances {
+            price_per_share: vault.price_per_share()?,
+            total_assets: vault.total_assets,
+            total_shares: vault.total_shares,
+            idle,
+            deployed_assets: vault.deployed_assets,
+            timestamp: now,
+        });
+    }
+    Ok(())
+}
+
+/// Send `amount` of idle assets to the strategy and count them as deployed
+fn deploy_to_strategy<'info>(
+    vault: &mut Account<'info, Vault>,
+    vault_token_account: &mut Account<'info, TokenAccount>,
+    strategy_token_account: AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    vault.deployed_assets = vault
+        .deployed_assets
+        .checked_add(amount)
+        .ok_or(VaultError::MathOverflow)?;
+    let effects = state_fingerprint(vault);
+    transfer_from_vault(
+        vault,
+        vault_token_account,
+        strategy_token_account,
+        token_program,
+        amount,
+    )?;
+    vault_token_account.reload()?;
+    check_solvency(vault, vault_token_account)?;
+    debug_assert_unchanged(&effects, vault);
+
+    emit!(AllocatedToStrategy {
+        amount,
+        deployed_assets: vault.deployed_assets,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    msg!("Allocated {} to strategy", amount);
+    Ok(())
+}
+
+/// Move `amount` of deployed assets from `source`, which `keeper` controls,
+/// back into the vault
+fn recall_from_strategy<'info>(
+    vault: &mut Account<'info, Vault>,
+    source: AccountInfo<'info>,
+    vault_token_account: AccountInfo<'info>,
+    keeper: AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    vault.deployed_assets = vault
+        .deployed_assets
+        .checked_sub(amount)
+        .ok_or(VaultError::Underflow)?;
+    vault.requested_pull = vault.requested_pull.saturating_sub(amount);
+
+    let cpi_accounts = Transfer {
+        from: source,
+        to: vault_token_account,
+        authority: keeper,
+    };
+    let cpi_ctx = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+    token::transfer(cpi_ctx, amount)?;
+
+    msg!(
+        "Returned {} from strategy, {} still requested",
+        amount,
+        vault.requested_pull
+    );
+    Ok(())
+}
+
+/// Withdraw that exceeds idle liquidity: pays out the share of `shares` idle
+/// covers and queues the remainder as an immediately claimable request,
+/// recording the shortfall for the keeper to pull from the strategy. Returns
+/// the assets paid out now.
+fn withdraw_with_pull(
+    accounts: &mut Withdraw,
+    shares: u64,
+    assets: u64,
+    idle: u64,
+    fee_discount_bps: u16,
+) -> Result<u64> {
+    require!(shares > 0, VaultError::ZeroAmount);
+    accounts.vault.require_op_enabled(OP_WITHDRAW)?;
+    let request = accounts
+        .withdrawal_request
+        .as_mut()
+        .ok_or(VaultError::WithdrawalRequestRequired)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let vault = &mut accounts.vault;
+    end_expired_bootstrap(vault, now);
+    accounts
+        .user_shares
+        .record_action(false, Clock::get()?.slot, vault.block_same_slot)?;
+    let first_deposit_at = debit_user_shares(vault, &mut accounts.user_shares, shares, now)?;
+
+    let shares_now = u64::try_from(
+        (shares as u128)
+            .checked_mul(idle as u128)
+            .ok_or(VaultError::MathOverflow)?
+            / assets as u128,
+    )
+    .map_err(|_| VaultError::MathOverflow)?;
+    let shares_queued = shares - shares_now;
+
+    let mut paid = 0;
+    if shares_now > 0 {
+        let (assets_out, fee, loss_penalty) = redeem_shares(
+            vault,
+            &mut accounts.vault_token_account,
+            accounts.user_token_account.to_account_info(),
+            &accounts.token_program,
+            shares_now,
+            Some(first_deposit_at),
+            None,
+            fee_discount_bps,
+            now,
+        )?;
+        vault.check_bootstrap_withdraw(assets_out, now)?;
+        emit!(WithdrawEvent {
+            user: accounts.user.key(),
+            assets: assets_out,
+            shares: shares_now,
+            fee,
+            loss_penalty,
+            timestamp: now,
+        });
+        emit_verbose_balances(vault, accounts.vault_token_account.amount, now)?;
+        paid = assets_out;
+    }
+
+    let shortfall = vault.convert_to_assets(shares_queued)?;
+    accounts
+        .user_shares
+        .open_request(vault.max_requests_per_user)?;
+    open_withdrawal_request(
+        vault,
+        request,
+        accounts.user.key(),
+        shares_queued,
+        first_deposit_at,
+        now,
+        now,
+    )?;
+    vault.requested_pull = vault
+        .requested_pull
+        .checked_add(shortfall)
+        .ok_or(VaultError::MathOverflow)?;
+
+    emit!(PullRequested {
+        user: accounts.user.key(),
+        request_id: request.id,
+        shortfall,
+        timestamp: now,
+    });
+
+    if vault.logs_enabled() {
+        msg!(
+            "Paid {} shares from idle, queued {} shares pending a pull of {}",
+            shares_now,
+            shares_queued,
+            shortfall
+        );
+    }
+    Ok(paid)
+}
+
+/// Fill in a new withdrawal request for `shares` already debited from
+/// `owner`, counting them against the pending cap
+fn open_withdrawal_request(
+    vault: &mut Vault,
+    request: &mut WithdrawalRequest,
+    owner: Pubkey,
+    shares: u64,
+    first_deposit_at: i64,
+    now: i64,
+    claimable_at: i64,
+) -> Result<()> {
+    vault.total_pending_withdrawals = vault
+        .total_pending_withdrawals
+        .checked_add(shares)
+        .ok_or(VaultError::MathOverflow)?;
+    require!(
+        vault.max_pending_withdrawals == 0
+            || vault.total_pending_withdrawals <= vault.max_pending_withdrawals,
+        VaultError::PendingQueueFull
+    );
+
+    request.owner = owner;
+    request.id = vault.next_withdrawal_request_id;
+    request.shares = shares;
+    request.first_deposit_at = first_deposit_at;
+    request.requested_at = now;
+    request.claimable_at = claimable_at;
+    request.locked_assets = if vault.lock_rate_at_request {
+        Some(vault.redeem_value(shares)?)
+    } else {
+        None
+    };
+
+    vault.next_withdrawal_request_id = vault
+        .next_withdrawal_request_id
+        .checked_add(1)
+        .ok_or(VaultError::MathOverflow)?;
+    Ok(())
+}
+
+/// Take `shares` out of a user's balance, enforcing locks and the dust rule.
+/// Returns the holding-period start the exit fee should be charged against.
+fn debit_user_shares(
+    vault: &mut Vault,
+    user_shares: &mut UserShares,
+    shares: u64,
+    now: i64,
+) -> Result<i64> {
+    require!(user_shares.shares >= shares, VaultError::InsufficientShares);
+    require!(now >= user_shares.unlock_at, VaultError::PositionLocked);
+
+    // Either keep a meaningful position or exit fully
+    let remaining_shares = user_shares.shares - shares;
+    require!(
+        remaining_shares == 0 || remaining_shares >= vault.min_position_shares,
+        VaultError::DustPositionRemaining
+    );
+
+    settle_rewards(vault, user_shares, now)?;
+    let first_deposit_at = user_shares.first_deposit_at;
+    user_shares.shares = remaining_shares;
+    if remaining_shares == 0 {
+        user_shares.first_deposit_at = 0;
+    }
+    Ok(first_deposit_at)
+}
+
+/// Burn `shares` already taken out of a user's balance and pay their value,
+/// less the exit fee and any loss-window penalty, to `destination`. A
+/// `first_deposit_at` of `None` exempts the redemption from both, and
+/// `locked_assets` replaces the current value of `shares`.
+/// `fee_discount_bps` comes off the exit fee. Returns
+/// `(assets_out, fee, loss_penalty)`.
+#[allow(clippy::too_many_arguments)]
+fn redeem_shares<'info>(
+    vault: &mut Account<'info, Vault>,
+    vault_token_account: &mut Account<'info, TokenAccount>,
+    destination: AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    shares: u64,
+    first_deposit_at: Option<i64>,
+    locked_assets: Option<u64>,
+    fee_discount_bps: u16,
+    now: i64,
+) -> Result<(u64, u64, u64)> {
+    check_vault_token_account(vault, vault_token_account)?;
+    vault.accrue_interest(now)?;
+    vault.accrue_management_fee(now)?;
+
+    let (assets, fee, loss_penalty) = validate_redeem(
+        vault,
+        shares,
+        first_deposit_at,
+        locked_assets,
+        fee_discount_bps,
+        now,
+    )?;
+    let assets_out = apply_redeem(vault, shares, assets, fee, loss_penalty)?;
+
+    // Interactions last: nothing below may write vault state
+    let effects = state_fingerprint(vault);
+    transfer_from_vault(
+        vault,
+        vault_token_account,
+        destination,
+        token_program,
+        assets_out,
+    )?;
+    vault_token_account.reload()?;
+    check_solvency(vault, vault_token_account)?;
+    debug_assert_unchanged(&effects, vault);
+
+    Ok((assets_out, fee, loss_penalty))
+}
+
+/// Redemption checks: the value of `shares` at the current rate (or
+/// `locked_assets` when the rate was fixed earlier), the exit fee and the
+/// loss-window penalty on it, both waived when `first_deposit_at` is `None`.
+/// The exit fee is reduced by `fee_discount_bps`. Returns
+/// `(assets, fee, loss_penalty)`.
+fn validate_redeem(
+    vault: &Vault,
+    shares: u64,
+    first_deposit_at: Option<i64>,
+    locked_assets: Option<u64>,
+    fee_discount_bps: u16,
+    now: i64,
+) -> Result<(u64, u64, u64)> {
+    vault.require_pps_in_bounds()?;
+    let assets = match locked_assets {
+        Some(assets) => assets,
+        None => vault.redeem_value(shares)?,
+    };
+
+    require!(assets <= vault.total_assets, VaultError::InsufficientAssets);
+
+    // Exit fee stays in the token account, parked in the penalty pool
+    let (fee, loss_penalty) = match first_deposit_at {
+        Some(first_deposit_at) => {
+            let fee = apply_fee_discount(
+                vault.exit_fee(assets, first_deposit_at, now)?,
+                fee_discount_bps,
+            )?;
+            let loss_penalty = vault.loss_penalty(assets, shares, now)?.min(assets - fee);
+            (fee, loss_penalty)
+        }
+        None => (0, 0),
+    };
+    Ok((assets, fee, loss_penalty))
+}
+
+/// Redemption effects: burn `shares` and take `assets` out of the books,
+/// parking `fee` in the penalty pool and leaving `loss_penalty` in
+/// `total_assets`. Returns the assets to pay out.
+fn apply_redeem(
+    vault: &mut Vault,
+    shares: u64,
+    assets: u64,
+    fee: u64,
+    loss_penalty: u64,
+) -> Result<u64> {
+    let assets_out = assets
+        .checked_sub(fee)
+        .and_then(|net| net.checked_sub(loss_penalty))
+        .ok_or(VaultError::Underflow)?;
+    vault.total_assets = vault
+        .total_assets
+        .checked_sub(assets - loss_penalty)
+        .ok_or(VaultError::Underflow)?;
+    vault.require_above_floor()?;
+    vault.penalty_pool = vault
+        .penalty_pool
+        .checked_add(fee)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.epoch_withdrawals = vault
+        .epoch_withdrawals
+        .checked_add(assets_out)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.cumulative_withdrawals = vault
+        .cumulative_withdrawals
+        .checked_add(assets_out as u128)
+        .ok_or(VaultError::MathOverflow)?;
+    vault.record_daily_flow(0, assets_out, Clock::get()?.unix_timestamp)?;
+    vault.total_shares = vault
+        .total_shares
+        .checked_sub(shares)
+        .ok_or(VaultError::Underflow)?;
+    Ok(assets_out)
+}
+
+/// Credit the rewards `user_shares` earned since its last settlement. Must run
+/// before every change to the user's share balance.
+fn settle_rewards(vault: &mut Vault, user_shares: &mut UserShares, now: i64) -> Result<()> {
+    // Every share balance change settles first, so checkpoint here too
+    user_shares.checkpoint(vault.snapshot_id);
+    vault.accrue_rewards(now)?;
+    user_shares.pending_rewards = user_shares.rewards_at(vault.reward_per_share_stored)?;
+    user_shares.reward_debt = vault.reward_per_share_stored;
+    Ok(())
+}
+
+/// Fee discount earned by the gov token account passed in, 0 without one
+fn gov_discount_bps(
+    vault: &Vault,
+    gov_token_account: Option<&Account<TokenAccount>>,
+) -> Result<u16> {
+    gov_token_account.map_or(Ok(0), |account| vault.fee_discount_bps(account.amount))
+}
+
+/// `fee` less `discount_bps` of it
+fn apply_fee_discount(fee: u64, discount_bps: u16) -> Result<u64> {
+    let discount = mul_div(fee, discount_bps as u64, BPS_DENOMINATOR, false)?;
+    fee.checked_sub(discount)
+        .ok_or(VaultError::Underflow.into())
+}
+
+/// `a * b / denominator`, rounded up when `round_up` is set
+fn mul_div(a: u64, b: u64, denominator: u64, round_up: bool) -> Result<u64> {
+    require!(denominator != 0, VaultError::DivideByZero);
+    // u64 * u64 always fits in u128
+    let product = a as u128 * b as u128;
+    let mut quotient = product / denominator as u128;
+    if round_up && quotient * denominator as u128 != product {
+        quotient += 1;
+    }
+    u64::try_from(quotient).map_err(|_| VaultError::MathOverflow.into())
+}
+
+/// Explicit backstop to the account constraints: `vault_token_account` must be
+/// the vault's registered token account and owned by the vault PDA
+fn check_vault_token_account(
+    vault: &Account<Vault>,
+    vault_token_account: &Account<TokenAccount>,
+) -> Result<()> {
+    require!(
+        vault_token_account.key() == vault.vault_token_account
+            && vault_token_account.owner == vault.key(),
+        VaultError::InvalidVaultTokenAccount
+    );
+    Ok(())
+}
+
+/// Fail unless deployed assets plus the token balance cover everything the
+/// vault owes (`total_assets`, the penalty pool and the match pool). Direct
+/// token transfers into the vault only create surplus, so they cannot trip
+/// this check. Callers must pass a freshly reloaded `vault_token_account`.
+fn check_solvency(vault: &Vault, vault_token_account: &TokenAccount) -> Result<()> {
+    let backing = (vault.deployed_assets as u128) + vault_token_account.amount as u128;
+    let liabilities = (vault.total_assets as u128) + vault.earmarked_balance() as u128;
+    require!(backing >= liabilities, VaultError::SolvencyCheckFailed);
+    Ok(())
+}
+
+/// Oracle price scaled to `PPS_SCALE`, failing with `StaleOracle` when it was
+/// published more than `max_staleness` seconds ago (0 = no limit)
+fn oracle_price(oracle: &AccountInfo, max_staleness: i64) -> Result<u64> {
+    let feed =
+        SolanaPriceAccount::account_info_to_feed(oracle).map_err(|_| VaultError::InvalidOracle)?;
+    let price = feed
+        .get_price_unchecked()
+        .scale_to_exponent(-9)
+        .ok_or(VaultError::InvalidOracle)?;
+    require!(price.price > 0, VaultError::InvalidOracle);
+    if max_staleness > 0 {
+        let age = Clock::get()?
+            .unix_timestamp
+            .saturating_sub(price.publish_time);
+        require!(age <= max_staleness, VaultError::StaleOracle);
+    }
+    Ok(price.price as u64)
+}
+
+/// When the vault has an oracle configured, fail if its price disagrees
+/// with the vault's PPS by more than `max_oracle_deviation_bps`
+fn check_oracle_deviation(vault: &Vault, oracle: Option<&UncheckedAccount>) -> Result<()> {
+    let Some(expected_oracle) = vault.oracle else {
+        return Ok(());
+    };
+    let oracle = oracle.ok_or(VaultError::InvalidOracle)?;
+    require_keys_eq!(oracle.key(), expected_oracle, VaultError::InvalidOracle);
+
+    let oracle_pps = oracle_price(oracle, vault.max_oracle_staleness)?;
+    let pps = vault.price_per_share()?;
+    // Outstanding shares backed by nothing price at zero
+    require!(pps > 0, VaultError::VaultInsolvent);
+    let deviation_bps = (oracle_pps.abs_diff(pps) as u128)
+        .checked_mul(BPS_DENOMINATOR as u128)
+        .ok_or(VaultError::MathOverflow)?
+        / pps as u128;
+    require!(
+        deviation_bps <= vault.max_oracle_deviation_bps as u128,
+        VaultError::OracleDeviationTooHigh
+    );
+    Ok(())
+}
+
+/// Whitelisted vaults only accept users proven to be in the tree
+fn check_whitelist(vault: &Vault, user: &Pubkey, proof: Option<&[[u8; 32]]>) -> Result<()> {
+    if vault.whitelist_enabled() {
+        let proof = proof.ok_or(VaultError::WhitelistProofRequired)?;
+        let leaf = hashv(&[user.as_ref()]).to_bytes();
+        require!(
+            verify_merkle_proof(proof, vault.whitelist_root, leaf),
+            VaultError::InvalidProof
+        );
+    }
+    Ok(())
+}
+
+/// Check `leaf` against `root` using keccak over sorted sibling pairs
+fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            hashv(&[&node, sibling]).to_bytes()
+        } else {
+            hashv(&[sibling, &node]).to_bytes()
+        }
+    });
+    computed == root
+}
+
+/// Call `preimage`'s instruction (`on_deposit` / `on_withdraw`) on the hook
+/// program with `(user, amount, shares)`, if one is registered.
+/// `remaining_accounts` holds the hook program followed by the accounts its
+/// instruction takes; a failing hook reverts the whole operation.
+fn invoke_hook(
+    vault: &Vault,
+    remaining_accounts: &[AccountInfo],
+    preimage: &[u8],
+    user: Pubkey,
+    amount: u64,
+    shares: u64,
+) -> Result<()> {
+    let hook_program = match vault.hook_program {
+        Some(hook_program) => hook_program,
+        None => return Ok(()),
+    };
+    let (program, hook_accounts) = remaining_accounts
+        .split_first()
+        .ok_or(VaultError::HookProgramMismatch)?;
+    require!(
+        program.key() == hook_program,
+        VaultError::HookProgramMismatch
+    );
+
+    let mut data = Vec::with_capacity(56);
+    data.extend_from_slice(&hash(preimage).to_bytes()[..8]);
+    data.extend_from_slice(user.as_ref());
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.extend_from_slice(&shares.to_le_bytes());
+    let hook_ix = Instruction {
+        program_id: hook_program,
+        accounts: hook_accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: account.key(),
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data,
+    };
+    invoke(&hook_ix, remaining_accounts)?;
+    Ok(())
+}
+
+/// Lazily clear a pause whose auto-unpause time has passed
+fn clear_expired_pause(vault: &mut Account<Vault>, now: i64) {
+    if vault.paused && !vault.deposits_paused(now) {
+        vault.paused = false;
+        vault.auto_unpause_at = 0;
+        emit!(AutoUnpaused {
+            vault: vault.key(),
+            timestamp: now,
+        });
+        msg!("Vault auto-unpaused");
+    }
+}
+
+/// Trip the circuit breaker when PPS fell more than `max_pps_drop_bps` below
+/// `last_pps`, pausing deposits until `recover_and_unpause`. Otherwise the
+/// current PPS becomes the reference.
+fn check_circuit_breaker(vault: &mut Account<Vault>, now: i64) -> Result<()> {
+    if vault.max_pps_drop_bps == 0 || vault.breaker_tripped {
+        return Ok(());
+    }
+    let pps = vault.price_per_share()?;
+    let floor = mul_div(
+        vault.last_pps,
+        BPS_DENOMINATOR - vault.max_pps_drop_bps as u64,
+        BPS_DENOMINATOR,
+        true,
+    )?;
+    if pps >= floor {
+        vault.last_pps = pps;
+        return Ok(());
+    }
+    vault.breaker_tripped = true;
+    vault.paused = true;
+    vault.auto_unpause_at = 0;
+    emit!(CircuitBreakerTripped {
+        vault: vault.key(),
+        last_pps: vault.last_pps,
+        price_per_share: pps,
+        timestamp: now,
+    });
+    msg!("Circuit breaker tripped at PPS {}", pps);
+    Ok(())
+}
+
+/// Lazily end a bootstrap period whose deadline has passed
+fn end_expired_bootstrap(vault: &mut Account<Vault>, now: i64) {
+    if vault.bootstrap_until != 0 && !vault.bootstrap_active(now) {
+        vault.bootstrap_until = 0;
+        emit!(BootstrapEnded {
+            vault: vault.key(),
+            timestamp: now,
+        });
+        msg!("Bootstrap period ended");
+    }
+}
+
+/// Share balance stored in a `UserShares` account, zero if not yet created
+fn read_user_shares(account: &AccountInfo) -> Result<u64> {
+    let data = account.try_borrow_data()?;
+    if data.is_empty() {
+        return Ok(0);
+    }
+    Ok(UserShares::try_deserialize(&mut &data[..])?.shares)
+}
+
+/// Annualized PPS growth from `from` to `to` in bps, negatives clamped to zero
+fn annualized_bps(from: &PpsCheckpoint, to: &PpsCheckpoint) -> Result<u32> {
+    let elapsed = to.timestamp.saturating_sub(from.timestamp);
+    if elapsed <= 0 || from.pps == 0 || to.pps <= from.pps {
+        return Ok(0);
+    }
+    let apy = ((to.pps - from.pps) as u128)
+        .checked_mul(BPS_DENOMINATOR as u128)
+        .ok_or(VaultError::MathOverflow)?
+        .checked_mul(SECONDS_PER_YEAR as u128)
+        .ok_or(VaultError::MathOverflow)?
+        / (from.pps as u128 * elapsed as u128);
+    Ok(u32::try_from(apy).unwrap_or(u32::MAX))
+}
+
+/// Serialized vault state in debug builds, taken before a transfer CPI so
+/// `debug_assert_unchanged` can check nothing is written after it
+fn state_fingerprint(vault: &Vault) -> Option<Vec<u8>> {
+    if !cfg!(debug_assertions) {
+        return None;
+    }
+    vault.try_to_vec().ok()
+}
+
+/// Debug-build check that `vault` still matches `before`
+fn debug_assert_unchanged(before: &Option<Vec<u8>>, vault: &Vault) {
+    debug_assert!(
+        *before == state_fingerprint(vault),
+        "vault state written after a transfer CPI"
+    );
+}
+
+/// Transfer `amount` out of the vault token account, signed by the vault PDA
+fn transfer_from_vault<'info>(
+    vault: &Account<'info, Vault>,
+    vault_token_account: &Account<'info, TokenAccount>,
+    to: AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let seeds = &[b"vault", vault.token_mint.as_ref(), &[vault.bump]];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: vault_token_account.to_account_info(),
+        to,
+        authority: vault.to_account_info(),
+    };
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)
+}
+
+// ============ Accounts ============
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Vault::INIT_SPACE,
+        seeds = [b"vault", token_mint.key().as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        constraint = vault_token_account.mint == token_mint.key(),
+        constraint = vault_token_account.owner == vault.key(),
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserShares::INIT_SPACE,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.mint == vault.token_mint,
+        constraint = user_token_account.owner == user.key(),
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: must match `vault.oracle`; verified in `check_oracle_deviation`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// Required while `vault.auto_allocate_bps` is set
+    #[account(
+        mut,
+        constraint = strategy_token_account.key() == vault.strategy_token_account,
+    )]
+    pub strategy_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == user.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SwapAndDeposit<'info> {
+    pub deposit: Deposit<'info>,
+
+    /// Token swapped from; the DEX debits it under the user's signature
+    #[account(
+        mut,
+        constraint = source_token_account.owner == deposit.user.key(),
+        constraint = source_token_account.mint != deposit.vault.token_mint @ VaultError::MintMismatch,
+    )]
+    pub source_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: must be the vault's registered DEX program
+    #[account(
+        executable,
+        constraint = deposit.vault.dex_program == Some(dex_program.key()) @ VaultError::DexProgramMismatch
+    )]
+    pub dex_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(shares: u64, new_owner: Pubkey)]
+pub struct SplitPosition<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub source_shares: Account<'info, UserShares>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserShares::INIT_SPACE,
+        seeds = [b"user_shares", vault.key().as_ref(), new_owner.as_ref()],
+        bump
+    )]
+    pub new_shares: Account<'info, UserShares>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EnableReceipts<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"receipt_mint", vault.key().as_ref()],
+        bump,
+        mint::decimals = vault.share_decimals,
+        mint::authority = vault,
+    )]
+    pub receipt_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MintReceipt<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(mut, address = vault.receipt_mint @ VaultError::ReceiptsDisabled)]
+    pub receipt_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_receipt_account.mint == receipt_mint.key() @ VaultError::MintMismatch,
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+
+    pub user: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemReceipt<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserShares::INIT_SPACE,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(mut, address = vault.receipt_mint @ VaultError::ReceiptsDisabled)]
+    pub receipt_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_receipt_account.mint == receipt_mint.key() @ VaultError::MintMismatch,
+        constraint = user_receipt_account.owner == user.key(),
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.mint == vault.token_mint,
+        constraint = user_token_account.owner == user.key(),
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: must match `vault.oracle`; verified in `check_oracle_deviation`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// Receives the unpaid remainder when auto-pull kicks in
+    #[account(
+        init,
+        payer = user,
+        space = 8 + WithdrawalRequest::INIT_SPACE,
+        seeds = [
+            b"withdrawal_request",
+            vault.key().as_ref(),
+            vault.next_withdrawal_request_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub withdrawal_request: Option<Account<'info, WithdrawalRequest>>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == user.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTo<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(mut, constraint = destination.mint == vault.token_mint @ VaultError::MintMismatch)]
+    pub destination: Account<'info, TokenAccount>,
+
+    /// Required while `vault.require_approved_destination` is set, unless the
+    /// user owns `destination`
+    #[account(
+        seeds = [b"approved_destination", vault.key().as_ref(), destination.key().as_ref()],
+        bump
+    )]
+    pub approved_destination: Option<Account<'info, ApprovedDestination>>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub user: Signer<'info>,
+
+    /// CHECK: must match `vault.oracle`; verified in `check_oracle_deviation`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == user.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawToAta<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(address = vault.token_mint @ VaultError::MintMismatch)]
+    pub token_mint: Account<'info, Mint>,
+
+    /// CHECK: any wallet; only used to derive its associated token account
+    pub recipient: UncheckedAccount<'info>,
+
+    /// Created only when missing; an existing account must be the canonical
+    /// ATA for `token_mint` owned by `recipient`
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = token_mint,
+        associated_token::authority = recipient
+    )]
+    pub recipient_token_account: Account<'info, TokenAccount>,
+
+    /// Required while `vault.require_approved_destination` is set, unless the
+    /// user is the recipient
+    #[account(
+        seeds = [
+            b"approved_destination",
+            vault.key().as_ref(),
+            recipient_token_account.key().as_ref()
+        ],
+        bump
+    )]
+    pub approved_destination: Option<Account<'info, ApprovedDestination>>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: must match `vault.oracle`; verified in `check_oracle_deviation`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == user.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveDestination<'info> {
+    #[account(
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(constraint = destination.mint == vault.token_mint @ VaultError::MintMismatch)]
+    pub destination: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ApprovedDestination::INIT_SPACE,
+        seeds = [b"approved_destination", vault.key().as_ref(), destination.key().as_ref()],
+        bump
+    )]
+    pub approved_destination: Account<'info, ApprovedDestination>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeDestination<'info> {
+    #[account(
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"approved_destination",
+            vault.key().as_ref(),
+            approved_destination.destination.as_ref()
+        ],
+        bump,
+        close = authority
+    )]
+    pub approved_destination: Account<'info, ApprovedDestination>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(shares: u64, recipient: Pubkey)]
+pub struct WithdrawSol<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(address = vault.token_mint)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    /// Temporary wSOL account, closed within the instruction
+    #[account(
+        init,
+        payer = user,
+        token::mint = token_mint,
+        token::authority = vault,
+        seeds = [b"unwrap", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub unwrap_account: Account<'info, TokenAccount>,
+
+    /// CHECK: only receives lamports
+    #[account(mut, address = recipient)]
+    pub recipient_wallet: UncheckedAccount<'info>,
+
+    /// Required while `vault.require_approved_destination` is set, unless the
+    /// user is the recipient; kept for the recipient's wSOL associated token account
+    #[account(
+        seeds = [
+            b"approved_destination",
+            vault.key().as_ref(),
+            get_associated_token_address(&recipient, &vault.token_mint).as_ref()
+        ],
+        bump
+    )]
+    pub approved_destination: Option<Account<'info, ApprovedDestination>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: must match `vault.oracle`; verified in `check_oracle_deviation`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == user.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveWithdrawDelegate<'info> {
+    #[account(seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + WithdrawDelegate::INIT_SPACE,
+        seeds = [b"withdraw_delegate", vault.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub withdraw_delegate: Account<'info, WithdrawDelegate>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawDelegated<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: only used to derive the owner's PDAs and check their token account
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"withdraw_delegate", vault.key().as_ref(), owner.key().as_ref()],
+        bump,
+        constraint = withdraw_delegate.delegate == delegate.key() @ VaultError::NotDelegate
+    )]
+    pub withdraw_delegate: Account<'info, WithdrawDelegate>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == vault.token_mint,
+        constraint = owner_token_account.owner == owner.key(),
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub delegate: Signer<'info>,
+
+    /// CHECK: must match `vault.oracle`; verified in `check_oracle_deviation`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == owner.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RequestWithdrawal<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + WithdrawalRequest::INIT_SPACE,
+        seeds = [
+            b"withdrawal_request",
+            vault.key().as_ref(),
+            vault.next_withdrawal_request_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StartMigration<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// One migration per vault: the bitmap can only be created once
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ImportBitmap::INIT_SPACE,
+        seeds = [b"import_bitmap", vault.key().as_ref()],
+        bump
+    )]
+    pub import_bitmap: Account<'info, ImportBitmap>,
+
+    #[account(
+        mut,
+        constraint = authority_token_account.mint == vault.token_mint,
+        constraint = authority_token_account.owner == authority.key(),
+    )]
+    pub authority_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct ImportPosition<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.migration_mode @ VaultError::MigrationNotActive,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"import_bitmap", vault.key().as_ref()],
+        bump
+    )]
+    pub import_bitmap: Account<'info, ImportBitmap>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + UserShares::INIT_SPACE,
+        seeds = [b"user_shares", vault.key().as_ref(), user.as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StartVestedWithdrawal<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + VestedExit::INIT_SPACE,
+        seeds = [b"vested_exit", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub vested_exit: Account<'info, VestedExit>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == user.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimVested<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"vested_exit", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub vested_exit: Account<'info, VestedExit>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.mint == vault.token_mint,
+        constraint = user_token_account.owner == user.key(),
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub user: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SkipClosedRequest<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: only checked to be empty at the head request's address
+    #[account(
+        seeds = [
+            b"withdrawal_request",
+            vault.key().as_ref(),
+            vault.next_claimable_seq.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub head_request: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWithdrawal<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"withdrawal_request",
+            vault.key().as_ref(),
+            withdrawal_request.id.to_le_bytes().as_ref()
+        ],
+        bump,
+        constraint = withdrawal_request.owner == user.key() @ VaultError::NotRequestOwner,
+        close = user
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.mint == vault.token_mint,
+        constraint = user_token_account.owner == user.key(),
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == user.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelWithdrawal<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"withdrawal_request",
+            vault.key().as_ref(),
+            withdrawal_request.id.to_le_bytes().as_ref()
+        ],
+        bump,
+        constraint = withdrawal_request.owner == user.key() @ VaultError::NotRequestOwner,
+        close = user
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AccrueInterest<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+}
+
+#[derive(Accounts)]
+pub struct Settle<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFeeRecipient<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(constraint = fee_recipient.mint == vault.token_mint @ VaultError::MintMismatch)]
+    pub fee_recipient: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RealizeFeeShares<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = fee_recipient.key() == vault.fee_recipient @ VaultError::Unauthorized,
+        constraint = fee_recipient.owner == recipient.key() @ VaultError::Unauthorized,
+    )]
+    pub fee_recipient: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    /// Owner of the fee recipient token account
+    pub recipient: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CompoundFeeShares<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// Fee shares reinvested for the protocol, held apart from any user
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + UserShares::INIT_SPACE,
+        seeds = [b"protocol_position", vault.key().as_ref()],
+        bump
+    )]
+    pub protocol_position: Account<'info, UserShares>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawProtocolFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, seeds = [b"protocol_position", vault.key().as_ref()], bump)]
+    pub protocol_position: Account<'info, UserShares>,
+
+    #[account(mut, constraint = destination.mint == vault.token_mint @ VaultError::MintMismatch)]
+    pub destination: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardToken<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub reward_mint: Account<'info, Mint>,
+
+    /// Held apart from the vault token account, whose balance backs deposits
+    #[account(
+        constraint = reward_token_account.mint == reward_mint.key() @ VaultError::MintMismatch,
+        constraint = reward_token_account.owner == vault.key(),
+        constraint = reward_token_account.key() != vault.vault_token_account @ VaultError::InvalidParameter,
+    )]
+    pub reward_token_account: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardSchedule<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == vault.reward_mint @ VaultError::MintMismatch,
+        constraint = funder_token_account.owner == authority.key(),
+    )]
+    pub funder_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = reward_token_account.key() == vault.reward_token_account,
+    )]
+    pub reward_token_account: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAndCompound<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        constraint = reward_token_account.key() == vault.reward_token_account,
+    )]
+    pub reward_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    /// Needed only when rewards are paid in a token other than the underlying
+    #[account(
+        mut,
+        constraint = user_reward_token_account.mint == vault.reward_mint @ VaultError::MintMismatch,
+        constraint = user_reward_token_account.owner == user.key(),
+    )]
+    pub user_reward_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct ClaimRewardFor<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.keeper == keeper.key() @ VaultError::NotKeeper
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", vault.key().as_ref(), user.as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        constraint = reward_token_account.key() == vault.reward_token_account,
+    )]
+    pub reward_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_reward_token_account.mint == vault.reward_mint @ VaultError::MintMismatch,
+        constraint = user_reward_token_account.owner == user,
+    )]
+    pub user_reward_token_account: Account<'info, TokenAccount>,
+
+    pub keeper: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SweepDust<'info> {
+    #[account(
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    /// The fee recipient account
+    #[account(
+        mut,
+        constraint = treasury.key() == vault.fee_recipient @ VaultError::InvalidParameter,
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundMatchPool<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = sponsor_token_account.mint == vault.token_mint,
+        constraint = sponsor_token_account.owner == sponsor.key(),
+    )]
+    pub sponsor_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub sponsor: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundInsurance<'info> {
+    #[account(mut, seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == vault.token_mint,
+        constraint = funder_token_account.owner == funder.key(),
+    )]
+    pub funder_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub funder: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct GrantShares<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + UserShares::INIT_SPACE,
+        seeds = [b"user_shares", vault.key().as_ref(), user.as_ref()],
+        bump
+    )]
+    pub user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        constraint = authority_token_account.mint == vault.token_mint,
+        constraint = authority_token_account.owner == authority.key(),
+    )]
+    pub authority_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePosition<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", source_vault.token_mint.as_ref()],
+        bump = source_vault.bump
+    )]
+    pub source_vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"user_shares", source_vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub source_user_shares: Account<'info, UserShares>,
+
+    #[account(
+        mut,
+        constraint = source_vault_token_account.key() == source_vault.vault_token_account,
+    )]
+    pub source_vault_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: vault owned by the destination program; its mint is checked
+    /// against the source vault and the rest is validated by its own deposit
+    #[account(mut, owner = destination_program.key())]
+    pub destination_vault: UncheckedAccount<'info>,
+
+    /// CHECK: initialized and validated by the destination program's deposit
+    #[account(mut)]
+    pub destination_user_shares: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = destination_vault_token_account.mint == source_vault.token_mint,
+    )]
+    pub destination_vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.mint == source_vault.token_mint,
+        constraint = user_token_account.owner == user.key(),
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: must match `source_vault.oracle`; verified in `check_oracle_deviation`
+    pub source_oracle: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: must be the deployment registered on the source vault
+    #[account(
+        executable,
+        constraint = source_vault.migration_program == Some(destination_program.key()) @ VaultError::MigrationProgramMismatch
+    )]
+    pub destination_program: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AdminAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RollEpoch<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.keeper == keeper.key() @ VaultError::NotKeeper
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = keeper,
+        space = 8 + EpochRecord::INIT_SPACE,
+        seeds = [
+            b"epoch_record",
+            vault.key().as_ref(),
+            vault.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_record: Account<'info, EpochRecord>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetStrategy<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(constraint = strategy_token_account.mint == vault.token_mint @ VaultError::MintMismatch)]
+    pub strategy_token_account: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AllocateToStrategy<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.keeper == keeper.key() @ VaultError::NotKeeper
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = strategy_token_account.key() == vault.strategy_token_account,
+    )]
+    pub strategy_token_account: Account<'info, TokenAccount>,
+
+    pub keeper: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateNav<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.keeper == keeper.key() @ VaultError::NotKeeper
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub keeper: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Rebalance<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.keeper == keeper.key() @ VaultError::NotKeeper
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    /// Recalls are signed by the keeper, so it must own this account
+    #[account(
+        mut,
+        constraint = strategy_token_account.key() == vault.strategy_token_account,
+        constraint = strategy_token_account.owner == keeper.key() @ VaultError::Unauthorized,
+    )]
+    pub strategy_token_account: Account<'info, TokenAccount>,
+
+    pub keeper: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReturnFromStrategy<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.keeper == keeper.key() @ VaultError::NotKeeper
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = source_token_account.mint == vault.token_mint,
+        constraint = source_token_account.owner == keeper.key(),
+    )]
+    pub source_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.vault_token_account,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub keeper: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PauseAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == operator.key()
+            || vault.risk_operator == operator.key() @ VaultError::NotRiskOperator
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// The authority or the risk operator
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptKeeper<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.pending_keeper == new_keeper.key() @ VaultError::NotPendingKeeper
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub new_keeper: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.pending_authority == new_authority.key() @ VaultError::NotPendingAuthority,
+        constraint = vault.guardian != new_authority.key() @ VaultError::NotPendingAuthority
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAuthorityTransfer<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.guardian != Pubkey::default()
+            && vault.guardian == guardian.key() @ VaultError::NotGuardian
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub guardian: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitPpsHistory<'info> {
+    #[account(
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PpsHistory::INIT_SPACE,
+        seeds = [b"pps_history", vault.key().as_ref()],
+        bump
+    )]
+    pub pps_history: Account<'info, PpsHistory>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordPps<'info> {
+    #[account(
+        seeds = [b"vault", vault.token_mint.as_ref()],
+        bump = vault.bump,
+        constraint = vault.authority == authority.key() @ VaultError::Unauthorized
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, seeds = [b"pps_history", vault.key().as_ref()], bump)]
+    pub pps_history: Account<'info, PpsHistory>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ViewPpsHistory<'info> {
+    #[account(seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(seeds = [b"pps_history", vault.key().as_ref()], bump)]
+    pub pps_history: Account<'info, PpsHistory>,
+}
+
+#[derive(Accounts)]
+pub struct ViewPosition<'info> {
+    #[account(seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: only used to derive the user's position PDA
+    pub user: UncheckedAccount<'info>,
+
+    /// Absent for users who never deposited
+    #[account(
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Option<Account<'info, UserShares>>,
+}
+
+#[derive(Accounts)]
+pub struct DryRunDeposit<'info> {
+    #[account(seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: only used to derive the user's position PDA and whitelist leaf
+    pub user: UncheckedAccount<'info>,
+
+    /// Absent for users who never deposited
+    #[account(
+        seeds = [b"user_shares", vault.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_shares: Option<Account<'info, UserShares>>,
+
+    /// CHECK: must match `vault.oracle`; verified in `check_oracle_deviation`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// The user's `vault.gov_mint` account, for the fee discount
+    #[account(
+        constraint = gov_token_account.mint == vault.gov_mint @ VaultError::MintMismatch,
+        constraint = gov_token_account.owner == user.key(),
+    )]
+    pub gov_token_account: Option<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct ViewWithdrawalRequest<'info> {
+    #[account(seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        seeds = [
+            b"withdrawal_request",
+            vault.key().as_ref(),
+            withdrawal_request.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+}
+
+#[derive(Accounts)]
+pub struct ViewTvl<'info> {
+    #[account(seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: must match `vault.quote_oracle`; verified in `tvl_in_quote`
+    pub quote_oracle: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ViewSolvency<'info> {
+    #[account(seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(constraint = vault_token_account.key() == vault.vault_token_account)]
+    pub vault_token_account: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct DeriveAddresses {}
+
+#[derive(Accounts)]
+pub struct ViewVault<'info> {
+    #[account(seeds = [b"vault", vault.token_mint.as_ref()], bump = vault.bump)]
+    pub vault: Account<'info, Vault>,
+}
+
+// ============ State ============
+
+#[account]
+#[derive(InitSpace)]
+pub struct Vault {
+    pub authority: Pubkey,
+    /// Operator key for automated maintenance
+    pub keeper: Pubkey,
+    /// Keeper nominated via `propose_keeper`, awaiting `accept_keeper`
+    pub pending_keeper: Pubkey,
+    pub token_mint: Pubkey,
+    pub vault_token_account: Pubkey,
+    pub total_assets: u64,
+    pub total_shares: u64,
+    /// Portion of `total_assets` deployed outside the vault token account
+    pub deployed_assets: u64,
+    /// Exit fees held in the vault token account but not yet in `total_assets`
+    pub penalty_pool: u64,
+    pub paused: bool,
+    pub bump: u8,
+    /// Time at which a pause lifts on its own (0 = manual unpause only)
+    pub auto_unpause_at: i64,
+    /// Bitmask of `OP_*` instructions currently disabled
+    pub paused_ops: u16,
+    /// Minimum seconds between consecutive deposits by the same user (0 = disabled)
+    pub deposit_cooldown: i64,
+    /// Shortest lock accepted by `deposit_and_lock`
+    pub min_lock: i64,
+    /// Longest lock accepted by `deposit_and_lock` (0 = locking disabled)
+    pub max_lock: i64,
+    /// Bonus shares granted per full day locked, in bps of minted shares
+    pub lock_bonus_bps_per_day: u16,
+    /// Exit fee charged on a withdrawal made right after the first deposit
+    pub max_exit_fee_bps: u16,
+    /// Holding time over which the exit fee decays to zero (0 = no exit fee)
+    pub exit_fee_decay_seconds: i64,
+    /// Lock applied to whoever deposits into an empty vault (0 = disabled).
+    /// Makes a share-inflation attack costly since the attacker's seed capital
+    /// stays at risk, at the price of also locking an honest first depositor.
+    pub bootstrap_lock_seconds: i64,
+    /// Deposits before this time mint `bonus_bps` extra shares
+    pub bonus_end_time: i64,
+    /// Extra shares for early deposits, in bps of the shares minted. Bonus
+    /// shares are unbacked, so they dilute every later entrant; that dilution
+    /// is the incentive paid to early participants.
+    pub bonus_bps: u16,
+    /// Smallest nonzero balance a partial withdrawal may leave (0 = no minimum)
+    pub min_position_shares: u64,
+    /// Price feed cross-checked against PPS on deposits and withdrawals
+    pub oracle: Option<Pubkey>,
+    /// Largest tolerated gap between oracle price and PPS
+    pub max_oracle_deviation_bps: u16,
+    pub current_epoch: u64,
+    pub epoch_started_at: i64,
+    /// Assets deposited since the current epoch started
+    pub epoch_deposits: u64,
+    /// Assets paid out since the current epoch started
+    pub epoch_withdrawals: u64,
+    /// Merkle root of keccak(depositor) leaves (all zeros = open to everyone)
+    pub whitelist_root: [u8; 32],
+    /// Seconds between `request_withdrawal` and `claim_withdrawal`
+    pub withdrawal_cooldown: i64,
+    /// Id assigned to the next withdrawal request
+    pub next_withdrawal_request_id: u64,
+    /// Shares sitting in unclaimed withdrawal requests
+    pub total_pending_withdrawals: u64,
+    /// Cap on `total_pending_withdrawals` (0 = unlimited)
+    pub max_pending_withdrawals: u64,
+    /// Rewards emitted per second, split across shares pro rata
+    pub reward_rate: u64,
+    /// Cumulative rewards per share, scaled by `REWARD_PRECISION`
+    pub reward_per_share_stored: u128,
+    pub rewards_updated_at: i64,
+    /// Token account holding deployed assets (default = no strategy set)
+    pub strategy_token_account: Pubkey,
+    /// Queue the unpaid part of a withdrawal beyond idle instead of failing
+    pub auto_pull_enabled: bool,
+    /// Assets queued withdrawals are waiting on from the strategy
+    pub requested_pull: u64,
+    /// `ROUNDING_*` mode for share and asset conversions, fixed at init
+    pub rounding: u8,
+    pub reward_mint: Pubkey,
+    /// Vault-owned account rewards are paid from
+    pub reward_token_account: Pubkey,
+    /// Assets withdrawals must leave behind until shutdown (0 = no floor)
+    pub min_vault_assets: u64,
+    /// Set once by `shutdown`: deposits stop for good and the floor no longer applies
+    pub is_shutdown: bool,
+    /// Lowest PPS deposits and withdrawals accept (0 = no floor)
+    pub min_pps: u64,
+    /// Highest PPS deposits and withdrawals accept (0 = no ceiling)
+    pub max_pps: u64,
+    /// Token account protocol fees are paid to
+    pub fee_recipient: Pubkey,
+    /// Share of each deposit sent straight to the strategy, in bps
+    pub auto_allocate_bps: u16,
+    /// Share of `total_assets` kept idle when allocating, in bps
+    pub reserve_bps: u16,
+    /// Set once by `lock_params`; exit terms can then only get better
+    pub params_locked: bool,
+    /// Most shares deposits may mint in a single slot (0 = no cap)
+    pub max_shares_per_slot: u64,
+    /// Shares minted so far in `last_mint_slot`
+    pub slot_shares_minted: u64,
+    pub last_mint_slot: u64,
+    /// May pause and unpause only; `Pubkey::default()` when unset
+    pub risk_operator: Pubkey,
+    /// Annual rate compounded into `supply_index`, in bps
+    pub interest_rate_bps: u16,
+    /// Growth of one unit of assets since initialization, scaled by `INDEX_SCALE`
+    pub supply_index: u128,
+    pub interest_updated_at: i64,
+    /// Limit `withdraw_to` to the user's own accounts and approved destinations
+    pub require_approved_destination: bool,
+    /// Sponsor-funded assets held back to match deposits
+    pub match_pool: u64,
+    /// Tokens held against strategy losses, outside `total_assets`
+    pub insurance_fund: u64,
+    /// SPL mint for receipt tokens (default = receipts disabled)
+    pub receipt_mint: Pubkey,
+    /// Shares held as receipt tokens, always the receipt mint's supply.
+    /// They count in `total_shares` but in no `UserShares`, and earn no
+    /// rewards.
+    pub receipt_shares: u64,
+    pub created_at: i64,
+    /// Per-deposit limit of `base + per_day * days since created_at`
+    /// (both 0 = no throttle)
+    pub deposit_throttle_base: u64,
+    pub deposit_throttle_per_day: u64,
+    /// Longest PPS may go unrefreshed before deposits stop (0 = no limit)
+    pub max_pps_staleness: i64,
+    /// Last `update_nav`, `accrue_interest` or recovery
+    pub pps_last_update: i64,
+    /// Share of each deposit matched from `match_pool`, in bps
+    pub match_bps: u16,
+    /// Annual management fee taken by minting shares, in bps
+    pub management_fee_bps: u16,
+    pub last_fee_accrual: i64,
+    /// Management fee shares owed to the fee recipient, part of `total_shares`
+    pub unclaimed_fee_shares: u64,
+    /// Price feed quoting the underlying in a reference currency, for `tvl_in_quote`
+    pub quote_oracle: Option<Pubkey>,
+    /// Oldest oracle price accepted, in seconds (0 = no limit)
+    pub max_oracle_staleness: i64,
+    /// Shortest gap allowed between harvests, in seconds
+    pub min_harvest_interval: i64,
+    pub last_harvest: i64,
+    /// Most `total_assets` deposits may bring the vault to (0 = uncapped)
+    pub max_total_assets: u64,
+    /// Fill a deposit crossing the cap up to it instead of rejecting it
+    pub allow_partial_deposit: bool,
+    /// When `reward_rate` stops emitting
+    pub reward_end: i64,
+    /// Nominated authority awaiting acceptance
+    pub pending_authority: Pubkey,
+    /// May veto a pending authority transfer, and nothing else
+    pub guardian: Pubkey,
+    /// Id of the latest share balance snapshot (0 = none taken)
+    pub snapshot_id: u64,
+    /// Program called after every deposit and withdrawal
+    pub hook_program: Option<Pubkey>,
+    /// Id of the oldest withdrawal request not yet served or skipped
+    pub next_claimable_seq: u64,
+    /// Deposit cap that grows linearly from `cap_start` at `cap_start_time`
+    /// to `cap_max` over `cap_ramp_seconds` (`cap_max` 0 = no ramp)
+    pub cap_start: u64,
+    pub cap_max: u64,
+    pub cap_start_time: i64,
+    pub cap_ramp_seconds: i64,
+    /// Price per share the first deposit mints at, scaled by `PPS_SCALE`
+    pub initial_pps: u64,
+    /// Assets burned into vested exits and not yet claimed
+    pub vested_owed: u64,
+    /// Exits before this time pay `loss_window_penalty_bps` on top of the exit fee
+    pub loss_window_until: i64,
+    /// Length of the window `report_loss` opens
+    pub loss_window_seconds: i64,
+    pub loss_window_penalty_bps: u16,
+    /// Merkle root of keccak(index, user, shares) leaves for v1 positions
+    pub migration_root: [u8; 32],
+    /// Whether `import_position` is open
+    pub migration_mode: bool,
+    /// Shares minted by `start_migration` not yet imported into a position
+    pub unimported_shares: u64,
+    /// Withdrawal requests one user may have open at once (0 = unlimited)
+    pub max_requests_per_user: u8,
+    /// Fix a withdrawal request's asset value when it is made instead of
+    /// when it is claimed
+    pub lock_rate_at_request: bool,
+    /// Program `swap_and_deposit` routes swaps through
+    pub dex_program: Option<Pubkey>,
+    /// Deployment `migrate_position` moves positions into
+    pub migration_program: Option<Pubkey>,
+    /// Largest fraction of `total_shares` a deposit may bring one position to
+    /// (0 = no cap)
+    pub max_user_share_bps: u16,
+    /// One of the `EVENT_LEVEL_*` values
+    pub event_level: u8,
+    /// Assets deposited and paid out over the vault's life, never reset
+    pub cumulative_deposits: u128,
+    pub cumulative_withdrawals: u128,
+    /// Most assets withdrawals may pay out per day (0 = no limit)
+    pub daily_withdrawal_limit: u64,
+    /// One of the `DAILY_LIMIT_*` modes
+    pub daily_limit_mode: u8,
+    /// Day (`unix_timestamp / SECONDS_PER_DAY`) the counters below cover
+    pub flow_day: i64,
+    pub day_deposits: u64,
+    pub day_withdrawals: u64,
+    /// Announced shutdown time; deposits are closed while set (0 = none)
+    pub shutdown_scheduled_at: i64,
+    /// Extra voting weight a position earns by holding for
+    /// `vote_time_boost_cap` seconds, growing linearly until then
+    pub vote_boost_bps: u16,
+    pub vote_time_boost_cap: i64,
+    /// End of the launch period the `bootstrap_*` limits apply in; cleared
+    /// by the first operation after it (0 = over or never set)
+    pub bootstrap_until: i64,
+    /// Most assets one withdrawal may pay out during bootstrap (0 = no limit)
+    pub bootstrap_max_withdraw: u64,
+    /// Lock put on deposits made during bootstrap
+    pub bootstrap_min_lock: i64,
+    pub bootstrap_allocation_disabled: bool,
+    /// Largest `update_nav` change in bps of total assets (0 = unbounded)
+    pub max_nav_change_bps: u16,
+    pub min_nav_interval: i64,
+    pub last_nav_update: i64,
+    /// Reject a deposit and a withdrawal by one user in the same slot
+    pub block_same_slot: bool,
+    /// Deposit fee tiers sorted by `threshold`; the first
+    /// `deposit_fee_tier_count` are in use
+    pub deposit_fee_tiers: [FeeTier; MAX_FEE_TIERS],
+    pub deposit_fee_tier_count: u8,
+    /// Governance token whose holders get a fee discount
+    pub gov_mint: Pubkey,
+    /// Largest share of a fee waived, in bps (0 = no discount)
+    pub max_discount_bps: u16,
+    /// Gov token balance that earns the full discount
+    pub discount_threshold: u64,
+    /// PPS drop below `last_pps` that trips the circuit breaker (0 = off)
+    pub max_pps_drop_bps: u16,
+    /// PPS after the last loss, NAV update or recovery the breaker accepted
+    pub last_pps: u64,
+    pub breaker_tripped: bool,
+    /// Decimals share amounts are displayed with
+    pub share_decimals: u8,
+    /// `10^(share_decimals - asset decimals)`: shares per unit of assets at
+    /// a PPS of 1.0
+    pub share_scale: u64,
+}
+
+impl Vault {
+    /// Whether deposit and withdrawal paths write `msg!` logs
+    pub fn logs_enabled(&self) -> bool {
+        self.event_level != EVENT_LEVEL_SILENT
+    }
+
+    pub fn whitelist_enabled(&self) -> bool {
+        self.whitelist_root != [0; 32]
+    }
+
+    /// Fail if `op` is disabled in `paused_ops`
+    pub fn require_op_enabled(&self, op: u16) -> Result<()> {
+        require!(self.paused_ops & op == 0, VaultError::OperationPaused);
+        Ok(())
+    }
+
+    /// Fail if `total_assets` sits below `min_vault_assets` outside of shutdown
+    pub fn require_above_floor(&self) -> Result<()> {
+        require!(
+            self.is_shutdown || self.total_assets >= self.min_vault_assets,
+            VaultError::WouldBreachFloor
+        );
+        Ok(())
+    }
+
+    /// Fail if PPS is outside `[min_pps, max_pps]`, a sign of corrupted accounting
+    pub fn require_pps_in_bounds(&self) -> Result<()> {
+        let pps = self.price_per_share()?;
+        require!(
+            self.max_pps == 0 || pps <= self.max_pps,
+            VaultError::PriceAboveCeiling
+        );
+        require!(pps >= self.min_pps, VaultError::PriceBelowFloor);
+        Ok(())
+    }
+
+    /// Token balance held outside `total_assets`: the penalty and match pools,
+    /// the insurance fund and assets owed to vested exits
+    pub fn earmarked_balance(&self) -> u64 {
+        self.penalty_pool
+            .saturating_add(self.match_pool)
+            .saturating_add(self.insurance_fund)
+            .saturating_add(self.vested_owed)
+    }
+
+    /// Whether PPS went unrefreshed for longer than `max_pps_staleness`
+    pub fn pps_stale(&self, now: i64) -> bool {
+        self.max_pps_staleness > 0
+            && now.saturating_sub(self.pps_last_update) > self.max_pps_staleness
+    }
+
+    /// Largest single deposit the age-based throttle allows at `now`, or
+    /// `None` without a throttle
+    pub fn deposit_throttle(&self, now: i64) -> Option<u64> {
+        if self.deposit_throttle_base == 0 && self.deposit_throttle_per_day == 0 {
+            return None;
+        }
+        let days = (now.saturating_sub(self.created_at) / SECONDS_PER_DAY).max(0) as u64;
+        Some(
+            self.deposit_throttle_base
+                .saturating_add(self.deposit_throttle_per_day.saturating_mul(days)),
+        )
+    }
+
+    /// Ramped deposit cap at `now`, or `None` without a ramp
+    pub fn ramp_cap(&self, now: i64) -> Option<u64> {
+        if self.cap_max == 0 {
+            return None;
+        }
+        if self.cap_ramp_seconds == 0 {
+            return Some(self.cap_max);
+        }
+        let elapsed = now
+            .saturating_sub(self.cap_start_time)
+            .clamp(0, self.cap_ramp_seconds);
+        // (cap_max - cap_start) * elapsed fits in u128
+        let growth = (self.cap_max - self.cap_start) as u128 * elapsed as u128
+            / self.cap_ramp_seconds as u128;
+        Some(self.cap_start + growth as u64)
+    }
+
+    /// Assets deposits may still add at `now` before the tighter of
+    /// `max_total_assets` and the ramped cap
+    pub fn cap_room(&self, now: i64) -> u64 {
+        let fixed_cap = match self.max_total_assets {
+            0 => u64::MAX,
+            cap => cap,
+        };
+        let cap = self
+            .ramp_cap(now)
+            .map_or(fixed_cap, |ramp| ramp.min(fixed_cap));
+        cap.saturating_sub(self.total_assets)
+    }
+
+    /// Part of the token `balance` beyond everything the vault owes, counting
+    /// deployed assets as held; never more than `balance` itself
+    pub fn surplus(&self, balance: u64) -> u64 {
+        let backing = balance as u128 + self.deployed_assets as u128;
+        let liabilities = self.total_assets as u128 + self.earmarked_balance() as u128;
+        (backing.saturating_sub(liabilities) as u64).min(balance)
+    }
+
+    /// Sponsor match for a deposit of `amount`, capped by the pool
+    pub fn match_amount(&self, amount: u64) -> Result<u64> {
+        let matched = (amount as u128)
+            .checked_mul(self.match_bps as u128)
+            .ok_or(VaultError::MathOverflow)?
+            / BPS_DENOMINATOR as u128;
+        Ok(matched.min(self.match_pool as u128) as u64)
+    }
+
+    /// Idle assets (`balance` minus earmarked pools) beyond the reserve
+    pub fn idle_above_reserve(&self, balance: u64) -> Result<u64> {
+        let idle = balance.saturating_sub(self.earmarked_balance());
+        let reserve = (self.total_assets as u128)
+            .checked_mul(self.reserve_bps as u128)
+            .ok_or(VaultError::MathOverflow)?
+            / BPS_DENOMINATOR as u128;
+        Ok((idle as u128).saturating_sub(reserve) as u64)
+    }
+
+    /// Fail with `ParamsLocked` if params are locked and the change `worsens` terms
+    pub fn require_terms_not_worsened(&self, worsens: bool) -> Result<()> {
+        require!(!(self.params_locked && worsens), VaultError::ParamsLocked);
+        Ok(())
+    }
+
+    /// Count `shares` against the current slot's mint cap, resetting the
+    /// counter when `slot` has moved on
+    pub fn record_slot_mint(&mut self, shares: u64, slot: u64) -> Result<()> {
+        if slot != self.last_mint_slot {
+            self.last_mint_slot = slot;
+            self.slot_shares_minted = 0;
+        }
+        self.slot_shares_minted = self
+            .slot_shares_minted
+            .checked_add(shares)
+            .ok_or(VaultError::MathOverflow)?;
+        require!(
+            self.max_shares_per_slot == 0 || self.slot_shares_minted <= self.max_shares_per_slot,
+            VaultError::SlotMintCapExceeded
+        );
+        Ok(())
+    }
+
+    /// Add to today's flow, starting a new day's counters first if `now`
+    /// falls on a later day, and enforce the daily withdrawal limit
+    pub fn record_daily_flow(&mut self, deposited: u64, withdrawn: u64, now: i64) -> Result<()> {
+        let day = now / SECONDS_PER_DAY;
+        if day != self.flow_day {
+            self.flow_day = day;
+            self.day_deposits = 0;
+            self.day_withdrawals = 0;
+        }
+        self.day_deposits = self
+            .day_deposits
+            .checked_add(deposited)
+            .ok_or(VaultError::MathOverflow)?;
+        self.day_withdrawals = self
+            .day_withdrawals
+            .checked_add(withdrawn)
+            .ok_or(VaultError::MathOverflow)?;
+        if withdrawn > 0 && self.daily_withdrawal_limit > 0 {
+            let outflow = if self.daily_limit_mode == DAILY_LIMIT_NET {
+                self.day_withdrawals.saturating_sub(self.day_deposits)
+            } else {
+                self.day_withdrawals
+            };
+            require!(
+                outflow <= self.daily_withdrawal_limit,
+                VaultError::DailyLimitExceeded
+            );
+        }
+        Ok(())
+    }
+
+    /// Fee in bps of the highest tier a deposit of `amount` reaches, 0 below
+    /// the first tier
+    pub fn deposit_fee_bps(&self, amount: u64) -> u16 {
+        self.deposit_fee_tiers[..self.deposit_fee_tier_count as usize]
+            .iter()
+            .rev()
+            .find(|tier| amount >= tier.threshold)
+            .map_or(0, |tier| tier.fee_bps)
+    }
+
+    /// Share of deposit and exit fees waived for holding `gov_balance` of
+    /// `gov_mint`, in bps, rising linearly to `max_discount_bps` at
+    /// `discount_threshold`
+    pub fn fee_discount_bps(&self, gov_balance: u64) -> Result<u16> {
+        if self.max_discount_bps == 0 {
+            return Ok(0);
+        }
+        let bps = mul_div(
+            self.max_discount_bps as u64,
+            gov_balance.min(self.discount_threshold),
+            self.discount_threshold,
+            false,
+        )?;
+        Ok(bps as u16)
+    }
+
+    /// Fee charged on `received` for a deposit of `amount`, with the tier's
+    /// bps. Returns `(fee, fee_bps)`.
+    pub fn deposit_fee(&self, amount: u64, received: u64) -> Result<(u64, u16)> {
+        let fee_bps = self.deposit_fee_bps(amount);
+        let fee = mul_div(received, fee_bps as u64, BPS_DENOMINATOR, false)?;
+        Ok((fee, fee_bps))
+    }
+
+    /// Shares a deposit of `received` mints at `now`, after the sponsor match
+    /// (never pushing the vault past `cap_room`) and any early-participant
+    /// bonus, counted against the per-slot mint cap. Returns
+    /// `(shares, bonus_shares, matched)`.
+    pub fn price_deposit(
+        &mut self,
+        received: u64,
+        cap_room: u64,
+        now: i64,
+        slot: u64,
+    ) -> Result<(u64, u64, u64)> {
+        let matched = self.match_amount(received)?.min(cap_room - received);
+        let backing = received
+            .checked_add(matched)
+            .ok_or(VaultError::MathOverflow)?;
+        self.require_pps_in_bounds()?;
+        let (shares, bonus_shares) = self.deposit_shares(backing, now)?;
+        require!(shares > 0, VaultError::ZeroSharesMinted);
+        self.record_slot_mint(shares, slot)?;
+        Ok((shares, bonus_shares, matched))
+    }
+
+    pub fn bootstrap_active(&self, now: i64) -> bool {
+        now < self.bootstrap_until
+    }
+
+    /// Whether the bootstrap period currently keeps assets out of the strategy
+    pub fn allocation_blocked(&self, now: i64) -> bool {
+        self.bootstrap_active(now) && self.bootstrap_allocation_disabled
+    }
+
+    /// Fail when a withdrawal paying `assets_out` exceeds the bootstrap limit
+    pub fn check_bootstrap_withdraw(&self, assets_out: u64, now: i64) -> Result<()> {
+        if self.bootstrap_active(now) && self.bootstrap_max_withdraw > 0 {
+            require!(
+                assets_out <= self.bootstrap_max_withdraw,
+                VaultError::BootstrapWithdrawLimitExceeded
+            );
+        }
+        Ok(())
+    }
+
+    /// Fail when a position of `position_shares`, already counted in
+    /// `total_shares`, exceeds `max_user_share_bps`. The bootstrap deposit is exempt.
+    pub fn check_concentration(&self, position_shares: u64, is_bootstrap: bool) -> Result<()> {
+        if self.max_user_share_bps > 0 && !is_bootstrap {
+            let share_bps =
+                (position_shares as u128) * BPS_DENOMINATOR as u128 / self.total_shares as u128;
+            require!(
+                share_bps <= self.max_user_share_bps as u128,
+                VaultError::ConcentrationLimitExceeded
+            );
+        }
+        Ok(())
+    }
+
+    /// `position`'s shares plus `vote_boost_bps` of them, scaled by the share
+    /// of `vote_time_boost_cap` elapsed since its first deposit
+    pub fn voting_power(&self, position: &UserShares, now: i64) -> Result<u64> {
+        if self.vote_boost_bps == 0 || self.vote_time_boost_cap == 0 || position.shares == 0 {
+            return Ok(position.shares);
+        }
+        let held = now
+            .saturating_sub(position.first_deposit_at)
+            .clamp(0, self.vote_time_boost_cap);
+        let boost = mul_div(
+            mul_div(
+                position.shares,
+                self.vote_boost_bps as u64,
+                BPS_DENOMINATOR,
+                false,
+            )?,
+            held as u64,
+            self.vote_time_boost_cap as u64,
+            false,
+        )?;
+        position
+            .shares
+            .checked_add(boost)
+            .ok_or(VaultError::MathOverflow.into())
+    }
+
+    pub fn rounds_up(&self) -> bool {
+        self.rounding == ROUNDING_FAVOR_USER
+    }
+
+    /// Compound `interest_rate_bps` into `supply_index` for the time since
+    /// `interest_updated_at`, growing `total_assets` by the same factor. The
+    /// interest is owed by borrowers, so it is booked as deployed. Returns the
+    /// interest credited.
+    pub fn accrue_interest(&mut self, now: i64) -> Result<u64> {
+        let elapsed = now.saturating_sub(self.interest_updated_at).max(0) as u128;
+        self.interest_updated_at = now;
+        if elapsed == 0 || self.interest_rate_bps == 0 {
+            return Ok(0);
+        }
+
+        // Growth this period is `rate * elapsed / (BPS_DENOMINATOR * year)`
+        let growth = self.interest_rate_bps as u128 * elapsed;
+        let period = BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR as u128;
+        let index_growth = self
+            .supply_index
+            .checked_mul(growth)
+            .ok_or(VaultError::MathOverflow)?
+            / period;
+        self.supply_index = self
+            .supply_index
+            .checked_add(index_growth)
+            .ok_or(VaultError::MathOverflow)?;
+
+        let interest = u64::try_from((self.total_assets as u128 * growth) / period)
+            .map_err(|_| VaultError::MathOverflow)?;
+        self.total_assets = self
+            .total_assets
+            .checked_add(interest)
+            .ok_or(VaultError::MathOverflow)?;
+        self.deployed_assets = self
+            .deployed_assets
+            .checked_add(interest)
+            .ok_or(VaultError::MathOverflow)?;
+        Ok(interest)
+    }
+
+    /// Mint `management_fee_bps` per year of `total_assets`, for the time
+    /// since `last_fee_accrual`, as shares owed to the fee recipient. Nothing
+    /// accrues while there is no recipient or no holders. Returns the shares
+    /// minted.
+    pub fn accrue_management_fee(&mut self, now: i64) -> Result<u64> {
+        let elapsed = now.saturating_sub(self.last_fee_accrual).max(0) as u128;
+        self.last_fee_accrual = now;
+        if elapsed == 0
+            || self.management_fee_bps == 0
+            || self.total_shares == 0
+            || self.fee_recipient == Pubkey::default()
+        {
+            return Ok(0);
+        }
+
+        let total_assets = self.total_assets as u128;
+        let fee_assets = (total_assets * self.management_fee_bps as u128 * elapsed
+            / (BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR as u128))
+            .min(total_assets);
+        let remaining = total_assets - fee_assets;
+        if fee_assets == 0 || remaining == 0 {
+            return Ok(0);
+        }
+        // Shares worth `fee_assets` once they dilute the existing supply
+        let fee_shares = u64::try_from(
+            fee_assets
+                .checked_mul(self.total_shares as u128)
+                .ok_or(VaultError::MathOverflow)?
+                / remaining,
+        )
+        .map_err(|_| VaultError::MathOverflow)?;
+        self.total_shares = self
+            .total_shares
+            .checked_add(fee_shares)
+            .ok_or(VaultError::MathOverflow)?;
+        self.unclaimed_fee_shares = self
+            .unclaimed_fee_shares
+            .checked_add(fee_shares)
+            .ok_or(VaultError::MathOverflow)?;
+        Ok(fee_shares)
+    }
+
+    /// Outstanding shares with nothing left to back them, e.g. after a loss
+    /// wrote `total_assets` down to zero
+    pub fn is_insolvent(&self) -> bool {
+        self.total_assets == 0 && self.total_shares > 0
+    }
+
+    /// Shares minted for `assets` at the current exchange rate. Fails with
+    /// `VaultInsolvent` rather than resetting to 1:1 when outstanding shares
+    /// are worth nothing, since a reset would let those shares claim part of
+    /// the new deposit.
+    pub fn convert_to_shares(&self, assets: u64) -> Result<u64> {
+        if self.total_shares == 0 {
+            return mul_div(
+                assets,
+                self.share_scale * PPS_SCALE,
+                self.initial_pps,
+                self.rounds_up(),
+            );
+        }
+        require!(!self.is_insolvent(), VaultError::VaultInsolvent);
+        mul_div(
+            assets,
+            self.total_shares,
+            self.total_assets,
+            self.rounds_up(),
+        )
+    }
+
+    /// Shares a deposit of `assets` mints at `now`, including any
+    /// early-participant bonus. Returns `(shares, bonus_shares)`.
+    pub fn deposit_shares(&self, assets: u64, now: i64) -> Result<(u64, u64)> {
+        let base_shares = self.convert_to_shares(assets)?;
+        let bonus_shares = if now < self.bonus_end_time {
+            u64::try_from(
+                (base_shares as u128)
+                    .checked_mul(self.bonus_bps as u128)
+                    .ok_or(VaultError::MathOverflow)?
+                    / BPS_DENOMINATOR as u128,
+            )
+            .map_err(|_| VaultError::MathOverflow)?
+        } else {
+            0
+        };
+        let shares = base_shares
+            .checked_add(bonus_shares)
+            .ok_or(VaultError::MathOverflow)?;
+        Ok((shares, bonus_shares))
+    }
+
+    /// Assets redeemed for `shares` at the current exchange rate. Never more
+    /// than `total_assets`, so rounding up cannot pay out unbacked assets.
+    pub fn convert_to_assets(&self, shares: u64) -> Result<u64> {
+        let assets = mul_div(
+            shares,
+            self.total_assets,
+            self.total_shares,
+            self.rounds_up(),
+        )?;
+        Ok(assets.min(self.total_assets))
+    }
+
+    /// Assets paid for burning `shares`. Burning the last outstanding shares
+    /// takes all remaining assets so rounding dust is not stranded.
+    pub fn redeem_value(&self, shares: u64) -> Result<u64> {
+        if shares == self.total_shares {
+            return Ok(self.total_assets);
+        }
+        self.convert_to_assets(shares)
+    }
+
+    /// Whether deposits are blocked at `now`, honoring any auto-unpause time
+    pub fn deposits_paused(&self, now: i64) -> bool {
+        self.paused && (self.auto_unpause_at == 0 || now < self.auto_unpause_at)
+    }
+
+    /// Largest deposit accepted from the holder of `user_shares` at `now`:
+    /// zero while paused, closing, insolvent or cooling down, otherwise
+    /// bounded by the deposit cap and arithmetic headroom
+    pub fn max_deposit(&self, user_shares: Option<&UserShares>, now: i64) -> u64 {
+        if self.is_shutdown
+            || self.shutdown_scheduled_at != 0
+            || self.pps_stale(now)
+            || self.is_insolvent()
+            || self.deposits_paused(now)
+            || self.paused_ops & OP_DEPOSIT != 0
+        {
+            return 0;
+        }
+        if let Some(position) = user_shares {
+            if now.saturating_sub(position.last_deposit_at) < self.deposit_cooldown {
+                return 0;
+            }
+        }
+        let headroom = self
+            .deposit_throttle(now)
+            .map_or(self.cap_room(now), |throttle| {
+                throttle.min(self.cap_room(now))
+            });
+        // The shares minted must also fit next to `total_shares`
+        let share_room = (u64::MAX - self.total_shares) as u128;
+        let by_shares = if self.total_shares == 0 {
+            share_room * self.initial_pps as u128 / (self.share_scale * PPS_SCALE) as u128
+        } else {
+            share_room * self.total_assets as u128 / self.total_shares as u128
+        };
+        headroom.min(by_shares.min(u64::MAX as u128) as u64)
+    }
+
+    /// Most shares the holder of `user_shares` could redeem at `now`: zero
+    /// while withdrawals are blocked for them, otherwise bounded by idle
+    /// assets, the asset floor and the dust rule
+    pub fn max_redeem(&self, user_shares: Option<&UserShares>, now: i64) -> Result<u64> {
+        let position = match user_shares {
+            Some(position) => position,
+            None => return Ok(0),
+        };
+        if self.paused_ops & OP_WITHDRAW != 0
+            || now < position.unlock_at
+            || self.require_pps_in_bounds().is_err()
+        {
+            return Ok(0);
+        }
+
+        let idle = self.total_assets.saturating_sub(self.deployed_assets);
+        let available = if self.is_shutdown {
+            idle
+        } else {
+            idle.min(self.total_assets.saturating_sub(self.min_vault_assets))
+        };
+        if self.redeem_value(position.shares)? <= available {
+            return Ok(position.shares);
+        }
+
+        // Rounding down keeps the value of `shares` within `available`
+        let mut shares =
+            mul_div(available, self.total_shares, self.total_assets, false)?.min(position.shares);
+        let remaining = position.shares - shares;
+        if remaining != 0 && remaining < self.min_position_shares {
+            shares = position.shares.saturating_sub(self.min_position_shares);
+        }
+        Ok(shares)
+    }
+
+    /// Exit fee in bps for a position first funded at `first_deposit_at`
+    pub fn exit_fee_bps(&self, first_deposit_at: i64, now: i64) -> u16 {
+        if self.max_exit_fee_bps == 0 || self.exit_fee_decay_seconds <= 0 {
+            return 0;
+        }
+        let held = now.saturating_sub(first_deposit_at).max(0);
+        let remaining = self.exit_fee_decay_seconds.saturating_sub(held).max(0);
+        (self.max_exit_fee_bps as i128 * remaining as i128 / self.exit_fee_decay_seconds as i128)
+            as u16
+    }
+
+    /// Extra charge on redeeming `shares` worth `assets` during a loss window.
+    /// It stays in `total_assets` for the remaining holders, so the last exit
+    /// is never charged.
+    pub fn loss_penalty(&self, assets: u64, shares: u64, now: i64) -> Result<u64> {
+        if now >= self.loss_window_until || shares == self.total_shares {
+            return Ok(0);
+        }
+        mul_div(
+            assets,
+            self.loss_window_penalty_bps as u64,
+            BPS_DENOMINATOR,
+            false,
+        )
+    }
+
+    /// Exit fee charged on withdrawing `assets`
+    pub fn exit_fee(&self, assets: u64, first_deposit_at: i64, now: i64) -> Result<u64> {
+        let fee_bps = self.exit_fee_bps(first_deposit_at, now);
+        let fee = (assets as u128)
+            .checked_mul(fee_bps as u128)
+            .ok_or(VaultError::MathOverflow)?
+            / BPS_DENOMINATOR as u128;
+        u64::try_from(fee).map_err(|_| VaultError::MathOverflow.into())
+    }
+
+    /// Assets per `share_scale` shares, scaled by `PPS_SCALE`; `initial_pps`
+    /// for an empty vault
+    pub fn price_per_share(&self) -> Result<u64> {
+        if self.total_shares == 0 {
+            return Ok(self.initial_pps);
+        }
+        let pps = (self.total_assets as u128)
+            .checked_mul(PPS_SCALE as u128 * self.share_scale as u128)
+            .ok_or(VaultError::MathOverflow)?
+            / self.total_shares as u128;
+        u64::try_from(pps).map_err(|_| VaultError::MathOverflow.into())
+    }
+
+    /// Advance `reward_per_share_stored` to `now`
+    pub fn accrue_rewards(&mut self, now: i64) -> Result<()> {
+        self.reward_per_share_stored = self.reward_per_share_at(now)?;
+        self.rewards_updated_at = now;
+        Ok(())
+    }
+
+    /// What `reward_per_share_stored` would be after accruing to `now`.
+    /// Emission stops at `reward_end`.
+    pub fn reward_per_share_at(&self, now: i64) -> Result<u128> {
+        let elapsed = now
+            .min(self.reward_end)
+            .saturating_sub(self.rewards_updated_at)
+            .max(0) as u128;
+        // Receipt shares sit in no position, so emissions go to the rest
+        let earning_shares = self.total_shares.saturating_sub(self.receipt_shares);
+        if elapsed == 0 || earning_shares == 0 {
+            return Ok(self.reward_per_share_stored);
+        }
+        let increment = (self.reward_rate as u128)
+            .checked_mul(elapsed)
+            .ok_or(VaultError::MathOverflow)?
+            .checked_mul(REWARD_PRECISION)
+            .ok_or(VaultError::MathOverflow)?
+            / earning_shares as u128;
+        self.reward_per_share_stored
+            .checked_add(increment)
+            .ok_or(VaultError::MathOverflow.into())
+    }
+
+    /// `deployed_assets / total_assets` in bps, zero for an empty vault
+    pub fn utilization_bps(&self) -> Result<u16> {
+        if self.total_assets == 0 {
+            return Ok(0);
+        }
+        let bps = (self.deployed_assets as u128)
+            .checked_mul(BPS_DENOMINATOR as u128)
+            .ok_or(VaultError::MathOverflow)?
+            / self.total_assets as u128;
+        u16::try_from(bps).map_err(|_| VaultError::MathOverflow.into())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserShares {
+    /// Vault this position belongs to
+    pub vault: Pubkey,
+    pub shares: u64,
+    pub last_deposit_at: i64,
+    pub unlock_at: i64,
+    /// Start of the current holding period (0 = no open position)
+    pub first_deposit_at: i64,
+    /// `Vault::reward_per_share_stored` as of the last settlement
+    pub reward_debt: u128,
+    /// Rewards settled but not yet claimed
+    pub pending_rewards: u64,
+    /// `Vault::snapshot_id` at the last balance change
+    pub snapshot_id: u64,
+    /// Balance held at every snapshot from `snapshot_from` to `snapshot_id`
+    pub snapshot_shares: u64,
+    pub snapshot_from: u64,
+    /// Withdrawal requests opened and not yet claimed or cancelled
+    pub open_requests: u8,
+    /// Slot of the last deposit or withdrawal, and which of the two it was
+    pub last_action_slot: u64,
+    pub last_action_deposit: bool,
+    /// Assets deposited into this position, net of the deposit fee. A split
+    /// moves the share it carries along with the shares.
+    pub lifetime_deposited: u64,
+}
+
+impl UserShares {
+    /// Count a new withdrawal request against `max_requests` (0 = unlimited)
+    pub fn open_request(&mut self, max_requests: u8) -> Result<()> {
+        require!(
+            max_requests == 0 || self.open_requests < max_requests,
+            VaultError::TooManyRequests
+        );
+        self.open_requests = self
+            .open_requests
+            .checked_add(1)
+            .ok_or(VaultError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Record a deposit (`is_deposit`) or withdrawal at `slot`. With
+    /// `block_same_slot`, fails if the other one already happened this slot.
+    pub fn record_action(
+        &mut self,
+        is_deposit: bool,
+        slot: u64,
+        block_same_slot: bool,
+    ) -> Result<()> {
+        require!(
+            !(block_same_slot
+                && slot == self.last_action_slot
+                && is_deposit != self.last_action_deposit),
+            VaultError::SameSlotActionBlocked
+        );
+        self.last_action_slot = slot;
+        self.last_action_deposit = is_deposit;
+        Ok(())
+    }
+
+    /// Record the balance about to change against the snapshots taken since
+    /// the last change. Only the most recent range is kept.
+    pub fn checkpoint(&mut self, current_snapshot: u64) {
+        if current_snapshot > self.snapshot_id {
+            self.snapshot_from = self.snapshot_id + 1;
+            self.snapshot_shares = self.shares;
+            self.snapshot_id = current_snapshot;
+        }
+    }
+
+    /// Balance at snapshot `id`, which must be no later than `current_snapshot`
+    pub fn shares_at(&self, id: u64, current_snapshot: u64) -> Result<u64> {
+        require!(
+            id > 0 && id <= current_snapshot,
+            VaultError::UnknownSnapshot
+        );
+        if id > self.snapshot_id {
+            // No balance change since the snapshot was taken
+            return Ok(self.shares);
+        }
+        require!(id >= self.snapshot_from, VaultError::UnknownSnapshot);
+        Ok(self.snapshot_shares)
+    }
+
+    /// Pending rewards once settled against `reward_per_share`
+    pub fn rewards_at(&self, reward_per_share: u128) -> Result<u64> {
+        let per_share = reward_per_share
+            .checked_sub(self.reward_debt)
+            .ok_or(VaultError::Underflow)?;
+        let earned = (self.shares as u128)
+            .checked_mul(per_share)
+            .ok_or(VaultError::MathOverflow)?
+            / REWARD_PRECISION;
+        self.pending_rewards
+            .checked_add(u64::try_from(earned).map_err(|_| VaultError::MathOverflow)?)
+            .ok_or(VaultError::MathOverflow.into())
+    }
+}
+
+/// Withdrawal allowance an owner grants to a delegate
+#[account]
+#[derive(InitSpace)]
+pub struct WithdrawDelegate {
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+    /// Remaining assets the delegate may withdraw
+    pub allowance: u64,
+}
+
+/// Token account `withdraw_to` may pay into while destinations are restricted
+#[account]
+#[derive(InitSpace)]
+pub struct ApprovedDestination {
+    pub destination: Pubkey,
+    pub approved_at: i64,
+}
+
+/// Shares queued for withdrawal, claimable once `claimable_at` passes
+#[account]
+#[derive(InitSpace)]
+pub struct WithdrawalRequest {
+    pub owner: Pubkey,
+    pub id: u64,
+    pub shares: u64,
+    /// Holding-period start carried over from the user's position for the exit fee
+    pub first_deposit_at: i64,
+    pub requested_at: i64,
+    pub claimable_at: i64,
+    /// Value fixed at request time when `Vault::lock_rate_at_request` was set;
+    /// `None` redeems at the rate current when claimed
+    pub locked_assets: Option<u64>,
+}
+
+/// Assets burned out of a position and paid out linearly over time
+#[account]
+#[derive(InitSpace)]
+pub struct VestedExit {
+    pub owner: Pubkey,
+    /// Assets owed in total, net of the exit fee
+    pub total: u64,
+    pub claimed: u64,
+    pub start: i64,
+    pub duration: i64,
+}
+
+impl VestedExit {
+    /// Assets unlocked by `now`; all of `total` once vesting ends, so the
+    /// last claim picks up any rounding remainder
+    pub fn unlocked_at(&self, now: i64) -> Result<u64> {
+        let elapsed = now.saturating_sub(self.start);
+        if elapsed >= self.duration {
+            return Ok(self.total);
+        }
+        mul_div(
+            self.total,
+            elapsed.max(0) as u64,
+            self.duration as u64,
+            false,
+        )
+    }
+}
+
+/// One bit per v1 position, set once it has been imported
+#[account]
+#[derive(InitSpace)]
+pub struct ImportBitmap {
+    pub bits: [u8; IMPORT_BITMAP_BYTES],
+}
+
+impl ImportBitmap {
+    /// Mark position `index` imported, failing if it already was
+    pub fn claim(&mut self, index: u32) -> Result<()> {
+        let byte = (index / 8) as usize;
+        let mask = 1u8 << (index % 8);
+        require!(byte < IMPORT_BITMAP_BYTES, VaultError::InvalidParameter);
+        require!(self.bits[byte] & mask == 0, VaultError::AlreadyImported);
+        self.bits[byte] |= mask;
+        Ok(())
+    }
+}
+
+/// Flow totals for one closed epoch
+#[account]
+#[derive(InitSpace)]
+pub struct EpochRecord {
+    pub vault: Pubkey,
+    pub epoch: u64,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub total_assets: u64,
+    pub total_shares: u64,
+}
+
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace,
+)]
+pub struct FeeTier {
+    /// Smallest deposit the tier applies to
+    pub threshold: u64,
+    pub fee_bps: u16,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, InitSpace)]
+pub struct PpsCheckpoint {
+    pub timestamp: i64,
+    pub pps: u64,
+}
+
+/// Ring buffer of the most recent price-per-share checkpoints
+#[account]
+#[derive(InitSpace)]
+pub struct PpsHistory {
+    pub vault: Pubkey,
+    /// Slot the next checkpoint is written to
+    pub head: u8,
+    pub count: u8,
+    pub checkpoints: [PpsCheckpoint; PPS_HISTORY_LEN],
+}
+
+impl PpsHistory {
+    pub fn push(&mut self, checkpoint: PpsCheckpoint) {
+        self.checkpoints[self.head as usize] = checkpoint;
+        self.head = ((self.head as usize + 1) % PPS_HISTORY_LEN) as u8;
+        self.count = (self.count as usize + 1).min(PPS_HISTORY_LEN) as u8;
+    }
+
+    /// The checkpoint `n` places before the newest one
+    pub fn nth_latest(&self, n: usize) -> Option<PpsCheckpoint> {
+        if n >= self.count as usize {
+            return None;
+        }
+        let index = (self.head as usize + PPS_HISTORY_LEN - 1 - n) % PPS_HISTORY_LEN;
+        Some(self.checkpoints[index])
+    }
+
+    pub fn latest(&self) -> Option<PpsCheckpoint> {
+        self.nth_latest(0)
+    }
+
+    /// The newest checkpoint taken at or before `timestamp`
+    pub fn at_or_before(&self, timestamp: i64) -> Option<PpsCheckpoint> {
+        (0..self.count as usize)
+            .filter_map(|n| self.nth_latest(n))
+            .find(|checkpoint| checkpoint.timestamp <= timestamp)
+    }
+}
+
+// ============ Views ============
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeatureFlags {
+    pub whitelist_enabled: bool,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub paused_ops: u16,
+    pub deposit_cooldown_on: bool,
+    pub locks_on: bool,
+    pub fees_on: bool,
+    pub oracle_check_on: bool,
+}
+
+impl FeatureFlags {
+    pub fn from_vault(vault: &Vault, now: i64) -> Self {
+        Self {
+            whitelist_enabled: vault.whitelist_enabled(),
+            deposits_paused: vault.deposits_paused(now) || vault.paused_ops & OP_DEPOSIT != 0,
+            withdrawals_paused: vault.paused_ops & OP_WITHDRAW != 0,
+            paused_ops: vault.paused_ops,
+            deposit_cooldown_on: vault.deposit_cooldown > 0,
+            locks_on: vault.max_lock > 0,
+            fees_on: vault.max_exit_fee_bps > 0 && vault.exit_fee_decay_seconds > 0,
+            oracle_check_on: vault.oracle.is_some(),
+        }
+    }
+}
+
+/// Outcome of `dry_run_deposit`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DepositResult {
+    pub success: bool,
+    /// Amount the deposit would take, below the request only on a partial fill
+    pub amount: u64,
+    pub shares: u64,
+    /// Error code and name `deposit` would fail with, 0 and empty on success
+    pub error_code: u32,
+    pub error_name: String,
+}
+
+impl DepositResult {
+    pub fn from_outcome(outcome: Result<(u64, u64)>) -> Self {
+        match outcome {
+            Ok((amount, shares)) => Self {
+                success: true,
+                amount,
+                shares,
+                error_code: 0,
+                error_name: String::new(),
+            },
+            Err(err) => {
+                let (error_code, error_name) = match err {
+                    Error::AnchorError(err) => (err.error_code_number, err.error_name),
+                    Error::ProgramError(err) => (u32::MAX, err.program_error.to_string()),
+                };
+                Self {
+                    success: false,
+                    amount: 0,
+                    shares: 0,
+                    error_code,
+                    error_name,
+                }
+            }
+        }
+    }
+}
+
+/// Deposit and exit terms, straight from `Vault`. Extend it whenever a new
+/// fee, lock, cooldown or cap is added.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct VaultPolicy {
+    /// One of the `ROUNDING_*` modes
+    pub rounding: u8,
+    /// Exit fee charged right after a deposit, decaying to zero
+    pub max_exit_fee_bps: u16,
+    pub exit_fee_decay_seconds: i64,
+    pub management_fee_bps: u16,
+    /// Deposit fee tiers in use, ascending by threshold
+    pub deposit_fee_tiers: Vec<FeeTier>,
+    pub gov_mint: Pubkey,
+    pub max_discount_bps: u16,
+    pub discount_threshold: u64,
+    pub loss_window_penalty_bps: u16,
+    pub loss_window_seconds: i64,
+    pub min_lock: i64,
+    pub max_lock: i64,
+    pub bootstrap_lock_seconds: i64,
+    pub bootstrap_until: i64,
+    pub bootstrap_max_withdraw: u64,
+    pub bootstrap_min_lock: i64,
+    pub bootstrap_allocation_disabled: bool,
+    pub deposit_cooldown: i64,
+    pub withdrawal_cooldown: i64,
+    pub lock_rate_at_request: bool,
+    pub block_same_slot: bool,
+    /// Caps, 0 = unlimited
+    pub max_total_assets: u64,
+    pub max_pending_withdrawals: u64,
+    pub max_requests_per_user: u8,
+    pub max_user_share_bps: u16,
+    pub daily_withdrawal_limit: u64,
+    pub daily_limit_mode: u8,
+    pub deposit_throttle_base: u64,
+    pub deposit_throttle_per_day: u64,
+    pub max_pps_staleness: i64,
+    /// Smallest position a partial exit may leave behind
+    pub min_position_shares: u64,
+    /// Whether exit terms can only improve from here
+    pub params_locked: bool,
+}
+
+impl VaultPolicy {
+    pub fn from_vault(vault: &Vault) -> Self {
+        Self {
+            rounding: vault.rounding,
+            max_exit_fee_bps: vault.max_exit_fee_bps,
+            exit_fee_decay_seconds: vault.exit_fee_decay_seconds,
+            management_fee_bps: vault.management_fee_bps,
+            deposit_fee_tiers: vault.deposit_fee_tiers[..vault.deposit_fee_tier_count as usize]
+                .to_vec(),
+            gov_mint: vault.gov_mint,
+            max_discount_bps: vault.max_discount_bps,
+            discount_threshold: vault.discount_threshold,
+            loss_window_penalty_bps: vault.loss_window_penalty_bps,
+            loss_window_seconds: vault.loss_window_seconds,
+            min_lock: vault.min_lock,
+            max_lock: vault.max_lock,
+            bootstrap_lock_seconds: vault.bootstrap_lock_seconds,
+            bootstrap_until: vault.bootstrap_until,
+            bootstrap_max_withdraw: vault.bootstrap_max_withdraw,
+            bootstrap_min_lock: vault.bootstrap_min_lock,
+            bootstrap_allocation_disabled: vault.bootstrap_allocation_disabled,
+            deposit_cooldown: vault.deposit_cooldown,
+            withdrawal_cooldown: vault.withdrawal_cooldown,
+            lock_rate_at_request: vault.lock_rate_at_request,
+            block_same_slot: vault.block_same_slot,
+            max_total_assets: vault.max_total_assets,
+            max_pending_withdrawals: vault.max_pending_withdrawals,
+            max_requests_per_user: vault.max_requests_per_user,
+            max_user_share_bps: vault.max_user_share_bps,
+            daily_withdrawal_limit: vault.daily_withdrawal_limit,
+            daily_limit_mode: vault.daily_limit_mode,
+            deposit_throttle_base: vault.deposit_throttle_base,
+            deposit_throttle_per_day: vault.deposit_throttle_per_day,
+            max_pps_staleness: vault.max_pps_staleness,
+            min_position_shares: vault.min_position_shares,
+            params_locked: vault.params_locked,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PositionSummary {
+    pub shares: u64,
+    /// Assets a full withdrawal would pay right now, net of the exit fee
+    pub asset_value: u64,
+    /// Settled plus not-yet-settled rewards
+    pub pending_reward: u64,
+    pub unlock_at: i64,
+    pub is_locked: bool,
+    pub first_deposit_at: i64,
+}
+
+impl PositionSummary {
+    pub fn from_position(vault: &Vault, position: &UserShares, now: i64) -> Result<Self> {
+        let gross = vault.redeem_value(position.shares)?;
+        let fee = vault.exit_fee(gross, position.first_deposit_at, now)?;
+        Ok(Self {
+            shares: position.shares,
+            asset_value: gross.checked_sub(fee).ok_or(VaultError::Underflow)?,
+            pending_reward: position.rewards_at(vault.reward_per_share_at(now)?)?,
+            unlock_at: position.unlock_at,
+            is_locked: now < position.unlock_at,
+            first_deposit_at: position.first_deposit_at,
+        })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Addresses {
+    /// `[b"vault", token_mint]`
+    pub vault: Pubkey,
+    pub vault_bump: u8,
+    /// `[b"user_shares", vault, user]`
+    pub user_shares: Pubkey,
+    pub user_shares_bump: u8,
+}
+
+impl Addresses {
+    pub fn new(token_mint: &Pubkey, user: &Pubkey) -> Self {
+        let (vault, vault_bump) =
+            Pubkey::find_program_address(&[b"vault", token_mint.as_ref()], &crate::ID);
+        let (user_shares, user_shares_bump) = Pubkey::find_program_address(
+            &[b"user_shares", vault.as_ref(), user.as_ref()],
+            &crate::ID,
+        );
+        Self {
+            vault,
+            vault_bump,
+            user_shares,
+            user_shares_bump,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DepositQuote {
+    /// Assets credited to the depositor after fees
+    pub net_deposit: u64,
+    /// Deposit fee of the tier `amount` reaches
+    pub fee: u64,
+    /// Shares minted, including the sponsor match and any early bonus
+    pub shares: u64,
+}
+
+impl DepositQuote {
+    pub fn new(vault: &Vault, amount: u64, now: i64) -> Result<Self> {
+        let (fee, _) = vault.deposit_fee(amount, amount)?;
+        let net_deposit = amount - fee;
+        let backing = net_deposit
+            .checked_add(vault.match_amount(net_deposit)?)
+            .ok_or(VaultError::MathOverflow)?;
+        let (shares, _) = vault.deposit_shares(backing, now)?;
+        Ok(Self {
+            net_deposit,
+            fee,
+            shares,
+        })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WithdrawQuote {
+    pub gross_assets: u64,
+    /// Exit fee after time-based decay, plus any loss-window penalty
+    pub fee: u64,
+    pub net_assets: u64,
+}
+
+impl WithdrawQuote {
+    pub fn new(vault: &Vault, shares: u64, first_deposit_at: i64, now: i64) -> Result<Self> {
+        Self::at(
+            vault,
+            vault.redeem_value(shares)?,
+            shares,
+            first_deposit_at,
+            now,
+        )
+    }
+
+    /// Quote for `shares` worth `gross_assets` before fees
+    pub fn at(
+        vault: &Vault,
+        gross_assets: u64,
+        shares: u64,
+        first_deposit_at: i64,
+        now: i64,
+    ) -> Result<Self> {
+        let exit_fee = vault.exit_fee(gross_assets, first_deposit_at, now)?;
+        let fee = exit_fee
+            .checked_add(vault.loss_penalty(gross_assets, shares, now)?)
+            .ok_or(VaultError::MathOverflow)?
+            .min(gross_assets);
+        Ok(Self {
+            gross_assets,
+            fee,
+            net_assets: gross_assets.checked_sub(fee).ok_or(VaultError::Underflow)?,
+        })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct VaultInfo {
+    pub total_assets: u64,
+    pub total_shares: u64,
+    pub deployed_assets: u64,
+    pub penalty_pool: u64,
+    pub insurance_fund: u64,
+    pub receipt_shares: u64,
+    pub price_per_share: u64,
+    pub current_epoch: u64,
+    pub epoch_started_at: i64,
+    pub epoch_deposits: u64,
+    pub epoch_withdrawals: u64,
+    /// `epoch_deposits - epoch_withdrawals`, negative on net outflow
+    pub epoch_net_flow: i128,
+    pub cumulative_deposits: u128,
+    pub cumulative_withdrawals: u128,
+    pub share_decimals: u8,
+}
+
+impl VaultInfo {
+    pub fn from_vault(vault: &Vault) -> Result<Self> {
+        Ok(Self {
+            total_assets: vault.total_assets,
+            total_shares: vault.total_shares,
+            deployed_assets: vault.deployed_assets,
+            penalty_pool: vault.penalty_pool,
+            insurance_fund: vault.insurance_fund,
+            receipt_shares: vault.receipt_shares,
+            price_per_share: vault.price_per_share()?,
+            current_epoch: vault.current_epoch,
+            epoch_started_at: vault.epoch_started_at,
+            epoch_deposits: vault.epoch_deposits,
+            epoch_withdrawals: vault.epoch_withdrawals,
+            epoch_net_flow: vault.epoch_deposits as i128 - vault.epoch_withdrawals as i128,
+            cumulative_deposits: vault.cumulative_deposits,
+            cumulative_withdrawals: vault.cumulative_withdrawals,
+            share_decimals: vault.share_decimals,
+        })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SolvencyStatus {
+    pub solvent: bool,
+    /// Assets missing from the backing, 0 when fully backed
+    pub shortfall: u64,
+    /// Whether `total_shares` and `total_assets` are both zero or both not
+    pub supply_consistent: bool,
+}
+
+impl SolvencyStatus {
+    /// Status of `vault` given the `token_balance` of its token account.
+    /// Earmarked tokens (penalty pool, match pool, insurance, vested) do not
+    /// count as backing.
+    pub fn from_vault(vault: &Vault, token_balance: u64) -> Self {
+        let backing = (token_balance as u128).saturating_sub(vault.earmarked_balance() as u128)
+            + vault.deployed_assets as u128;
+        let shortfall = (vault.total_assets as u128).saturating_sub(backing) as u64;
+        let supply_consistent = (vault.total_shares == 0) == (vault.total_assets == 0);
+        Self {
+            solvent: shortfall == 0 && supply_consistent,
+            shortfall,
+            supply_consistent,
+        }
+    }
+}
+
+// ============ Events ============
+
+#[event]
+pub struct DepositEvent {
+    pub user: Pubkey,
     pub amount: u64,
     pub shares: u64,
+    pub bonus_shares: u64,
+    /// Assets added from the sponsor match pool
+    pub matched: u64,
+    /// Deposit fee taken from `amount`, at the applied tier's `fee_bps`
+    pub fee: u64,
+    pub fee_bps: u16,
     pub timestamp: i64,
 }
 
@@ -302,6 +7570,371 @@ pub struct WithdrawEvent {
     pub user: Pubkey,
     pub assets: u64,
     pub shares: u64,
+    pub fee: u64,
+    /// Loss-window penalty left in the vault for remaining holders
+    pub loss_penalty: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalRequested {
+    pub user: Pubkey,
+    pub request_id: u64,
+    pub shares: u64,
+    pub claimable_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionImported {
+    pub user: Pubkey,
+    pub index: u32,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MigrationEnded {
+    pub burned_shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VestedExitStarted {
+    pub user: Pubkey,
+    pub shares: u64,
+    pub assets: u64,
+    pub fee: u64,
+    pub vesting_seconds: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VestedClaimed {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub remaining: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionSplit {
+    pub owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalCancelled {
+    pub user: Pubkey,
+    pub request_id: u64,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ClaimServed {
+    pub user: Pubkey,
+    pub seq: u64,
+    pub next_claimable_seq: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PullRequested {
+    pub user: Pubkey,
+    pub request_id: u64,
+    pub shortfall: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardClaimed {
+    pub user: Pubkey,
+    /// Keeper that triggered the payout, or the user claiming themselves
+    pub claimed_by: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Compounded {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ShutdownScheduled {
+    pub vault: Pubkey,
+    /// 0 when a schedule is cancelled
+    pub shutdown_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultShutdown {
+    pub vault: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeRecipientChanged {
+    pub old_recipient: Pubkey,
+    pub new_recipient: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Part of a `deposit_or_refund` that did not fit under the cap
+#[event]
+pub struct Refunded {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted after deposits and withdrawals at `EVENT_LEVEL_VERBOSE`
+#[event]
+pub struct VaultBalances {
+    pub price_per_share: u64,
+    pub total_assets: u64,
+    pub total_shares: u64,
+    /// Vault token account balance
+    pub idle: u64,
+    pub deployed_assets: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Rebalanced {
+    pub target_deployed_bps: u16,
+    pub deployed_before: u64,
+    pub deployed_after: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProtocolFeeMinted {
+    pub shares: u64,
+    /// Shares the protocol position holds afterwards
+    pub protocol_shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeesRealized {
+    pub recipient: Pubkey,
+    pub shares: u64,
+    pub assets: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DustSwept {
+    pub amount: u64,
+    pub treasury: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InsuranceUsed {
+    pub amount: u64,
+    /// Insurance left after covering the loss
+    pub insurance_fund: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LossReported {
+    pub loss: u64,
+    pub total_assets: u64,
+    pub loss_window_until: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Harvested {
+    pub profit: u64,
+    pub total_assets: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerTripped {
+    pub vault: Pubkey,
+    pub last_pps: u64,
+    pub price_per_share: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Recovered {
+    pub old_total_assets: u64,
+    pub new_total_assets: u64,
+    pub price_per_share: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct NavUpdated {
+    pub old_total_assets: u64,
+    pub new_total_assets: u64,
+    pub price_per_share: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AllocatedToStrategy {
+    pub amount: u64,
+    pub deployed_assets: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SharesGranted {
+    pub user: Pubkey,
+    pub shares: u64,
+    pub assets: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Locked {
+    pub user: Pubkey,
+    pub unlock_at: i64,
+    pub bonus_shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AutoUnpaused {
+    pub vault: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BootstrapEnded {
+    pub vault: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionMigrated {
+    pub user: Pubkey,
+    pub source_vault: Pubkey,
+    pub destination_vault: Pubkey,
+    pub assets: u64,
+    pub shares_burned: u64,
+    pub shares_minted: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PenaltyDistributed {
+    pub amount: u64,
+    pub total_assets: u64,
+    pub total_shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DexProgramChanged {
+    pub old_dex_program: Option<Pubkey>,
+    pub new_dex_program: Option<Pubkey>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct HookProgramChanged {
+    pub old_hook_program: Option<Pubkey>,
+    pub new_hook_program: Option<Pubkey>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SnapshotTaken {
+    pub id: u64,
+    pub total_shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct GuardianChanged {
+    pub old_guardian: Pubkey,
+    pub new_guardian: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityTransferProposed {
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityChanged {
+    pub old_authority: Pubkey,
+    pub new_authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityTransferCancelled {
+    pub cancelled: Pubkey,
+    pub guardian: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct KeeperChanged {
+    pub old_keeper: Pubkey,
+    pub new_keeper: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DestinationApproved {
+    pub vault: Pubkey,
+    pub destination: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DestinationRevoked {
+    pub vault: Pubkey,
+    pub destination: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InterestAccrued {
+    pub interest: u64,
+    pub supply_index: u128,
+    pub total_assets: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RiskOperatorChanged {
+    pub old_operator: Pubkey,
+    pub new_operator: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptMinted {
+    pub user: Pubkey,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptRedeemed {
+    pub user: Pubkey,
+    pub shares: u64,
     pub timestamp: i64,
 }
 
@@ -323,6 +7956,152 @@ pub enum VaultError {
     InsufficientAssets,
     #[msg("Math overflow")]
     MathOverflow,
+    #[msg("Division by zero")]
+    DivideByZero,
+    #[msg("Math underflow")]
+    Underflow,
     #[msg("Unauthorized")]
     Unauthorized,
+    #[msg("Deposit cooldown is still active")]
+    DepositCooldownActive,
+    #[msg("Invalid parameter")]
+    InvalidParameter,
+    #[msg("Lock duration outside the allowed range")]
+    InvalidLockDuration,
+    #[msg("Position is still locked")]
+    PositionLocked,
+    #[msg("Vaults have different token mints")]
+    MintMismatch,
+    #[msg("Operation is paused")]
+    OperationPaused,
+    #[msg("Penalty pool is empty")]
+    NoPenaltiesToDistribute,
+    #[msg("Vault has no shareholders")]
+    NoShareholders,
+    #[msg("Signer is not the pending keeper")]
+    NotPendingKeeper,
+    #[msg("Withdrawal exceeds delegate allowance")]
+    AllowanceExceeded,
+    #[msg("Signer is not the approved delegate")]
+    NotDelegate,
+    #[msg("Vault backing does not cover its accounted assets")]
+    SolvencyCheckFailed,
+    #[msg("Withdrawal would leave a dust position")]
+    DustPositionRemaining,
+    #[msg("Oracle account is missing or invalid")]
+    InvalidOracle,
+    #[msg("Oracle price deviates too far from the vault exchange rate")]
+    OracleDeviationTooHigh,
+    #[msg("Signer is not the keeper")]
+    NotKeeper,
+    #[msg("Vault is whitelisted; deposit with a merkle proof")]
+    WhitelistProofRequired,
+    #[msg("Invalid merkle proof")]
+    InvalidProof,
+    #[msg("Withdrawal queue is full")]
+    PendingQueueFull,
+    #[msg("Withdrawal request is still cooling down")]
+    WithdrawalNotReady,
+    #[msg("Signer does not own this withdrawal request")]
+    NotRequestOwner,
+    #[msg("Withdrawal exceeds idle liquidity; pass a withdrawal request account")]
+    WithdrawalRequestRequired,
+    #[msg("No pending rewards")]
+    NoPendingRewards,
+    #[msg("Withdrawal would push vault assets below the configured floor")]
+    WouldBreachFloor,
+    #[msg("Vault is shut down")]
+    VaultIsShutdown,
+    #[msg("Price per share is above the configured ceiling")]
+    PriceAboveCeiling,
+    #[msg("Price per share is below the configured floor")]
+    PriceBelowFloor,
+    #[msg("Auto-allocation is on; pass the strategy token account")]
+    StrategyAccountRequired,
+    #[msg("Granted shares are not fully backed by the transfer")]
+    GrantNotBacked,
+    #[msg("Parameters are locked against this change")]
+    ParamsLocked,
+    #[msg("Vault does not hold wrapped SOL")]
+    NotNativeMint,
+    #[msg("Vault token account is not the vault's registered, vault-owned account")]
+    InvalidVaultTokenAccount,
+    #[msg("Deposit would exceed the shares mintable this slot")]
+    SlotMintCapExceeded,
+    #[msg("Signer is neither the authority nor the risk operator")]
+    NotRiskOperator,
+    #[msg("Vault has outstanding shares but no assets")]
+    VaultInsolvent,
+    #[msg("Deposit is too small to mint any shares")]
+    ZeroSharesMinted,
+    #[msg("Withdrawal destination is not approved")]
+    DestinationNotApproved,
+    #[msg("Oracle price is older than the configured maximum staleness")]
+    StaleOracle,
+    #[msg("Harvest called before the minimum interval elapsed")]
+    HarvestTooSoon,
+    #[msg("Deposit would exceed the vault's total asset cap")]
+    GlobalCapExceeded,
+    #[msg("Vault holds no balance beyond what it owes")]
+    NoDustToSweep,
+    #[msg("Signer is not the pending authority")]
+    NotPendingAuthority,
+    #[msg("Signer is not the guardian")]
+    NotGuardian,
+    #[msg("Snapshot id was never taken or is no longer recorded")]
+    UnknownSnapshot,
+    #[msg("First remaining account must be the registered hook program")]
+    HookProgramMismatch,
+    #[msg("Earlier withdrawal requests must be claimed first")]
+    ClaimOutOfOrder,
+    #[msg("The head withdrawal request is still pending")]
+    QueueHeadPending,
+    #[msg("Previous vested exit has not been fully claimed")]
+    VestedExitActive,
+    #[msg("No migration import window is open")]
+    MigrationNotActive,
+    #[msg("Position was already imported")]
+    AlreadyImported,
+    #[msg("Too many open withdrawal requests for this user")]
+    TooManyRequests,
+    #[msg("DEX program is not the one registered on the vault")]
+    DexProgramMismatch,
+    #[msg("Destination program is not the migration program registered on the vault")]
+    MigrationProgramMismatch,
+    #[msg("Swap spent more than amount_in")]
+    SwapInputExceeded,
+    #[msg("Output below the minimum accepted")]
+    SlippageExceeded,
+    #[msg("Deposit would give this position too large a share of the vault")]
+    ConcentrationLimitExceeded,
+    #[msg("Withdrawal exceeds the vault's daily limit")]
+    DailyLimitExceeded,
+    #[msg("Vault is closed to deposits ahead of a scheduled shutdown")]
+    ShutdownScheduled,
+    #[msg("Withdrawal exceeds the bootstrap period's per-transaction limit")]
+    BootstrapWithdrawLimitExceeded,
+    #[msg("Strategy allocation is disabled during the bootstrap period")]
+    BootstrapAllocationDisabled,
+    #[msg("NAV update moves total assets by more than max_nav_change_bps")]
+    NavChangeTooLarge,
+    #[msg("NAV was updated too recently")]
+    NavUpdateTooSoon,
+    #[msg("A reward token account is required to pay out rewards")]
+    RewardAccountRequired,
+    #[msg("Deposit and withdrawal in the same slot are blocked")]
+    SameSlotActionBlocked,
+    #[msg("Fee tiers must number at most three, ascend by threshold and stay within 100%")]
+    InvalidFeeTiers,
+    #[msg("Circuit breaker is tripped; recover through recover_and_unpause")]
+    BreakerTripped,
+    #[msg("Circuit breaker is not tripped")]
+    BreakerNotTripped,
+    #[msg("Deposit exceeds the size the vault's age allows")]
+    ThrottleExceeded,
+    #[msg("PPS is stale; refresh it with update_nav or accrue_interest first")]
+    StalePps,
+    #[msg("Receipts are not enabled for this vault")]
+    ReceiptsDisabled,
+    #[msg("Migration target vault already has shares or assets")]
+    MigrationTargetNotEmpty,
 }
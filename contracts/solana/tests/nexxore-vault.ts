@@ -6,8 +6,14 @@ import {
   createMint, 
   createAccount, 
   mintTo,
-  getAccount 
+  getAccount,
+  createWrappedNativeAccount,
+  NATIVE_MINT,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  getAssociatedTokenAddressSync,
+  createTransferInstruction,
 } from "@solana/spl-token";
+import { keccak_256 } from "@noble/hashes/sha3";
 import { assert } from "chai";
 
 describe("nexxore-vault", () => {
@@ -15,6 +21,16 @@ describe("nexxore-vault", () => {
   anchor.setProvider(provider);
 
   const program = anchor.workspace.NexxoreVault as Program<NexxoreVault>;
+  // Second deployment of the same program, loaded at genesis from the
+  // `migration-target` build, for migrating positions between deployments
+  const MIGRATION_PROGRAM_ID = new anchor.web3.PublicKey(
+    "NexxVau1tV211111111111111111111111111111111"
+  );
+  const migrationProgram = new Program<NexxoreVault>(
+    program.idl,
+    MIGRATION_PROGRAM_ID,
+    provider
+  );
   
   let tokenMint: anchor.web3.PublicKey;
   let vaultTokenAccount: anchor.web3.PublicKey;
@@ -26,6 +42,135 @@ describe("nexxore-vault", () => {
   const authority = provider.wallet.publicKey;
   const user = anchor.web3.Keypair.generate();
 
+  // Shares minted per asset into an empty vault (10^SHARE_DECIMALS)
+  const SHARE_SCALE = 1_000;
+  // Fixed-point scale for price-per-share values
+  const PPS_SCALE = 1_000_000_000;
+
+  // Creates a fresh depositor with SOL for rent and underlying tokens
+  const createFundedUser = async (amount = 1_000_000_000) => {
+    const keypair = anchor.web3.Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(
+      keypair.publicKey,
+      2 * anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+
+    const tokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      tokenMint,
+      keypair.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      tokenMint,
+      tokenAccount,
+      authority,
+      amount
+    );
+
+    const [sharesPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), vault.toBuffer(), keypair.publicKey.toBuffer()],
+      program.programId
+    );
+
+    return { keypair, tokenAccount, userShares: sharesPda };
+  };
+
+  // Initializes a separate vault over a fresh mint, for tests that need an
+  // empty vault or change settings irreversibly
+  const createVault = async (
+    rounding = 0,
+    initialPps = PPS_SCALE,
+    bootstrapSeconds = 0,
+    shareDecimals: number | null = null
+  ) => {
+    const mint = await createMint(
+      provider.connection,
+      provider.wallet.payer,
+      authority,
+      null,
+      6
+    );
+    const [vaultPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), mint.toBuffer()],
+      program.programId
+    );
+    const tokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      vaultPda,
+      anchor.web3.Keypair.generate()
+    );
+    await program.methods
+      .initialize(
+        rounding,
+        new anchor.BN(initialPps),
+        new anchor.BN(bootstrapSeconds),
+        shareDecimals
+      )
+      .accounts({
+        vault: vaultPda,
+        tokenMint: mint,
+        vaultTokenAccount: tokenAccount,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    return { mint, vault: vaultPda, vaultTokenAccount: tokenAccount };
+  };
+
+  // Funds `owner` with `funded` tokens of the vault's mint and deposits
+  // `amount` of them, or only funds the account when `amount` is 0. Returns
+  // the deposit accounts for later deposits and withdrawals.
+  const fundAndDeposit = async (
+    vaultPda: anchor.web3.PublicKey,
+    owner: anchor.web3.Keypair,
+    amount: number,
+    funded = amount
+  ) => {
+    const { tokenMint: mint, vaultTokenAccount } = await program.account.vault.fetch(vaultPda);
+    const userTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      owner.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      userTokenAccount,
+      authority,
+      funded
+    );
+    const [userShares] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), vaultPda.toBuffer(), owner.publicKey.toBuffer()],
+      program.programId
+    );
+    const accounts = {
+      vault: vaultPda,
+      userShares,
+      userTokenAccount,
+      vaultTokenAccount,
+      user: owner.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    if (amount > 0) {
+      await program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(accounts)
+        .signers([owner])
+        .rpc();
+    }
+    return accounts;
+  };
+
   before(async () => {
     // Airdrop SOL to user
     const airdropSig = await provider.connection.requestAirdrop(
@@ -78,7 +223,7 @@ describe("nexxore-vault", () => {
 
   it("Initializes the vault", async () => {
     await program.methods
-      .initialize()
+      .initialize(0, new anchor.BN(PPS_SCALE), new anchor.BN(0), null) // ROUNDING_FAVOR_VAULT
       .accounts({
         vault,
         tokenMint,
@@ -95,6 +240,7 @@ describe("nexxore-vault", () => {
     assert.equal(vaultAccount.totalAssets.toNumber(), 0);
     assert.equal(vaultAccount.totalShares.toNumber(), 0);
     assert.equal(vaultAccount.paused, false);
+    assert.equal(vaultAccount.rounding, 0);
   });
 
   it("Deposits tokens and mints shares", async () => {
@@ -123,8 +269,8 @@ describe("nexxore-vault", () => {
     const userSharesAccount = await program.account.userShares.fetch(userShares);
 
     assert.equal(vaultAccount.totalAssets.toNumber(), depositAmount.toNumber());
-    assert.equal(vaultAccount.totalShares.toNumber(), depositAmount.toNumber());
-    assert.equal(userSharesAccount.shares.toNumber(), depositAmount.toNumber());
+    assert.equal(vaultAccount.totalShares.toNumber(), depositAmount.toNumber() * SHARE_SCALE);
+    assert.equal(userSharesAccount.shares.toNumber(), depositAmount.toNumber() * SHARE_SCALE);
   });
 
   it("Calculates shares correctly for second deposit", async () => {
@@ -162,7 +308,7 @@ describe("nexxore-vault", () => {
 
   it("Withdraws assets and burns shares", async () => {
     const userSharesAccount = await program.account.userShares.fetch(userShares);
-    const sharesToBurn = new anchor.BN(50_000_000);
+    const sharesToBurn = new anchor.BN(50_000_000 * SHARE_SCALE);
 
     const userTokenBefore = await getAccount(provider.connection, userTokenAccount);
 
@@ -192,10 +338,10 @@ describe("nexxore-vault", () => {
 
   it("Pauses and unpauses the vault", async () => {
     await program.methods
-      .pause()
+      .pause(null)
       .accounts({
         vault,
-        authority,
+        operator: authority,
       })
       .rpc();
 
@@ -227,7 +373,7 @@ describe("nexxore-vault", () => {
       .unpause()
       .accounts({
         vault,
-        authority,
+        operator: authority,
       })
       .rpc();
 
@@ -275,4 +421,4547 @@ describe("nexxore-vault", () => {
       assert.include(err.toString(), "InsufficientShares");
     }
   });
+
+  it("Rejects a second deposit within the cooldown window", async () => {
+    const depositAccounts = {
+      vault,
+      userShares,
+      userTokenAccount,
+      vaultTokenAccount,
+      user: user.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+
+    await program.methods
+      .deposit(new anchor.BN(10_000_000))
+      .accounts(depositAccounts)
+      .signers([user])
+      .rpc();
+
+    await program.methods
+      .setDepositCooldown(new anchor.BN(3600))
+      .accounts({ vault, authority })
+      .rpc();
+
+    try {
+      await program.methods
+        .deposit(new anchor.BN(10_000_000))
+        .accounts(depositAccounts)
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "DepositCooldownActive");
+    }
+
+    await program.methods
+      .setDepositCooldown(new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Reports enabled features", async () => {
+    const features = await program.methods
+      .getFeatures()
+      .accounts({ vault })
+      .view();
+
+    assert.equal(features.depositsPaused, false);
+    assert.equal(features.depositCooldownOn, false);
+  });
+
+  it("Deposits and locks a position", async () => {
+    const locker = await createFundedUser();
+
+    await program.methods
+      .setLockConfig(new anchor.BN(60), new anchor.BN(30 * 86_400), 10)
+      .accounts({ vault, authority })
+      .rpc();
+
+    const vaultBefore = await program.account.vault.fetch(vault);
+    const depositAmount = new anchor.BN(20_000_000);
+    const lockSeconds = new anchor.BN(2 * 86_400);
+
+    await program.methods
+      .depositAndLock(depositAmount, lockSeconds)
+      .accounts({
+        vault,
+        userShares: locker.userShares,
+        userTokenAccount: locker.tokenAccount,
+        vaultTokenAccount,
+        user: locker.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([locker.keypair])
+      .rpc();
+
+    const position = await program.account.userShares.fetch(locker.userShares);
+    const baseShares = depositAmount
+      .mul(vaultBefore.totalShares)
+      .div(vaultBefore.totalAssets);
+    // 10 bps/day for 2 days
+    const bonus = baseShares.muln(20).divn(10_000);
+    assert.equal(position.shares.toString(), baseShares.add(bonus).toString());
+    assert(position.unlockAt.toNumber() > Date.now() / 1000);
+
+    try {
+      await program.methods
+        .withdraw(new anchor.BN(1))
+        .accounts({
+          vault,
+          userShares: locker.userShares,
+          userTokenAccount: locker.tokenAccount,
+          vaultTokenAccount,
+          user: locker.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([locker.keypair])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "PositionLocked");
+    }
+  });
+
+  it("Rejects a lock outside the configured range", async () => {
+    try {
+      await program.methods
+        .depositAndLock(new anchor.BN(10_000_000), new anchor.BN(10))
+        .accounts({
+          vault,
+          userShares,
+          userTokenAccount,
+          vaultTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidLockDuration");
+    }
+  });
+
+  it("Reports zero utilization with nothing deployed", async () => {
+    const utilization = await program.methods
+      .utilization()
+      .accounts({ vault })
+      .view();
+
+    assert.equal(utilization, 0);
+  });
+
+  it("Auto-unpauses deposits once the pause duration elapses", async () => {
+    const depositAccounts = {
+      vault,
+      userShares,
+      userTokenAccount,
+      vaultTokenAccount,
+      user: user.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+
+    await program.methods
+      .pause(new anchor.BN(2))
+      .accounts({ vault, operator: authority })
+      .rpc();
+
+    try {
+      await program.methods
+        .deposit(new anchor.BN(10_000_000))
+        .accounts(depositAccounts)
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "VaultPaused");
+    }
+
+    await new Promise((resolve) => setTimeout(resolve, 3000));
+
+    await program.methods
+      .deposit(new anchor.BN(10_000_000))
+      .accounts(depositAccounts)
+      .signers([user])
+      .rpc();
+
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.paused, false);
+    assert.equal(vaultAccount.autoUnpauseAt.toNumber(), 0);
+  });
+
+  it("Charges an exit fee that decays with holding time", async () => {
+    const holder = await createFundedUser();
+    const holderAccounts = {
+      vault,
+      userShares: holder.userShares,
+      userTokenAccount: holder.tokenAccount,
+      vaultTokenAccount,
+      user: holder.keypair.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+    const windowSeconds = 10;
+
+    await program.methods
+      .setExitFee(1_000, new anchor.BN(windowSeconds))
+      .accounts({ vault, authority })
+      .rpc();
+
+    await program.methods
+      .deposit(new anchor.BN(100_000_000))
+      .accounts({
+        ...holderAccounts,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    // Returns the fee in bps actually charged on a small withdrawal
+    const withdrawFeeBps = async () => {
+      const shares = new anchor.BN(10_000_000 * SHARE_SCALE);
+      const vaultBefore = await program.account.vault.fetch(vault);
+      const gross = shares.mul(vaultBefore.totalAssets).div(vaultBefore.totalShares);
+      const before = await getAccount(provider.connection, holder.tokenAccount);
+
+      await program.methods
+        .withdraw(shares)
+        .accounts(holderAccounts)
+        .signers([holder.keypair])
+        .rpc();
+
+      const after = await getAccount(provider.connection, holder.tokenAccount);
+      const fee = gross.sub(new anchor.BN((after.amount - before.amount).toString()));
+      return fee.muln(10_000).div(gross).toNumber();
+    };
+
+    const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+    // t = 0: close to the full fee
+    const feeAtStart = await withdrawFeeBps();
+    assert(feeAtStart > 700 && feeAtStart <= 1_000, `fee at start ${feeAtStart}`);
+
+    // t ~ half the window: roughly half the fee
+    await sleep((windowSeconds / 2) * 1000);
+    const feeAtHalf = await withdrawFeeBps();
+    assert(feeAtHalf > 100 && feeAtHalf < 700, `fee at half ${feeAtHalf}`);
+
+    // t > window: no fee
+    await sleep((windowSeconds / 2 + 2) * 1000);
+    const feeAfter = await withdrawFeeBps();
+    assert.equal(feeAfter, 0);
+
+    await program.methods
+      .setExitFee(0, new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Disables withdrawals via the paused ops bitmask", async () => {
+    const OP_WITHDRAW = 1 << 1;
+
+    await program.methods
+      .setPausedOps(OP_WITHDRAW)
+      .accounts({ vault, authority })
+      .rpc();
+
+    try {
+      await program.methods
+        .withdraw(new anchor.BN(1_000_000))
+        .accounts({
+          vault,
+          userShares,
+          userTokenAccount,
+          vaultTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "OperationPaused");
+    }
+
+    const features = await program.methods
+      .getFeatures()
+      .accounts({ vault })
+      .view();
+    assert.equal(features.withdrawalsPaused, true);
+
+    await program.methods
+      .setPausedOps(0)
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Distributes accumulated exit fees to remaining holders", async () => {
+    const vaultBefore = await program.account.vault.fetch(vault);
+    assert(vaultBefore.penaltyPool.toNumber() > 0);
+
+    await program.methods
+      .distributePenalties()
+      .accounts({ vault, authority })
+      .rpc();
+
+    const vaultAfter = await program.account.vault.fetch(vault);
+    assert.equal(vaultAfter.penaltyPool.toNumber(), 0);
+    assert.equal(
+      vaultAfter.totalAssets.toString(),
+      vaultBefore.totalAssets.add(vaultBefore.penaltyPool).toString()
+    );
+  });
+
+  it("Mints bonus shares during the early deposit window", async () => {
+    const early = await createFundedUser();
+    const bonusEnd = new anchor.BN(Math.floor(Date.now() / 1000) + 3600);
+
+    await program.methods
+      .setDepositBonus(bonusEnd, 500)
+      .accounts({ vault, authority })
+      .rpc();
+
+    const vaultBefore = await program.account.vault.fetch(vault);
+    const depositAmount = new anchor.BN(40_000_000);
+
+    await program.methods
+      .deposit(depositAmount)
+      .accounts({
+        vault,
+        userShares: early.userShares,
+        userTokenAccount: early.tokenAccount,
+        vaultTokenAccount,
+        user: early.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([early.keypair])
+      .rpc();
+
+    const position = await program.account.userShares.fetch(early.userShares);
+    const baseShares = depositAmount
+      .mul(vaultBefore.totalShares)
+      .div(vaultBefore.totalAssets);
+    const bonus = baseShares.muln(500).divn(10_000);
+    assert.equal(position.shares.toString(), baseShares.add(bonus).toString());
+
+    await program.methods
+      .setDepositBonus(new anchor.BN(0), 0)
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Bounds the deposit bonus and only lets it shrink once params are locked", async () => {
+    const { vault: bonusVault } = await createVault();
+    const admin = { vault: bonusVault, authority };
+    const bonusEnd = Math.floor(Date.now() / 1000) + 3600;
+    const setBonus = (endTime: number, bonusBps: number) =>
+      program.methods
+        .setDepositBonus(new anchor.BN(endTime), bonusBps)
+        .accounts(admin)
+        .rpc();
+
+    try {
+      await setBonus(bonusEnd, 10_001);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidParameter");
+    }
+    await setBonus(bonusEnd, 500);
+
+    await program.methods.lockParams().accounts(admin).rpc();
+    for (const [endTime, bonusBps] of [
+      [bonusEnd, 600],
+      [bonusEnd + 1, 500],
+    ]) {
+      try {
+        await setBonus(endTime, bonusBps);
+        assert.fail("Should have thrown");
+      } catch (err) {
+        assert.include(err.toString(), "ParamsLocked");
+      }
+    }
+    await setBonus(bonusEnd - 1, 200);
+    const vaultAccount = await program.account.vault.fetch(bonusVault);
+    assert.equal(vaultAccount.bonusBps, 200);
+  });
+
+  it("Reports APY from the two latest PPS checkpoints", async () => {
+    const [ppsHistory] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("pps_history"), vault.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .initPpsHistory()
+      .accounts({
+        vault,
+        ppsHistory,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .recordPps()
+      .accounts({ vault, ppsHistory, authority })
+      .rpc();
+
+    // A single checkpoint is not enough to annualize
+    let apy = await program.methods
+      .currentApyBps()
+      .accounts({ vault, ppsHistory })
+      .view();
+    assert.equal(apy, 0);
+
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    await program.methods
+      .recordPps()
+      .accounts({ vault, ppsHistory, authority })
+      .rpc();
+
+    // PPS did not move between the checkpoints
+    apy = await program.methods
+      .currentApyBps()
+      .accounts({ vault, ppsHistory })
+      .view();
+    assert.equal(apy, 0);
+
+    const history = await program.account.ppsHistory.fetch(ppsHistory);
+    assert.equal(history.count, 2);
+  });
+
+  it("Rotates the keeper in two steps", async () => {
+    const newKeeper = anchor.web3.Keypair.generate();
+
+    await program.methods
+      .proposeKeeper(newKeeper.publicKey)
+      .accounts({ vault, authority })
+      .rpc();
+
+    // The current keeper stays in place until the new one accepts
+    let vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.keeper.toString(), authority.toString());
+    assert.equal(
+      vaultAccount.pendingKeeper.toString(),
+      newKeeper.publicKey.toString()
+    );
+
+    await program.methods
+      .acceptKeeper()
+      .accounts({ vault, newKeeper: newKeeper.publicKey })
+      .signers([newKeeper])
+      .rpc();
+
+    vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.keeper.toString(), newKeeper.publicKey.toString());
+    assert.equal(
+      vaultAccount.pendingKeeper.toString(),
+      anchor.web3.PublicKey.default.toString()
+    );
+
+    await program.methods
+      .setKeeper(authority)
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Lets an approved delegate withdraw within its allowance", async () => {
+    const delegate = anchor.web3.Keypair.generate();
+    const [withdrawDelegate] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("withdraw_delegate"), vault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .approveWithdrawDelegate(delegate.publicKey, new anchor.BN(5_000_000))
+      .accounts({
+        vault,
+        withdrawDelegate,
+        owner: user.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const delegatedAccounts = {
+      vault,
+      owner: user.publicKey,
+      withdrawDelegate,
+      userShares,
+      ownerTokenAccount: userTokenAccount,
+      vaultTokenAccount,
+      delegate: delegate.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    const ownerBefore = await getAccount(provider.connection, userTokenAccount);
+    await program.methods
+      .withdrawDelegated(new anchor.BN(1_000_000 * SHARE_SCALE))
+      .accounts(delegatedAccounts)
+      .signers([delegate])
+      .rpc();
+    const ownerAfter = await getAccount(provider.connection, userTokenAccount);
+    const paid = new anchor.BN((ownerAfter.amount - ownerBefore.amount).toString());
+
+    const approval = await program.account.withdrawDelegate.fetch(withdrawDelegate);
+    assert.equal(
+      approval.allowance.toString(),
+      new anchor.BN(5_000_000).sub(paid).toString()
+    );
+
+    try {
+      await program.methods
+        .withdrawDelegated(new anchor.BN(10_000_000 * SHARE_SCALE))
+        .accounts(delegatedAccounts)
+        .signers([delegate])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "AllowanceExceeded");
+    }
+  });
+
+  it("Rejects a partial withdrawal that leaves a dust position", async () => {
+    const position = await program.account.userShares.fetch(userShares);
+
+    await program.methods
+      .setMinPositionShares(new anchor.BN(1_000_000))
+      .accounts({ vault, authority })
+      .rpc();
+
+    try {
+      await program.methods
+        .withdraw(position.shares.subn(1))
+        .accounts({
+          vault,
+          userShares,
+          userTokenAccount,
+          vaultTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "DustPositionRemaining");
+    }
+
+    await program.methods
+      .setMinPositionShares(new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Rolls the epoch and snapshots its flows", async () => {
+    const before = await program.methods
+      .vaultInfo()
+      .accounts({ vault })
+      .view();
+    assert(before.epochDeposits.toNumber() > 0);
+
+    const [epochRecord] = anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("epoch_record"),
+        vault.toBuffer(),
+        before.currentEpoch.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+
+    await program.methods
+      .rollEpoch()
+      .accounts({
+        vault,
+        epochRecord,
+        keeper: authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const record = await program.account.epochRecord.fetch(epochRecord);
+    assert.equal(record.deposits.toString(), before.epochDeposits.toString());
+    assert.equal(record.withdrawals.toString(), before.epochWithdrawals.toString());
+
+    const after = await program.methods
+      .vaultInfo()
+      .accounts({ vault })
+      .view();
+    assert.equal(after.currentEpoch.toNumber(), before.currentEpoch.toNumber() + 1);
+    assert.equal(after.epochDeposits.toNumber(), 0);
+    assert.equal(after.epochWithdrawals.toNumber(), 0);
+  });
+
+  it("Gates deposits behind a merkle whitelist", async () => {
+    const outsider = await createFundedUser();
+    const members = [
+      user.publicKey,
+      anchor.web3.Keypair.generate().publicKey,
+      anchor.web3.Keypair.generate().publicKey,
+      anchor.web3.Keypair.generate().publicKey,
+    ];
+
+    // Sorted-pair keccak tree, matching the on-chain verifier
+    const hashPair = (a: Buffer, b: Buffer) =>
+      Buffer.from(
+        keccak_256(Buffer.compare(a, b) <= 0 ? Buffer.concat([a, b]) : Buffer.concat([b, a]))
+      );
+    const leaves = members.map((key) => Buffer.from(keccak_256(key.toBuffer())));
+    const left = hashPair(leaves[0], leaves[1]);
+    const right = hashPair(leaves[2], leaves[3]);
+    const root = hashPair(left, right);
+    const userProof = [Array.from(leaves[1]), Array.from(right)];
+
+    await program.methods
+      .setWhitelistRoot(Array.from(root))
+      .accounts({ vault, authority })
+      .rpc();
+
+    const depositAccounts = {
+      vault,
+      userShares,
+      userTokenAccount,
+      vaultTokenAccount,
+      user: user.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+
+    try {
+      await program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts(depositAccounts)
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "WhitelistProofRequired");
+    }
+
+    await program.methods
+      .depositWithProof(new anchor.BN(1_000_000), userProof)
+      .accounts(depositAccounts)
+      .signers([user])
+      .rpc();
+
+    try {
+      await program.methods
+        .depositWithProof(new anchor.BN(1_000_000), userProof)
+        .accounts({
+          ...depositAccounts,
+          userShares: outsider.userShares,
+          userTokenAccount: outsider.tokenAccount,
+          user: outsider.keypair.publicKey,
+        })
+        .signers([outsider.keypair])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidProof");
+    }
+
+    await program.methods
+      .setWhitelistRoot(Array(32).fill(0))
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Caps pending withdrawal requests and supports cancel and claim", async () => {
+    const holder = await createFundedUser();
+    await program.methods
+      .deposit(new anchor.BN(10_000_000))
+      .accounts({
+        vault,
+        userShares: holder.userShares,
+        userTokenAccount: holder.tokenAccount,
+        vaultTokenAccount,
+        user: holder.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    await program.methods
+      .setWithdrawalQueue(new anchor.BN(0), new anchor.BN(1_000_000))
+      .accounts({ vault, authority })
+      .rpc();
+
+    const nextRequest = async () => {
+      const { nextWithdrawalRequestId } = await program.account.vault.fetch(vault);
+      const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("withdrawal_request"),
+          vault.toBuffer(),
+          nextWithdrawalRequestId.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+      return pda;
+    };
+    const request = (withdrawalRequest: anchor.web3.PublicKey, shares: number) =>
+      program.methods
+        .requestWithdrawal(new anchor.BN(shares))
+        .accounts({
+          vault,
+          userShares: holder.userShares,
+          withdrawalRequest,
+          user: holder.keypair.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([holder.keypair])
+        .rpc();
+
+    const first = await nextRequest();
+    await request(first, 600_000);
+
+    try {
+      await request(await nextRequest(), 600_000);
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "PendingQueueFull");
+    }
+
+    const before = await program.account.userShares.fetch(holder.userShares);
+    await program.methods
+      .cancelWithdrawal()
+      .accounts({
+        vault,
+        userShares: holder.userShares,
+        withdrawalRequest: first,
+        user: holder.keypair.publicKey,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    const restored = await program.account.userShares.fetch(holder.userShares);
+    assert.equal(restored.shares.toNumber(), before.shares.toNumber() + 600_000);
+    assert.equal((await program.account.vault.fetch(vault)).totalPendingWithdrawals.toNumber(), 0);
+
+    const second = await nextRequest();
+    await request(second, 600_000);
+    const balanceBefore = (await getAccount(provider.connection, holder.tokenAccount)).amount;
+    await program.methods
+      .claimWithdrawal()
+      .accounts({
+        vault,
+        userShares: holder.userShares,
+        withdrawalRequest: second,
+        userTokenAccount: holder.tokenAccount,
+        vaultTokenAccount,
+        user: holder.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    const balanceAfter = (await getAccount(provider.connection, holder.tokenAccount)).amount;
+    assert.isTrue(balanceAfter > balanceBefore);
+    assert.equal((await program.account.vault.fetch(vault)).totalPendingWithdrawals.toNumber(), 0);
+
+    await program.methods
+      .setWithdrawalQueue(new anchor.BN(0), new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Accrues rewards in proportion to share-seconds", async () => {
+    const early = await createFundedUser();
+    const late = await createFundedUser();
+    const depositFor = (holder: Awaited<ReturnType<typeof createFundedUser>>) =>
+      program.methods
+        .deposit(new anchor.BN(50_000_000))
+        .accounts({
+          vault,
+          userShares: holder.userShares,
+          userTokenAccount: holder.tokenAccount,
+          vaultTokenAccount,
+          user: holder.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([holder.keypair])
+        .rpc();
+    const settle = (holder: Awaited<ReturnType<typeof createFundedUser>>) =>
+      program.methods
+        .settle()
+        .accounts({ vault, userShares: holder.userShares, user: holder.keypair.publicKey })
+        .signers([holder.keypair])
+        .rpc();
+
+    await program.methods
+      .setRewardRate(new anchor.BN(1_000_000))
+      .accounts({ vault, authority })
+      .rpc();
+
+    await depositFor(early);
+    await new Promise((resolve) => setTimeout(resolve, 3000));
+    await depositFor(late);
+    await new Promise((resolve) => setTimeout(resolve, 3000));
+    await settle(early);
+    await settle(late);
+
+    const earlyPosition = await program.account.userShares.fetch(early.userShares);
+    const latePosition = await program.account.userShares.fetch(late.userShares);
+    assert.isTrue(latePosition.pendingRewards.gtn(0));
+    assert.isTrue(earlyPosition.pendingRewards.gt(latePosition.pendingRewards));
+
+    await program.methods
+      .setRewardRate(new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Reports max deposit and mint, zero while paused", async () => {
+    const newcomer = anchor.web3.Keypair.generate();
+    const viewAccounts = { vault, user: newcomer.publicKey, userShares: null };
+
+    const maxDeposit = await program.methods.maxDeposit().accounts(viewAccounts).view();
+    const maxMint = await program.methods.maxMint().accounts(viewAccounts).view();
+    assert.isTrue(maxDeposit.gtn(0));
+    assert.isTrue(maxMint.gtn(0));
+
+    await program.methods.pause(null).accounts({ vault, operator: authority }).rpc();
+    const paused = await program.methods.maxDeposit().accounts(viewAccounts).view();
+    assert.equal(paused.toNumber(), 0);
+    await program.methods.unpause().accounts({ vault, operator: authority }).rpc();
+  });
+
+  it("Queues the shortfall of a withdrawal beyond idle for a strategy pull", async () => {
+    const holder = await createFundedUser();
+    await program.methods
+      .deposit(new anchor.BN(10_000_000))
+      .accounts({
+        vault,
+        userShares: holder.userShares,
+        userTokenAccount: holder.tokenAccount,
+        vaultTokenAccount,
+        user: holder.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    // The keeper (authority here) custodies the strategy's token account
+    const strategyTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      tokenMint,
+      authority,
+      anchor.web3.Keypair.generate()
+    );
+    await program.methods
+      .setStrategy()
+      .accounts({ vault, strategyTokenAccount, authority })
+      .rpc();
+
+    const { penaltyPool } = await program.account.vault.fetch(vault);
+    const balance = (await getAccount(provider.connection, vaultTokenAccount)).amount;
+    const allocated = new anchor.BN((balance - BigInt(penaltyPool.toString())).toString()).subn(
+      1_000_000
+    );
+    await program.methods
+      .allocateToStrategy(allocated)
+      .accounts({
+        vault,
+        vaultTokenAccount,
+        strategyTokenAccount,
+        keeper: authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    await program.methods.setAutoPull(true).accounts({ vault, authority }).rpc();
+
+    const { nextWithdrawalRequestId } = await program.account.vault.fetch(vault);
+    const [withdrawalRequest] = anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("withdrawal_request"),
+        vault.toBuffer(),
+        nextWithdrawalRequestId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+    const position = await program.account.userShares.fetch(holder.userShares);
+    await program.methods
+      .withdraw(position.shares)
+      .accounts({
+        vault,
+        userShares: holder.userShares,
+        userTokenAccount: holder.tokenAccount,
+        vaultTokenAccount,
+        user: holder.keypair.publicKey,
+        withdrawalRequest,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    const request = await program.account.withdrawalRequest.fetch(withdrawalRequest);
+    assert.isTrue(request.shares.gtn(0));
+    let vaultAccount = await program.account.vault.fetch(vault);
+    assert.isTrue(vaultAccount.requestedPull.gtn(0));
+
+    await program.methods
+      .returnFromStrategy(allocated)
+      .accounts({
+        vault,
+        sourceTokenAccount: strategyTokenAccount,
+        vaultTokenAccount,
+        keeper: authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    await program.methods
+      .claimWithdrawal()
+      .accounts({
+        vault,
+        userShares: holder.userShares,
+        withdrawalRequest,
+        userTokenAccount: holder.tokenAccount,
+        vaultTokenAccount,
+        user: holder.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.requestedPull.toNumber(), 0);
+    assert.equal(vaultAccount.deployedAssets.toNumber(), 0);
+    assert.equal(vaultAccount.totalPendingWithdrawals.toNumber(), 0);
+
+    await program.methods.setAutoPull(false).accounts({ vault, authority }).rpc();
+  });
+
+  it("Lets the keeper pay out a user's settled rewards", async () => {
+    const holder = await createFundedUser();
+    await program.methods
+      .deposit(new anchor.BN(50_000_000))
+      .accounts({
+        vault,
+        userShares: holder.userShares,
+        userTokenAccount: holder.tokenAccount,
+        vaultTokenAccount,
+        user: holder.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    const rewardMint = await createMint(
+      provider.connection,
+      provider.wallet.payer,
+      authority,
+      null,
+      6
+    );
+    const rewardTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      rewardMint,
+      vault,
+      anchor.web3.Keypair.generate()
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      rewardMint,
+      rewardTokenAccount,
+      authority,
+      1_000_000_000_000
+    );
+    const userRewardTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      rewardMint,
+      holder.keypair.publicKey
+    );
+
+    await program.methods
+      .setRewardToken()
+      .accounts({ vault, rewardMint, rewardTokenAccount, authority })
+      .rpc();
+    await program.methods
+      .setRewardRate(new anchor.BN(1_000_000))
+      .accounts({ vault, authority })
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const claimAccounts = {
+      vault,
+      userShares: holder.userShares,
+      rewardTokenAccount,
+      userRewardTokenAccount,
+      keeper: authority,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+    // Stop emissions so the claim below settles everything there is
+    await program.methods
+      .setRewardRate(new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+    await program.methods
+      .claimRewardFor(holder.keypair.publicKey)
+      .accounts(claimAccounts)
+      .rpc();
+
+    const paid = (await getAccount(provider.connection, userRewardTokenAccount)).amount;
+    assert.isTrue(paid > BigInt(0));
+    const position = await program.account.userShares.fetch(holder.userShares);
+    assert.equal(position.pendingRewards.toNumber(), 0);
+
+    // Nothing left to pay once the settled amount is out
+    try {
+      await program.methods
+        .claimRewardFor(holder.keypair.publicKey)
+        .accounts(claimAccounts)
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "NoPendingRewards");
+    }
+  });
+
+  it("Rejects withdrawals that breach the vault asset floor", async () => {
+    const { totalAssets } = await program.account.vault.fetch(vault);
+    await program.methods
+      .setMinVaultAssets(totalAssets)
+      .accounts({ vault, authority })
+      .rpc();
+
+    try {
+      await program.methods
+        .withdraw(new anchor.BN(1_000 * SHARE_SCALE))
+        .accounts({
+          vault,
+          userShares,
+          userTokenAccount,
+          vaultTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "WouldBreachFloor");
+    }
+
+    await program.methods
+      .setMinVaultAssets(new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Rejects deposits while PPS is above the configured ceiling", async () => {
+    const { pricePerShare } = await program.methods
+      .vaultInfo()
+      .accounts({ vault })
+      .view();
+    await program.methods
+      .setPpsBounds(new anchor.BN(0), pricePerShare.subn(1))
+      .accounts({ vault, authority })
+      .rpc();
+
+    try {
+      await program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts({
+          vault,
+          userShares,
+          userTokenAccount,
+          vaultTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "PriceAboveCeiling");
+    }
+
+    await program.methods
+      .setPpsBounds(new anchor.BN(0), new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Pays all remaining assets to the final withdrawer", async () => {
+    // A separate vault so this holder can be the last one out
+    const { vault: soloVault, vaultTokenAccount: soloVaultTokenAccount } = await createVault();
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } =
+      await fundAndDeposit(soloVault, user, 10_000_000);
+
+    const withdrawAccounts = {
+      vault: soloVault,
+      userShares: holderShares,
+      userTokenAccount: holderTokenAccount,
+      vaultTokenAccount: soloVaultTokenAccount,
+      user: user.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+    await program.methods
+      .withdraw(new anchor.BN(3_333_333 * SHARE_SCALE))
+      .accounts(withdrawAccounts)
+      .signers([user])
+      .rpc();
+    await program.methods
+      .withdraw(new anchor.BN(3_333_333 * SHARE_SCALE))
+      .accounts(withdrawAccounts)
+      .signers([user])
+      .rpc();
+
+    const { shares } = await program.account.userShares.fetch(holderShares);
+    await program.methods
+      .withdraw(shares)
+      .accounts(withdrawAccounts)
+      .signers([user])
+      .rpc();
+
+    const soloAccount = await program.account.vault.fetch(soloVault);
+    assert.equal(soloAccount.totalShares.toNumber(), 0);
+    assert.equal(soloAccount.totalAssets.toNumber(), 0);
+  });
+
+  it("Updates the fee recipient only to an account of the vault mint", async () => {
+    const treasury = anchor.web3.Keypair.generate();
+    const feeRecipient = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      tokenMint,
+      treasury.publicKey
+    );
+    await program.methods
+      .setFeeRecipient()
+      .accounts({ vault, feeRecipient, authority })
+      .rpc();
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.feeRecipient.toString(), feeRecipient.toString());
+
+    const otherMint = await createMint(
+      provider.connection,
+      provider.wallet.payer,
+      authority,
+      null,
+      6
+    );
+    const wrongAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      otherMint,
+      treasury.publicKey
+    );
+    try {
+      await program.methods
+        .setFeeRecipient()
+        .accounts({ vault, feeRecipient: wrongAccount, authority })
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "MintMismatch");
+    }
+  });
+
+  it("Auto-allocates part of each deposit to the strategy", async () => {
+    const { strategyTokenAccount, deployedAssets } = await program.account.vault.fetch(vault);
+    await program.methods
+      .setAllocationConfig(5_000, 0)
+      .accounts({ vault, authority })
+      .rpc();
+
+    await program.methods
+      .deposit(new anchor.BN(2_000_000))
+      .accounts({
+        vault,
+        userShares,
+        userTokenAccount,
+        vaultTokenAccount,
+        user: user.publicKey,
+        strategyTokenAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(
+      vaultAccount.deployedAssets.toNumber(),
+      deployedAssets.toNumber() + 1_000_000
+    );
+
+    await program.methods
+      .returnFromStrategy(new anchor.BN(1_000_000))
+      .accounts({
+        vault,
+        sourceTokenAccount: strategyTokenAccount,
+        vaultTokenAccount,
+        keeper: authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    await program.methods
+      .setAllocationConfig(0, 0)
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Summarizes a user's position in one read", async () => {
+    const summary = await program.methods
+      .positionSummary()
+      .accounts({ vault, user: user.publicKey, userShares })
+      .view();
+    const position = await program.account.userShares.fetch(userShares);
+    assert.isTrue(summary.shares.eq(position.shares));
+    assert.isTrue(summary.assetValue.gtn(0));
+    assert.equal(summary.unlockAt.toNumber(), position.unlockAt.toNumber());
+    assert.equal(summary.isLocked, false);
+
+    const empty = await program.methods
+      .positionSummary()
+      .accounts({ vault, user: anchor.web3.Keypair.generate().publicKey, userShares: null })
+      .view();
+    assert.equal(empty.shares.toNumber(), 0);
+  });
+
+  it("Grants shares backed by an authority transfer", async () => {
+    const authorityTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      tokenMint,
+      authority
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      tokenMint,
+      authorityTokenAccount,
+      authority,
+      10_000_000
+    );
+
+    const grantee = anchor.web3.Keypair.generate().publicKey;
+    const [granteeShares] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), vault.toBuffer(), grantee.toBuffer()],
+      program.programId
+    );
+    const before = await program.account.vault.fetch(vault);
+
+    await program.methods
+      .grantShares(grantee, new anchor.BN(1_000_000))
+      .accounts({
+        vault,
+        userShares: granteeShares,
+        authorityTokenAccount,
+        vaultTokenAccount,
+        authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const after = await program.account.vault.fetch(vault);
+    const position = await program.account.userShares.fetch(granteeShares);
+    assert.equal(position.shares.toNumber(), 1_000_000);
+    assert.equal(after.totalShares.toNumber(), before.totalShares.toNumber() + 1_000_000);
+    // Backing is rounded up, so PPS never drops
+    const spent = 10_000_000 - Number((await getAccount(provider.connection, authorityTokenAccount)).amount);
+    assert.equal(after.totalAssets.toNumber(), before.totalAssets.toNumber() + spent);
+    assert.isTrue(
+      after.totalAssets.mul(before.totalShares).gte(before.totalAssets.mul(after.totalShares))
+    );
+  });
+
+  it("Blocks worsening exit terms once params are locked", async () => {
+    const { vault: lockedVault } = await createVault();
+    const admin = { vault: lockedVault, authority };
+
+    await program.methods
+      .setExitFee(100, new anchor.BN(86_400))
+      .accounts(admin)
+      .rpc();
+    await program.methods.lockParams().accounts(admin).rpc();
+
+    try {
+      await program.methods
+        .setExitFee(200, new anchor.BN(86_400))
+        .accounts(admin)
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "ParamsLocked");
+    }
+
+    // Lowering the fee is still allowed
+    await program.methods
+      .setExitFee(50, new anchor.BN(86_400))
+      .accounts(admin)
+      .rpc();
+    const vaultAccount = await program.account.vault.fetch(lockedVault);
+    assert.equal(vaultAccount.maxExitFeeBps, 50);
+  });
+
+  it("Withdraws from a wSOL vault as native SOL", async () => {
+    const [solVault] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), NATIVE_MINT.toBuffer()],
+      program.programId
+    );
+    const solVaultTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      NATIVE_MINT,
+      solVault,
+      anchor.web3.Keypair.generate()
+    );
+    await program.methods
+      .initialize(0, new anchor.BN(PPS_SCALE), new anchor.BN(0), null)
+      .accounts({
+        vault: solVault,
+        tokenMint: NATIVE_MINT,
+        vaultTokenAccount: solVaultTokenAccount,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const depositAmount = anchor.web3.LAMPORTS_PER_SOL / 2;
+    const wsolAccount = await createWrappedNativeAccount(
+      provider.connection,
+      provider.wallet.payer,
+      user.publicKey,
+      depositAmount
+    );
+    const [solShares] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), solVault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({
+        vault: solVault,
+        userShares: solShares,
+        userTokenAccount: wsolAccount,
+        vaultTokenAccount: solVaultTokenAccount,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const recipient = anchor.web3.Keypair.generate().publicKey;
+    const [unwrapAccount] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("unwrap"), solVault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const withdrawSolTo = (approvedDestination: anchor.web3.PublicKey | null) =>
+      program.methods
+        .withdrawSolTo(new anchor.BN(depositAmount * SHARE_SCALE), recipient)
+        .accounts({
+          vault: solVault,
+          userShares: solShares,
+          tokenMint: NATIVE_MINT,
+          vaultTokenAccount: solVaultTokenAccount,
+          unwrapAccount,
+          recipientWallet: recipient,
+          approvedDestination,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+    // A third-party wallet needs its wSOL associated token account approved
+    const admin = { vault: solVault, authority };
+    await program.methods.setRequireApprovedDestination(true).accounts(admin).rpc();
+    try {
+      await withdrawSolTo(null);
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "DestinationNotApproved");
+    }
+
+    const recipientAta = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      NATIVE_MINT,
+      recipient
+    );
+    const [approvedDestination] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("approved_destination"), solVault.toBuffer(), recipientAta.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .approveDestination()
+      .accounts({
+        vault: solVault,
+        destination: recipientAta,
+        approvedDestination,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+    await withdrawSolTo(approvedDestination);
+
+    const received = await provider.connection.getBalance(recipient);
+    assert.isAtLeast(received, depositAmount);
+    assert.isNull(await provider.connection.getAccountInfo(unwrapAccount));
+  });
+
+  it("Loses less to rounding with scaled share precision", async () => {
+    const { vault: scaledVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(scaledVault, user, 0, 20_000_000);
+    const admin = { vault: scaledVault, authority };
+
+    // Push PPS off 1.0 by folding an exit fee back into the vault
+    await program.methods.setExitFee(1_000, new anchor.BN(86_400)).accounts(admin).rpc();
+    await program.methods
+      .deposit(new anchor.BN(10_000_000))
+      .accounts(holderAccounts)
+      .signers([user])
+      .rpc();
+    await program.methods
+      .withdraw(new anchor.BN(5_000_000 * SHARE_SCALE))
+      .accounts(holderAccounts)
+      .signers([user])
+      .rpc();
+    await program.methods.distributePenalties().accounts(admin).rpc();
+
+    const { totalAssets, totalShares } = await program.account.vault.fetch(scaledVault);
+    const before = await program.account.userShares.fetch(holderAccounts.userShares);
+    const amount = new anchor.BN(7);
+    await program.methods.deposit(amount).accounts(holderAccounts).signers([user]).rpc();
+    const after = await program.account.userShares.fetch(holderAccounts.userShares);
+    const minted = after.shares.sub(before.shares);
+
+    // Loss = amount - value of minted shares, compared as fractions:
+    // scaled (minted / totalShares) vs unscaled (totalShares / SHARE_SCALE)
+    const unscaledTotal = totalShares.divn(SHARE_SCALE);
+    const unscaledMinted = amount.mul(unscaledTotal).div(totalAssets);
+    const scaledLoss = amount.mul(totalShares).sub(minted.mul(totalAssets));
+    const unscaledLoss = amount.mul(unscaledTotal).sub(unscaledMinted.mul(totalAssets));
+    assert.isTrue(scaledLoss.mul(unscaledTotal).lt(unscaledLoss.mul(totalShares)));
+  });
+
+  it("Caps the shares deposits can mint within a slot", async () => {
+    const holder = await createFundedUser();
+    const holderAccounts = {
+      vault,
+      userShares: holder.userShares,
+      userTokenAccount: holder.tokenAccount,
+      vaultTokenAccount,
+      user: holder.keypair.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+
+    await program.methods
+      .setMaxSharesPerSlot(new anchor.BN(SHARE_SCALE))
+      .accounts({ vault, authority })
+      .rpc();
+    try {
+      await program.methods
+        .deposit(new anchor.BN(10_000_000))
+        .accounts(holderAccounts)
+        .signers([holder.keypair])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "SlotMintCapExceeded");
+    }
+
+    await program.methods
+      .setMaxSharesPerSlot(new anchor.BN(0))
+      .accounts({ vault, authority })
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(10_000_000))
+      .accounts(holderAccounts)
+      .signers([holder.keypair])
+      .rpc();
+  });
+
+  it("Reports max redeem and withdraw bounded by the asset floor", async () => {
+    const { vault: floorVault } = await createVault();
+    const { userShares: holderShares } = await fundAndDeposit(floorVault, user, 10_000_000);
+    const admin = { vault: floorVault, authority };
+    const viewAccounts = { vault: floorVault, user: user.publicKey, userShares: holderShares };
+
+    await program.methods.setMinVaultAssets(new anchor.BN(4_000_000)).accounts(admin).rpc();
+    const maxRedeem = await program.methods.maxRedeem().accounts(viewAccounts).view();
+    const maxWithdraw = await program.methods.maxWithdraw().accounts(viewAccounts).view();
+    assert.equal(maxRedeem.toNumber(), 6_000_000 * SHARE_SCALE);
+    assert.equal(maxWithdraw.toNumber(), 6_000_000);
+
+    const newcomer = anchor.web3.Keypair.generate();
+    const none = await program.methods
+      .maxRedeem()
+      .accounts({ vault: floorVault, user: newcomer.publicKey, userShares: null })
+      .view();
+    assert.equal(none.toNumber(), 0);
+
+    // OP_WITHDRAW
+    await program.methods.setPausedOps(1 << 1).accounts(admin).rpc();
+    const blocked = await program.methods.maxRedeem().accounts(viewAccounts).view();
+    assert.equal(blocked.toNumber(), 0);
+  });
+
+  it("Lets the risk operator pause and unpause but not administer", async () => {
+    const operator = anchor.web3.Keypair.generate();
+    await program.methods
+      .setRiskOperator(operator.publicKey)
+      .accounts({ vault, authority })
+      .rpc();
+
+    await program.methods
+      .pause(null)
+      .accounts({ vault, operator: operator.publicKey })
+      .signers([operator])
+      .rpc();
+    assert.equal((await program.account.vault.fetch(vault)).paused, true);
+    await program.methods
+      .unpause()
+      .accounts({ vault, operator: operator.publicKey })
+      .signers([operator])
+      .rpc();
+    assert.equal((await program.account.vault.fetch(vault)).paused, false);
+
+    try {
+      await program.methods
+        .setPausedOps(0)
+        .accounts({ vault, authority: operator.publicKey })
+        .signers([operator])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "Unauthorized");
+    }
+
+    const stranger = anchor.web3.Keypair.generate();
+    try {
+      await program.methods
+        .pause(null)
+        .accounts({ vault, operator: stranger.publicKey })
+        .signers([stranger])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "NotRiskOperator");
+    }
+
+    await program.methods
+      .setRiskOperator(anchor.web3.PublicKey.default)
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Realizes only the accrued fee shares, leaving the recipient's position", async () => {
+    const { vault: feeVault, vaultTokenAccount: feeTokenAccount } = await createVault();
+    const holderAccounts = await fundAndDeposit(feeVault, user, 0, 100_000_000_000);
+    const { userTokenAccount: feeRecipient, userShares: recipientShares } = holderAccounts;
+    const admin = { vault: feeVault, authority };
+
+    await program.methods.setExitFee(1_000, new anchor.BN(86_400)).accounts(admin).rpc();
+    await program.methods
+      .setFeeRecipient()
+      .accounts({ vault: feeVault, feeRecipient, authority })
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(100_000_000_000))
+      .accounts(holderAccounts)
+      .signers([user])
+      .rpc();
+    await program.methods.setManagementFee(10_000).accounts(admin).rpc();
+    await new Promise((resolve) => setTimeout(resolve, 3000));
+
+    await program.methods
+      .realizeFeeShares()
+      .accounts({
+        vault: feeVault,
+        feeRecipient,
+        vaultTokenAccount: feeTokenAccount,
+        recipient: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    // Only the fee shares were paid out; the deposit stays behind the exit fee
+    const recipientAccount = await getAccount(provider.connection, feeRecipient);
+    assert.isTrue(Number(recipientAccount.amount) > 0);
+    const vaultAccount = await program.account.vault.fetch(feeVault);
+    assert.equal(vaultAccount.unclaimedFeeShares.toNumber(), 0);
+    const position = await program.account.userShares.fetch(recipientShares);
+    assert.equal(position.shares.toNumber(), 100_000_000_000 * SHARE_SCALE);
+    assert.equal(vaultAccount.totalShares.toNumber(), 100_000_000_000 * SHARE_SCALE);
+  });
+
+  it("Rounds shares and assets up in favor of users", async () => {
+    // At 3 assets per share unit neither conversion divides evenly
+    const depositTen = async (rounding: number) => {
+      const { vault: roundingVault } = await createVault(rounding, 3 * PPS_SCALE);
+      const { userShares } = await fundAndDeposit(roundingVault, user, 10);
+      const quote = (shares: number) =>
+        program.methods
+          .quoteWithdraw(new anchor.BN(shares))
+          .accounts({ vault: roundingVault, user: user.publicKey, userShares: null })
+          .view();
+      const position = await program.account.userShares.fetch(userShares);
+      return { shares: position.shares.toNumber(), quote };
+    };
+
+    const favorVault = await depositTen(0);
+    assert.equal(favorVault.shares, Math.floor((10 * SHARE_SCALE) / 3));
+    assert.equal((await favorVault.quote(1)).grossAssets.toNumber(), 0);
+
+    const favorUser = await depositTen(1);
+    assert.equal(favorUser.shares, Math.ceil((10 * SHARE_SCALE) / 3));
+    assert.equal((await favorUser.quote(1)).grossAssets.toNumber(), 1);
+    // Rounded up or not, a redemption is never worth more than the vault holds
+    const oversized = await favorUser.quote(2 * favorUser.shares + 1);
+    assert.equal(oversized.grossAssets.toNumber(), 10);
+  });
+
+  it("Rejects an unknown rounding mode", async () => {
+    try {
+      await createVault(2);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidParameter");
+    }
+  });
+
+  it("Rejects deposits into a vault whose shares are backed by nothing", async () => {
+    // Rounding in favor of users lets partial redemptions drain the last asset
+    const { vault: drainedVault } = await createVault(1);
+    const first = await fundAndDeposit(drainedVault, user, 1, 10);
+    const second = await fundAndDeposit(drainedVault, provider.wallet.payer, 1, 10);
+
+    const one = new anchor.BN(1);
+    const half = new anchor.BN(SHARE_SCALE / 2);
+    await program.methods.withdraw(half).accounts(first).signers([user]).rpc();
+    await program.methods.withdraw(half).accounts(first).signers([user]).rpc();
+
+    const vaultAccount = await program.account.vault.fetch(drainedVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 0);
+    assert.equal(vaultAccount.totalShares.toNumber(), SHARE_SCALE);
+
+    const maxDeposit = await program.methods
+      .maxDeposit()
+      .accounts({ vault: drainedVault, user: authority, userShares: second.userShares })
+      .view();
+    assert.equal(maxDeposit.toNumber(), 0);
+    try {
+      await program.methods.deposit(one).accounts(second).rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "VaultInsolvent");
+    }
+  });
+
+  it("Reports division by zero and underflow as their own errors", async () => {
+    const { mint, vault: emptyVault, vaultTokenAccount: emptyTokenAccount } = await createVault();
+
+    // No shares outstanding, so valuing any shares divides by zero
+    try {
+      await program.methods
+        .quoteWithdraw(new anchor.BN(SHARE_SCALE))
+        .accounts({ vault: emptyVault, user: user.publicKey, userShares: null })
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "DivideByZero");
+    }
+
+    // Recalling more than was ever deployed
+    const keeperTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      keeperTokenAccount,
+      authority,
+      1_000
+    );
+    try {
+      await program.methods
+        .returnFromStrategy(new anchor.BN(1_000))
+        .accounts({
+          vault: emptyVault,
+          sourceTokenAccount: keeperTokenAccount,
+          vaultTokenAccount: emptyTokenAccount,
+          keeper: authority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "Underflow");
+    }
+  });
+
+  it("Compounds interest into the supply index and total assets", async () => {
+    const { vault: lendingVault } = await createVault();
+    const principal = new anchor.BN(1_000_000_000_000);
+    await fundAndDeposit(lendingVault, user, principal.toNumber());
+    await program.methods
+      .setInterestRate(1_000)
+      .accounts({ vault: lendingVault, authority })
+      .rpc();
+
+    // growth = 1_000 bps * elapsed / (10_000 * 365 days)
+    const period = new anchor.BN(10_000).mul(new anchor.BN(365 * 86_400));
+    let expected = await program.account.vault.fetch(lendingVault);
+    const indexScale = new anchor.BN(10).pow(new anchor.BN(18));
+    assert.isTrue(expected.supplyIndex.eq(indexScale));
+
+    for (let i = 0; i < 2; i++) {
+      await new Promise((resolve) => setTimeout(resolve, 2000));
+      await program.methods.accrueInterest().accounts({ vault: lendingVault }).rpc();
+      const after = await program.account.vault.fetch(lendingVault);
+      const elapsed = after.interestUpdatedAt.sub(expected.interestUpdatedAt);
+      assert.isTrue(elapsed.gtn(0));
+      const growth = elapsed.muln(1_000);
+
+      const index = expected.supplyIndex.add(expected.supplyIndex.mul(growth).div(period));
+      const totalAssets = expected.totalAssets.add(expected.totalAssets.mul(growth).div(period));
+      assert.equal(after.supplyIndex.toString(), index.toString());
+      assert.equal(after.totalAssets.toString(), totalAssets.toString());
+      assert.equal(
+        after.deployedAssets.toString(),
+        after.totalAssets.sub(principal).toString()
+      );
+      expected = after;
+    }
+  });
+
+  it("Flags deposits too small to mint a share", async () => {
+    const { vault: pricedVault } = await createVault();
+    const admin = { vault: pricedVault, authority };
+
+    // A 50% exit fee folded back into one share-unit of supply makes shares expensive
+    await program.methods.setExitFee(5_000, new anchor.BN(86_400)).accounts(admin).rpc();
+    await fundAndDeposit(pricedVault, user, 1);
+    const large = await fundAndDeposit(pricedVault, provider.wallet.payer, 10_000_000, 20_000_000);
+    const largePosition = await program.account.userShares.fetch(large.userShares);
+    await program.methods.withdraw(largePosition.shares).accounts(large).rpc();
+    await program.methods.distributePenalties().accounts(admin).rpc();
+
+    const view = (amount: number) =>
+      program.methods
+        .wouldRoundToZero(new anchor.BN(amount))
+        .accounts({ vault: pricedVault })
+        .view();
+    assert.isTrue(await view(1));
+    assert.isFalse(await view(10_000));
+
+    try {
+      await program.methods.deposit(new anchor.BN(1)).accounts(large).rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "ZeroSharesMinted");
+    }
+  });
+
+  it("Restricts withdraw_to to approved destinations when required", async () => {
+    const holder = await createFundedUser();
+    await program.methods
+      .deposit(new anchor.BN(10_000_000))
+      .accounts({
+        vault,
+        userShares: holder.userShares,
+        userTokenAccount: holder.tokenAccount,
+        vaultTokenAccount,
+        user: holder.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([holder.keypair])
+      .rpc();
+
+    const custodian = anchor.web3.Keypair.generate();
+    const custodianAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      tokenMint,
+      custodian.publicKey
+    );
+    const [approvedDestination] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("approved_destination"), vault.toBuffer(), custodianAccount.toBuffer()],
+      program.programId
+    );
+    const withdrawTo = (
+      destination: anchor.web3.PublicKey,
+      approved: anchor.web3.PublicKey | null
+    ) =>
+      program.methods
+        .withdrawTo(new anchor.BN(1_000_000 * SHARE_SCALE))
+        .accounts({
+          vault,
+          userShares: holder.userShares,
+          destination,
+          approvedDestination: approved,
+          vaultTokenAccount,
+          user: holder.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([holder.keypair])
+        .rpc();
+
+    await program.methods
+      .setRequireApprovedDestination(true)
+      .accounts({ vault, authority })
+      .rpc();
+    try {
+      await withdrawTo(custodianAccount, null);
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "DestinationNotApproved");
+    }
+    // The user's own account needs no approval
+    await withdrawTo(holder.tokenAccount, null);
+
+    await program.methods
+      .approveDestination()
+      .accounts({
+        vault,
+        destination: custodianAccount,
+        approvedDestination,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+    await withdrawTo(custodianAccount, approvedDestination);
+    const custodianBalance = await getAccount(provider.connection, custodianAccount);
+    assert.isTrue(Number(custodianBalance.amount) > 0);
+
+    await program.methods
+      .revokeDestination()
+      .accounts({ vault, approvedDestination, authority })
+      .rpc();
+    assert.isNull(await provider.connection.getAccountInfo(approvedDestination));
+    await program.methods
+      .setRequireApprovedDestination(false)
+      .accounts({ vault, authority })
+      .rpc();
+  });
+
+  it("Matches deposits from the sponsor pool until it runs dry", async () => {
+    const { mint, vault: matchVault, vaultTokenAccount: matchTokenAccount } =
+      await createVault();
+    const sponsorTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      sponsorTokenAccount,
+      authority,
+      5_000_000
+    );
+    const depositAccounts = await fundAndDeposit(matchVault, user, 0, 5_000_000);
+
+    await program.methods
+      .fundMatchPool(new anchor.BN(1_000_000))
+      .accounts({
+        vault: matchVault,
+        sponsorTokenAccount,
+        vaultTokenAccount: matchTokenAccount,
+        sponsor: authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    await program.methods
+      .setMatchBps(5_000)
+      .accounts({ vault: matchVault, authority })
+      .rpc();
+
+    // 50% of 1_000_000 is matched in full
+    await program.methods
+      .deposit(new anchor.BN(1_000_000))
+      .accounts(depositAccounts)
+      .signers([user])
+      .rpc();
+    let vaultAccount = await program.account.vault.fetch(matchVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 1_500_000);
+    assert.equal(vaultAccount.matchPool.toNumber(), 500_000);
+
+    // 50% of 2_000_000 exceeds the remaining pool, so only 500_000 is matched
+    await program.methods
+      .deposit(new anchor.BN(2_000_000))
+      .accounts(depositAccounts)
+      .signers([user])
+      .rpc();
+    vaultAccount = await program.account.vault.fetch(matchVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 4_000_000);
+    assert.equal(vaultAccount.matchPool.toNumber(), 0);
+    const position = await program.account.userShares.fetch(depositAccounts.userShares);
+    assert.equal(position.shares.toNumber(), 4_000_000 * SHARE_SCALE);
+
+    // An empty pool matches nothing
+    await program.methods
+      .deposit(new anchor.BN(1_000_000))
+      .accounts(depositAccounts)
+      .signers([user])
+      .rpc();
+    vaultAccount = await program.account.vault.fetch(matchVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 5_000_000);
+  });
+
+  it("Quotes deposits and withdrawals with the caller's exit fee", async () => {
+    const { vault: quoteVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(quoteVault, user, 0, 10_000_000);
+    const viewAccounts = {
+      vault: quoteVault,
+      user: user.publicKey,
+      userShares: holderAccounts.userShares,
+    };
+
+    await program.methods
+      .setExitFee(1_000, new anchor.BN(365 * 86_400))
+      .accounts({ vault: quoteVault, authority })
+      .rpc();
+    const depositQuote = await program.methods
+      .quoteDeposit(new anchor.BN(10_000_000))
+      .accounts({ ...viewAccounts, userShares: null })
+      .view();
+    assert.equal(depositQuote.netDeposit.toNumber(), 10_000_000);
+    assert.equal(depositQuote.fee.toNumber(), 0);
+    await program.methods
+      .deposit(new anchor.BN(10_000_000))
+      .accounts(holderAccounts)
+      .signers([user])
+      .rpc();
+    const position = await program.account.userShares.fetch(holderAccounts.userShares);
+    assert.isTrue(position.shares.eq(depositQuote.shares));
+
+    const shares = new anchor.BN(1_000_000 * SHARE_SCALE);
+    const withdrawQuote = await program.methods
+      .quoteWithdraw(shares)
+      .accounts(viewAccounts)
+      .view();
+    assert.equal(withdrawQuote.grossAssets.toNumber(), 1_000_000);
+    assert.isTrue(withdrawQuote.fee.gtn(0));
+    assert.isTrue(withdrawQuote.netAssets.eq(withdrawQuote.grossAssets.sub(withdrawQuote.fee)));
+
+    const before = await getAccount(provider.connection, holderAccounts.userTokenAccount);
+    await program.methods.withdraw(shares).accounts(holderAccounts).signers([user]).rpc();
+    const after = await getAccount(provider.connection, holderAccounts.userTokenAccount);
+    assert.equal(Number(after.amount - before.amount), withdrawQuote.netAssets.toNumber());
+  });
+
+  it("Rejects withdrawing against one vault with another vault's position", async () => {
+    const { vault: otherVault, vaultTokenAccount: otherTokenAccount } = await createVault();
+
+    // `userShares` is the main vault's PDA, so the seeds check rejects it
+    try {
+      await program.methods
+        .withdraw(new anchor.BN(1))
+        .accounts({
+          vault: otherVault,
+          userShares,
+          userTokenAccount,
+          vaultTokenAccount: otherTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintSeeds");
+    }
+  });
+
+  it("Accrues the management fee lazily on each deposit", async () => {
+    const { mint, vault: feeVault } = await createVault();
+    const treasury = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    const amount = new anchor.BN(1_000_000_000_000);
+    const depositAccounts = await fundAndDeposit(feeVault, user, 0, amount.toNumber() * 2);
+    await program.methods
+      .setFeeRecipient()
+      .accounts({ vault: feeVault, feeRecipient: treasury, authority })
+      .rpc();
+    await program.methods
+      .setManagementFee(1_000)
+      .accounts({ vault: feeVault, authority })
+      .rpc();
+
+    await program.methods.deposit(amount).accounts(depositAccounts).signers([user]).rpc();
+    const before = await program.account.vault.fetch(feeVault);
+    assert.equal(before.unclaimedFeeShares.toNumber(), 0);
+
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    await program.methods.deposit(amount).accounts(depositAccounts).signers([user]).rpc();
+    const after = await program.account.vault.fetch(feeVault);
+
+    // fee = assets * 1_000 bps * elapsed / (10_000 * 365 days), minted as
+    // shares worth `fee` after diluting the supply
+    const elapsed = after.lastFeeAccrual.sub(before.lastFeeAccrual);
+    assert.isTrue(elapsed.gtn(0));
+    const feeAssets = before.totalAssets
+      .muln(1_000)
+      .mul(elapsed)
+      .div(new anchor.BN(10_000).mul(new anchor.BN(365 * 86_400)));
+    const feeShares = feeAssets.mul(before.totalShares).div(before.totalAssets.sub(feeAssets));
+    assert.isTrue(feeShares.gtn(0));
+    assert.equal(after.unclaimedFeeShares.toString(), feeShares.toString());
+
+    // The second deposit was priced after the fee diluted the supply
+    const minted = amount.mul(before.totalShares.add(feeShares)).div(before.totalAssets);
+    assert.equal(
+      after.totalShares.toString(),
+      before.totalShares.add(feeShares).add(minted).toString()
+    );
+  });
+
+  it("Reports TVL as total assets when no quote oracle is set", async () => {
+    const { totalAssets } = await program.account.vault.fetch(vault);
+    const tvl = await program.methods
+      .tvlInQuote()
+      .accounts({ vault, quoteOracle: null })
+      .view();
+    assert.isTrue(tvl.eq(totalAssets));
+
+    try {
+      await program.methods
+        .setMaxOracleStaleness(new anchor.BN(-1))
+        .accounts({ vault, authority })
+        .rpc();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidParameter");
+    }
+  });
+
+  it("Rate-limits harvests to the minimum interval", async () => {
+    const { mint, vault: harvestVault, vaultTokenAccount: harvestTokenAccount } =
+      await createVault();
+    await fundAndDeposit(harvestVault, user, 10_000_000);
+    // The keeper (authority here) custodies the strategy's token account
+    const strategyTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      strategyTokenAccount,
+      authority,
+      10_000_000
+    );
+    await program.methods
+      .setMinHarvestInterval(new anchor.BN(3_600))
+      .accounts({ vault: harvestVault, authority })
+      .rpc();
+
+    const harvest = () =>
+      program.methods
+        .harvest(new anchor.BN(1_000_000))
+        .accounts({
+          vault: harvestVault,
+          sourceTokenAccount: strategyTokenAccount,
+          vaultTokenAccount: harvestTokenAccount,
+          keeper: authority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+    await harvest();
+    const vaultAccount = await program.account.vault.fetch(harvestVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 11_000_000);
+
+    try {
+      await harvest();
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "HarvestTooSoon");
+    }
+  });
+
+  it("Fills deposits up to the cap only when partial fills are allowed", async () => {
+    const { vault: cappedVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(cappedVault, user, 0, 10_000_000);
+    const { userTokenAccount: holderTokenAccount } = holderAccounts;
+    const deposit = (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+    const admin = { vault: cappedVault, authority };
+
+    await program.methods.setDepositCap(new anchor.BN(5_000_000), false).accounts(admin).rpc();
+    await deposit(3_000_000);
+    try {
+      await deposit(3_000_000);
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "GlobalCapExceeded");
+    }
+
+    await program.methods.setDepositCap(new anchor.BN(5_000_000), true).accounts(admin).rpc();
+    await deposit(3_000_000);
+    const vaultAccount = await program.account.vault.fetch(cappedVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 5_000_000);
+    const holderAccount = await getAccount(provider.connection, holderTokenAccount);
+    assert.equal(Number(holderAccount.amount), 5_000_000);
+
+    try {
+      await deposit(1);
+      assert.fail("Should have thrown error");
+    } catch (err) {
+      assert.include(err.toString(), "GlobalCapExceeded");
+    }
+  });
+
+  it("Emits rewards only within the funded schedule", async () => {
+    const { vault: emissionVault } = await createVault();
+    const rewardMint = await createMint(
+      provider.connection,
+      provider.wallet.payer,
+      authority,
+      null,
+      6
+    );
+    const rewardTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      rewardMint,
+      emissionVault,
+      anchor.web3.Keypair.generate()
+    );
+    const funderTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      rewardMint,
+      authority
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      rewardMint,
+      funderTokenAccount,
+      authority,
+      1_000_000
+    );
+    const { userShares: holderShares } = await fundAndDeposit(emissionVault, user, 10_000_000);
+    await program.methods
+      .setRewardToken()
+      .accounts({ vault: emissionVault, rewardMint, rewardTokenAccount, authority })
+      .rpc();
+
+    const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+    const settledRewardPerShare = async () => {
+      await program.methods
+        .settle()
+        .accounts({ vault: emissionVault, userShares: holderShares, user: user.publicKey })
+        .signers([user])
+        .rpc();
+      return (await program.account.vault.fetch(emissionVault)).rewardPerShareStored;
+    };
+
+    // Before: nothing scheduled, nothing emitted
+    await sleep(1000);
+    assert.equal((await settledRewardPerShare()).toNumber(), 0);
+
+    const rate = 1_000;
+    const duration = 4;
+    await program.methods
+      .setRewardSchedule(new anchor.BN(rate), new anchor.BN(duration))
+      .accounts({
+        vault: emissionVault,
+        funderTokenAccount,
+        rewardTokenAccount,
+        authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    const pool = await getAccount(provider.connection, rewardTokenAccount);
+    assert.equal(Number(pool.amount), rate * duration);
+
+    // During: partway through the emission
+    await sleep(2000);
+    const during = await settledRewardPerShare();
+    // rate * duration * REWARD_PRECISION / total_shares (10^10)
+    const full = new anchor.BN(rate * duration).mul(new anchor.BN(100));
+    assert.isTrue(during.gtn(0));
+    assert.isTrue(during.lt(full));
+
+    // After: exactly the scheduled amount, and no more once it has ended
+    await sleep(4000);
+    assert.equal((await settledRewardPerShare()).toString(), full.toString());
+    await sleep(2000);
+    assert.equal((await settledRewardPerShare()).toString(), full.toString());
+  });
+
+  it("Sweeps only the balance no one has a claim on", async () => {
+    const { mint, vault: dustVault, vaultTokenAccount: dustTokenAccount } =
+      await createVault();
+    const treasury = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    await program.methods
+      .setFeeRecipient()
+      .accounts({ vault: dustVault, feeRecipient: treasury, authority })
+      .rpc();
+
+    // A direct transfer bypasses deposit and is owed to no one
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      dustTokenAccount,
+      authority,
+      12_345
+    );
+    const sweepAccounts = {
+      vault: dustVault,
+      vaultTokenAccount: dustTokenAccount,
+      treasury,
+      authority,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+    await program.methods.sweepDust().accounts(sweepAccounts).rpc();
+    assert.equal(Number((await getAccount(provider.connection, treasury)).amount), 12_345);
+    assert.equal(Number((await getAccount(provider.connection, dustTokenAccount)).amount), 0);
+
+    try {
+      await program.methods.sweepDust().accounts(sweepAccounts).rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "NoDustToSweep");
+    }
+  });
+
+  it("Lets the guardian veto a pending authority transfer", async () => {
+    const { vault: govVault } = await createVault();
+    const guardian = anchor.web3.Keypair.generate();
+    const successor = anchor.web3.Keypair.generate();
+    await program.methods
+      .setGuardian(guardian.publicKey)
+      .accounts({ vault: govVault, authority })
+      .rpc();
+
+    try {
+      await program.methods
+        .proposeAuthority(guardian.publicKey)
+        .accounts({ vault: govVault, authority })
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidParameter");
+    }
+
+    await program.methods
+      .proposeAuthority(successor.publicKey)
+      .accounts({ vault: govVault, authority })
+      .rpc();
+    try {
+      await program.methods
+        .cancelAuthorityTransfer()
+        .accounts({ vault: govVault, guardian: successor.publicKey })
+        .signers([successor])
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "NotGuardian");
+    }
+    await program.methods
+      .cancelAuthorityTransfer()
+      .accounts({ vault: govVault, guardian: guardian.publicKey })
+      .signers([guardian])
+      .rpc();
+    try {
+      await program.methods
+        .acceptAuthority()
+        .accounts({ vault: govVault, newAuthority: successor.publicKey })
+        .signers([successor])
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "NotPendingAuthority");
+    }
+
+    await program.methods
+      .proposeAuthority(successor.publicKey)
+      .accounts({ vault: govVault, authority })
+      .rpc();
+    await program.methods
+      .acceptAuthority()
+      .accounts({ vault: govVault, newAuthority: successor.publicKey })
+      .signers([successor])
+      .rpc();
+    const vaultAccount = await program.account.vault.fetch(govVault);
+    assert.equal(vaultAccount.authority.toBase58(), successor.publicKey.toBase58());
+    assert.equal(
+      vaultAccount.pendingAuthority.toBase58(),
+      anchor.web3.PublicKey.default.toBase58()
+    );
+  });
+
+  it("Reads share balances as of each snapshot", async () => {
+    const { vault: snapVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(snapVault, user, 0, 3_000_000);
+    const { userShares: holderShares } = holderAccounts;
+    const deposit = (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+    const takeSnapshot = () =>
+      program.methods.takeSnapshot().accounts({ vault: snapVault, authority }).rpc();
+    const snapshotShare = (id: number) =>
+      program.methods
+        .snapshotShare(new anchor.BN(id))
+        .accounts({ vault: snapVault, user: user.publicKey, userShares: holderShares })
+        .view();
+
+    await takeSnapshot(); // 1: before any deposit
+    await deposit(1_000_000);
+    const afterFirst = (await program.account.userShares.fetch(holderShares)).shares;
+    await takeSnapshot(); // 2
+    await deposit(2_000_000);
+    await takeSnapshot(); // 3: no change since
+    const current = (await program.account.userShares.fetch(holderShares)).shares;
+
+    // Snapshot 1 was overwritten by the checkpoint taken at the second deposit
+    assert.equal((await snapshotShare(2)).toString(), afterFirst.toString());
+    assert.equal((await snapshotShare(3)).toString(), current.toString());
+
+    try {
+      await snapshotShare(4);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "UnknownSnapshot");
+    }
+  });
+
+  it("Requires the registered hook program on deposits", async () => {
+    const { vault: hookVault } = await createVault();
+    const depositAccounts = await fundAndDeposit(hookVault, user, 0, 1_000_000);
+
+    const hookProgram = anchor.web3.Keypair.generate().publicKey;
+    await program.methods
+      .setHookProgram(hookProgram)
+      .accounts({ vault: hookVault, authority })
+      .rpc();
+    try {
+      await program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts(depositAccounts)
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "HookProgramMismatch");
+    }
+
+    // Unregistering the hook lets plain deposits through again
+    await program.methods
+      .setHookProgram(null)
+      .accounts({ vault: hookVault, authority })
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(1_000_000))
+      .accounts(depositAccounts)
+      .signers([user])
+      .rpc();
+    const position = await program.account.userShares.fetch(depositAccounts.userShares);
+    assert.isTrue(position.shares.gtn(0));
+  });
+
+  it("Serves withdrawal claims in request order while liquidity is short", async () => {
+    const { mint, vault: queueVault, vaultTokenAccount: queueTokenAccount } =
+      await createVault();
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } =
+      await fundAndDeposit(queueVault, user, 10_000_000);
+
+    const requestPda = (id: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("withdrawal_request"),
+          queueVault.toBuffer(),
+          new anchor.BN(id).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+    const shares = new anchor.BN(3_000_000 * SHARE_SCALE);
+    for (const id of [0, 1]) {
+      await program.methods
+        .requestWithdrawal(shares)
+        .accounts({
+          vault: queueVault,
+          userShares: holderShares,
+          withdrawalRequest: requestPda(id),
+          user: user.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+    }
+
+    // Leave 4M idle: enough for one request, not both
+    const strategyTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority,
+      anchor.web3.Keypair.generate()
+    );
+    await program.methods
+      .setStrategy()
+      .accounts({ vault: queueVault, strategyTokenAccount, authority })
+      .rpc();
+    await program.methods
+      .allocateToStrategy(new anchor.BN(6_000_000))
+      .accounts({
+        vault: queueVault,
+        vaultTokenAccount: queueTokenAccount,
+        strategyTokenAccount,
+        keeper: authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const claim = (id: number) =>
+      program.methods
+        .claimWithdrawal()
+        .accounts({
+          vault: queueVault,
+          userShares: holderShares,
+          withdrawalRequest: requestPda(id),
+          userTokenAccount: holderTokenAccount,
+          vaultTokenAccount: queueTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    try {
+      await claim(1);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "ClaimOutOfOrder");
+    }
+
+    await claim(0);
+    assert.equal(
+      (await program.account.vault.fetch(queueVault)).nextClaimableSeq.toNumber(),
+      1
+    );
+
+    // Request #1 is the head now and still open, so it cannot be skipped
+    try {
+      await program.methods
+        .skipClosedRequest()
+        .accounts({ vault: queueVault, headRequest: requestPda(1) })
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "QueueHeadPending");
+    }
+  });
+
+  it("Ramps the deposit cap linearly over time", async () => {
+    const { vault: rampVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(rampVault, user, 0, 10_000_000);
+    const deposit = (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+    const expectCapExceeded = async (amount: number) => {
+      try {
+        await deposit(amount);
+        assert.fail("Should have thrown");
+      } catch (err) {
+        assert.include(err.toString(), "GlobalCapExceeded");
+      }
+    };
+    const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+    // 1M -> 3M over 6 seconds
+    await program.methods
+      .setDepositCapRamp(new anchor.BN(1_000_000), new anchor.BN(3_000_000), new anchor.BN(6))
+      .accounts({ vault: rampVault, authority })
+      .rpc();
+
+    // Start: only about `cap_start` fits
+    await deposit(1_000_000);
+    await expectCapExceeded(1_500_000);
+
+    // Midpoint: the cap has grown part of the way
+    await sleep(3000);
+    await deposit(500_000);
+    await expectCapExceeded(1_500_000);
+
+    // Completion: the cap stops at `cap_max`
+    await sleep(4000);
+    await deposit(1_500_000);
+    await expectCapExceeded(1);
+    assert.equal(
+      (await program.account.vault.fetch(rampVault)).totalAssets.toNumber(),
+      3_000_000
+    );
+  });
+
+  it("Keeps vault books consistent with balances across deposit and withdraw", async () => {
+    const { vault: ceiVault, vaultTokenAccount: ceiTokenAccount } = await createVault();
+    const holderAccounts = await fundAndDeposit(ceiVault, user, 0, 5_000_000);
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } = holderAccounts;
+    const expectConsistent = async () => {
+      const vaultAccount = await program.account.vault.fetch(ceiVault);
+      const position = await program.account.userShares.fetch(holderShares);
+      const balance = (await getAccount(provider.connection, ceiTokenAccount)).amount;
+      // Every token in the account is on the books, and every share is held
+      assert.equal(
+        vaultAccount.totalAssets.add(vaultAccount.penaltyPool).toString(),
+        balance.toString()
+      );
+      assert.equal(vaultAccount.totalShares.toString(), position.shares.toString());
+    };
+
+    await program.methods
+      .deposit(new anchor.BN(5_000_000))
+      .accounts(holderAccounts)
+      .signers([user])
+      .rpc();
+    await expectConsistent();
+
+    // The payout transfer is the last step; books already reflect it
+    await program.methods
+      .withdraw(new anchor.BN(2_000_000 * SHARE_SCALE))
+      .accounts({
+        vault: ceiVault,
+        userShares: holderShares,
+        userTokenAccount: holderTokenAccount,
+        vaultTokenAccount: ceiTokenAccount,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    await expectConsistent();
+    assert.equal(
+      Number((await getAccount(provider.connection, holderTokenAccount)).amount),
+      2_000_000
+    );
+  });
+
+  it("Mints the first deposit at the configured initial price per share", async () => {
+    // Continue a migrated vault's rate of 2.0
+    const { vault: pricedVault } = await createVault(0, 2 * PPS_SCALE);
+    const { userShares: holderShares } = await fundAndDeposit(pricedVault, user, 1_000_000);
+
+    // amount * SHARE_SCALE * PPS_SCALE / initial_pps
+    const position = await program.account.userShares.fetch(holderShares);
+    assert.equal(position.shares.toNumber(), (1_000_000 * SHARE_SCALE) / 2);
+
+    try {
+      await createVault(0, 0);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidParameter");
+    }
+  });
+
+  it("Splits part of a position into a new owner's account", async () => {
+    const { vault: splitVault } = await createVault();
+    const { userShares: holderShares } = await fundAndDeposit(splitVault, user, 1_000_000);
+    const sharesPda = (owner: anchor.web3.PublicKey) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("user_shares"), splitVault.toBuffer(), owner.toBuffer()],
+        program.programId
+      )[0];
+    const split = (
+      shares: anchor.BN,
+      newOwner: anchor.web3.PublicKey,
+      proof: number[][] | null = null
+    ) =>
+      program.methods
+        .splitPosition(shares, newOwner, proof)
+        .accounts({
+          vault: splitVault,
+          sourceShares: holderShares,
+          newShares: sharesPda(newOwner),
+          user: user.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+    const heir = anchor.web3.Keypair.generate().publicKey;
+    const moved = new anchor.BN(400_000 * SHARE_SCALE);
+    const before = await program.account.userShares.fetch(holderShares);
+    await split(moved, heir);
+
+    const remaining = await program.account.userShares.fetch(holderShares);
+    const created = await program.account.userShares.fetch(sharesPda(heir));
+    assert.equal(remaining.shares.toString(), before.shares.sub(moved).toString());
+    assert.equal(created.shares.toString(), moved.toString());
+    assert.equal(created.firstDepositAt.toString(), before.firstDepositAt.toString());
+    assert.equal(before.lifetimeDeposited.toNumber(), 1_000_000);
+    assert.equal(remaining.lifetimeDeposited.toNumber(), 600_000);
+    assert.equal(created.lifetimeDeposited.toNumber(), 400_000);
+    assert.equal(
+      (await program.account.vault.fetch(splitVault)).totalShares.toString(),
+      before.shares.toString()
+    );
+
+    try {
+      await split(remaining.shares.addn(1), anchor.web3.Keypair.generate().publicKey);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientShares");
+    }
+
+    // A whitelisted vault only splits to members; a one-leaf tree's root is the leaf
+    const member = anchor.web3.Keypair.generate().publicKey;
+    const outsider = anchor.web3.Keypair.generate().publicKey;
+    const admin = { vault: splitVault, authority };
+    await program.methods
+      .setWhitelistRoot(Array.from(keccak_256(member.toBuffer())))
+      .accounts(admin)
+      .rpc();
+    try {
+      await split(new anchor.BN(100_000 * SHARE_SCALE), outsider);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "WhitelistProofRequired");
+    }
+    try {
+      await split(new anchor.BN(100_000 * SHARE_SCALE), outsider, []);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidProof");
+    }
+    await split(new anchor.BN(100_000 * SHARE_SCALE), member, []);
+    const memberShares = await program.account.userShares.fetch(sharesPda(member));
+    assert.equal(memberShares.shares.toNumber(), 100_000 * SHARE_SCALE);
+  });
+
+  it("Pays a vested exit out linearly and in full at the end", async () => {
+    const { vault: vestVault, vaultTokenAccount: vestTokenAccount } = await createVault();
+    const holderAccounts = await fundAndDeposit(vestVault, user, 0, 1_000_003);
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } = holderAccounts;
+    const [vestedExit] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vested_exit"), vestVault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .deposit(new anchor.BN(1_000_003))
+      .accounts(holderAccounts)
+      .signers([user])
+      .rpc();
+
+    const { shares } = await program.account.userShares.fetch(holderShares);
+    await program.methods
+      .startVestedWithdrawal(shares, new anchor.BN(6))
+      .accounts({
+        vault: vestVault,
+        userShares: holderShares,
+        vestedExit,
+        user: user.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+    const burned = await program.account.vault.fetch(vestVault);
+    assert.equal(burned.totalShares.toNumber(), 0);
+    assert.equal(burned.vestedOwed.toNumber(), 1_000_003);
+
+    const claim = () =>
+      program.methods
+        .claimVested()
+        .accounts({
+          vault: vestVault,
+          vestedExit,
+          userTokenAccount: holderTokenAccount,
+          vaultTokenAccount: vestTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+    const balance = async () =>
+      Number((await getAccount(provider.connection, holderTokenAccount)).amount);
+    const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+    await sleep(2000);
+    await claim();
+    const partial = await balance();
+    assert.isTrue(partial > 0 && partial < 1_000_003);
+
+    // The final claim includes the rounding remainder
+    await sleep(5000);
+    await claim();
+    assert.equal(await balance(), 1_000_003);
+    assert.equal((await program.account.vault.fetch(vestVault)).vestedOwed.toNumber(), 0);
+
+    try {
+      await claim();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "ZeroAmount");
+    }
+  });
+
+  it("Derives the canonical vault and position addresses", async () => {
+    const addresses = await program.methods
+      .deriveAddresses(tokenMint, user.publicKey)
+      .accounts({})
+      .view();
+
+    const [expectedVault, vaultBump] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), tokenMint.toBuffer()],
+      program.programId
+    );
+    const [expectedShares, sharesBump] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), expectedVault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    assert.equal(addresses.vault.toBase58(), vault.toBase58());
+    assert.equal(addresses.vault.toBase58(), expectedVault.toBase58());
+    assert.equal(addresses.vaultBump, vaultBump);
+    assert.equal(addresses.userShares.toBase58(), expectedShares.toBase58());
+    assert.equal(addresses.userSharesBump, sharesBump);
+  });
+
+  it("Charges a penalty that stays in the vault during a loss window", async () => {
+    const { mint, vault: lossVault, vaultTokenAccount: lossTokenAccount } =
+      await createVault();
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } =
+      await fundAndDeposit(lossVault, user, 1_000_000);
+
+    const strategyTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority,
+      anchor.web3.Keypair.generate()
+    );
+    await program.methods
+      .setStrategy()
+      .accounts({ vault: lossVault, strategyTokenAccount, authority })
+      .rpc();
+    await program.methods
+      .allocateToStrategy(new anchor.BN(300_000))
+      .accounts({
+        vault: lossVault,
+        vaultTokenAccount: lossTokenAccount,
+        strategyTokenAccount,
+        keeper: authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    await program.methods
+      .setLossWindow(new anchor.BN(3_600), 500)
+      .accounts({ vault: lossVault, authority })
+      .rpc();
+    await program.methods
+      .reportLoss(new anchor.BN(100_000))
+      .accounts({ vault: lossVault, authority })
+      .rpc();
+
+    // 900k backs 10^9 shares; half of them are worth 450k, less 5%
+    await program.methods
+      .withdraw(new anchor.BN(500_000 * SHARE_SCALE))
+      .accounts({
+        vault: lossVault,
+        userShares: holderShares,
+        userTokenAccount: holderTokenAccount,
+        vaultTokenAccount: lossTokenAccount,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    assert.equal(
+      Number((await getAccount(provider.connection, holderTokenAccount)).amount),
+      427_500
+    );
+    // The 22,500 penalty stays with the remaining shares
+    assert.equal(
+      (await program.account.vault.fetch(lossVault)).totalAssets.toNumber(),
+      900_000 - 427_500
+    );
+  });
+
+  it("Imports v1 positions once each against the migration root", async () => {
+    const { mint, vault: v2Vault, vaultTokenAccount: v2TokenAccount } = await createVault();
+    const authorityTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authorityTokenAccount,
+      authority,
+      1_000_000
+    );
+
+    // keccak(index u32 LE, user, shares u64 LE) leaves, sorted-pair tree
+    const other = anchor.web3.Keypair.generate().publicKey;
+    const entries: [number, anchor.web3.PublicKey, anchor.BN][] = [
+      [0, user.publicKey, new anchor.BN(600_000 * SHARE_SCALE)],
+      [1, other, new anchor.BN(400_000 * SHARE_SCALE)],
+    ];
+    const leaves = entries.map(([index, owner, shares]) => {
+      const indexBytes = Buffer.alloc(4);
+      indexBytes.writeUInt32LE(index);
+      return Buffer.from(
+        keccak_256(
+          Buffer.concat([indexBytes, owner.toBuffer(), shares.toArrayLike(Buffer, "le", 8)])
+        )
+      );
+    });
+    const [a, b] = leaves;
+    const root = Buffer.from(
+      keccak_256(Buffer.compare(a, b) <= 0 ? Buffer.concat([a, b]) : Buffer.concat([b, a]))
+    );
+
+    const [importBitmap] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("import_bitmap"), v2Vault.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .startMigration(Array.from(root), new anchor.BN(1_000_000 * SHARE_SCALE), new anchor.BN(1_000_000))
+      .accounts({
+        vault: v2Vault,
+        importBitmap,
+        authorityTokenAccount,
+        vaultTokenAccount: v2TokenAccount,
+        authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const [holderShares] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), v2Vault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const importUser = () =>
+      program.methods
+        .importPosition(user.publicKey, 0, entries[0][2], [Array.from(leaves[1])])
+        .accounts({
+          vault: v2Vault,
+          importBitmap,
+          userShares: holderShares,
+          authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    // Only the authority imports, even with a valid proof
+    try {
+      await program.methods
+        .importPosition(user.publicKey, 0, entries[0][2], [Array.from(leaves[1])])
+        .accounts({
+          vault: v2Vault,
+          importBitmap,
+          userShares: holderShares,
+          authority: user.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "Unauthorized");
+    }
+
+    await importUser();
+    const position = await program.account.userShares.fetch(holderShares);
+    assert.equal(position.shares.toString(), entries[0][2].toString());
+
+    try {
+      await importUser();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "AlreadyImported");
+    }
+
+    // The position never imported is burned, leaving its assets to holders
+    await program.methods.endMigration().accounts({ vault: v2Vault, authority }).rpc();
+    const closed = await program.account.vault.fetch(v2Vault);
+    assert.isFalse(closed.migrationMode);
+    assert.equal(closed.totalShares.toString(), entries[0][2].toString());
+    assert.equal(closed.totalAssets.toNumber(), 1_000_000);
+
+    try {
+      await importUser();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "MigrationNotActive");
+    }
+  });
+
+  it("Only opens a migration into a fresh vault", async () => {
+    const { mint, vault: usedVault, vaultTokenAccount: usedTokenAccount } = await createVault();
+    await fundAndDeposit(usedVault, user, 1_000_000);
+    const authorityTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authorityTokenAccount,
+      authority,
+      1_000_000
+    );
+    const [importBitmap] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("import_bitmap"), usedVault.toBuffer()],
+      program.programId
+    );
+
+    // Minting v1 shares at any rate would dilute the existing depositor
+    try {
+      await program.methods
+        .startMigration(
+          Array(32).fill(0),
+          new anchor.BN(1_000_000 * SHARE_SCALE),
+          new anchor.BN(1)
+        )
+        .accounts({
+          vault: usedVault,
+          importBitmap,
+          authorityTokenAccount,
+          vaultTokenAccount: usedTokenAccount,
+          authority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "MigrationTargetNotEmpty");
+    }
+  });
+
+  it("Caps the withdrawal requests one user may have open", async () => {
+    const { vault: capVault } = await createVault();
+    const { userShares: holderShares } = await fundAndDeposit(capVault, user, 1_000_000);
+
+    const { maxRequestsPerUser } = await program.account.vault.fetch(capVault);
+    assert.equal(maxRequestsPerUser, 4);
+    const requestPda = (id: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("withdrawal_request"),
+          capVault.toBuffer(),
+          new anchor.BN(id).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+    const request = (id: number) =>
+      program.methods
+        .requestWithdrawal(new anchor.BN(100_000 * SHARE_SCALE))
+        .accounts({
+          vault: capVault,
+          userShares: holderShares,
+          withdrawalRequest: requestPda(id),
+          user: user.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+    for (let id = 0; id < maxRequestsPerUser; id++) {
+      await request(id);
+    }
+    assert.equal(
+      (await program.account.userShares.fetch(holderShares)).openRequests,
+      maxRequestsPerUser
+    );
+
+    try {
+      await request(maxRequestsPerUser);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "TooManyRequests");
+    }
+
+    // Cancelling one frees a slot
+    await program.methods
+      .cancelWithdrawal()
+      .accounts({
+        vault: capVault,
+        userShares: holderShares,
+        withdrawalRequest: requestPda(0),
+        user: user.publicKey,
+      })
+      .signers([user])
+      .rpc();
+    await request(maxRequestsPerUser);
+    assert.equal(
+      (await program.account.userShares.fetch(holderShares)).openRequests,
+      maxRequestsPerUser
+    );
+  });
+
+  it("Reports what a pending withdrawal will pay under either rate policy", async () => {
+    const { vault: lockVault, vaultTokenAccount: lockTokenAccount } = await createVault();
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } =
+      await fundAndDeposit(lockVault, user, 1_000_000);
+
+    const requestPda = (id: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("withdrawal_request"),
+          lockVault.toBuffer(),
+          new anchor.BN(id).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+    const request = (id: number) =>
+      program.methods
+        .requestWithdrawal(new anchor.BN(400_000 * SHARE_SCALE))
+        .accounts({
+          vault: lockVault,
+          userShares: holderShares,
+          withdrawalRequest: requestPda(id),
+          user: user.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+    const pendingValue = (id: number) =>
+      program.methods
+        .pendingWithdrawalValue()
+        .accounts({ vault: lockVault, withdrawalRequest: requestPda(id) })
+        .view();
+
+    await program.methods.setLockRateAtRequest(true).accounts({ vault: lockVault, authority }).rpc();
+    await request(0);
+    const locked = await program.account.withdrawalRequest.fetch(requestPda(0));
+    assert.equal(locked.lockedAssets.toNumber(), 400_000);
+    assert.equal((await pendingValue(0)).toNumber(), 400_000);
+
+    await program.methods.setLockRateAtRequest(false).accounts({ vault: lockVault, authority }).rpc();
+    await request(1);
+    const floating = await program.account.withdrawalRequest.fetch(requestPda(1));
+    assert.isNull(floating.lockedAssets);
+    assert.equal((await pendingValue(1)).toNumber(), 400_000);
+
+    // The locked request keeps its value after the policy changes
+    const balanceBefore = (await getAccount(provider.connection, holderTokenAccount)).amount;
+    await program.methods
+      .claimWithdrawal()
+      .accounts({
+        vault: lockVault,
+        userShares: holderShares,
+        withdrawalRequest: requestPda(0),
+        userTokenAccount: holderTokenAccount,
+        vaultTokenAccount: lockTokenAccount,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    const balanceAfter = (await getAccount(provider.connection, holderTokenAccount)).amount;
+    assert.equal(Number(balanceAfter - balanceBefore), 400_000);
+  });
+
+  it("Withdraws to a recipient's associated token account, creating it once", async () => {
+    const { mint, vault: ataVault, vaultTokenAccount: ataTokenAccount } = await createVault();
+    const { userShares: holderShares } = await fundAndDeposit(ataVault, user, 1_000_000);
+
+    const recipient = anchor.web3.Keypair.generate().publicKey;
+    const recipientTokenAccount = getAssociatedTokenAddressSync(mint, recipient);
+    assert.isNull(await provider.connection.getAccountInfo(recipientTokenAccount));
+
+    const withdrawToAta = (shares: number) =>
+      program.methods
+        .withdrawToAta(new anchor.BN(shares))
+        .accounts({
+          vault: ataVault,
+          userShares: holderShares,
+          tokenMint: mint,
+          recipient,
+          recipientTokenAccount,
+          vaultTokenAccount: ataTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+    await withdrawToAta(300_000 * SHARE_SCALE);
+    let account = await getAccount(provider.connection, recipientTokenAccount);
+    assert.isTrue(account.owner.equals(recipient));
+    assert.equal(Number(account.amount), 300_000);
+
+    // The second withdrawal reuses the existing account
+    await withdrawToAta(200_000 * SHARE_SCALE);
+    account = await getAccount(provider.connection, recipientTokenAccount);
+    assert.equal(Number(account.amount), 500_000);
+  });
+
+  it("Deposits the output of a swap through the registered DEX", async () => {
+    const { mint, vault: swapVault, vaultTokenAccount: swapTokenAccount } = await createVault();
+    const otherMint = await createMint(
+      provider.connection,
+      provider.wallet.payer,
+      authority,
+      null,
+      6
+    );
+    const sourceTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      otherMint,
+      user.publicKey
+    );
+    const holderTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      user.publicKey
+    );
+    // Stand-in for the DEX pool: the token program moving underlying from it
+    const poolTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      user.publicKey,
+      anchor.web3.Keypair.generate()
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      poolTokenAccount,
+      authority,
+      1_000_000
+    );
+    const [holderShares] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), swapVault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .setDexProgram(TOKEN_PROGRAM_ID)
+      .accounts({ vault: swapVault, authority })
+      .rpc();
+
+    const swapIx = createTransferInstruction(
+      poolTokenAccount,
+      holderTokenAccount,
+      user.publicKey,
+      500_000
+    );
+    const swapAndDeposit = (minOut: number) =>
+      program.methods
+        .swapAndDeposit(
+          new anchor.BN(100_000),
+          new anchor.BN(minOut),
+          new anchor.BN(500_000 * SHARE_SCALE),
+          swapIx.data,
+          swapIx.keys.length
+        )
+        .accounts({
+          deposit: {
+            vault: swapVault,
+            userShares: holderShares,
+            userTokenAccount: holderTokenAccount,
+            vaultTokenAccount: swapTokenAccount,
+            user: user.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          },
+          sourceTokenAccount,
+          dexProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(swapIx.keys)
+        .signers([user])
+        .rpc();
+
+    try {
+      await swapAndDeposit(500_001);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "SlippageExceeded");
+    }
+
+    await swapAndDeposit(500_000);
+    const position = await program.account.userShares.fetch(holderShares);
+    assert.equal(position.shares.toNumber(), 500_000 * SHARE_SCALE);
+    const vaultBalance = (await getAccount(provider.connection, swapTokenAccount)).amount;
+    assert.equal(Number(vaultBalance), 500_000);
+  });
+
+  it("Reads the vault's terms as one policy", async () => {
+    const { vault: policyVault } = await createVault(1);
+    await program.methods
+      .setWithdrawalQueue(new anchor.BN(3_600), new anchor.BN(0))
+      .accounts({ vault: policyVault, authority })
+      .rpc();
+    await program.methods.setManagementFee(200).accounts({ vault: policyVault, authority }).rpc();
+
+    const policy = await program.methods.getPolicy().accounts({ vault: policyVault }).view();
+    assert.equal(policy.rounding, 1);
+    assert.equal(policy.withdrawalCooldown.toNumber(), 3_600);
+    assert.equal(policy.managementFeeBps, 200);
+    assert.equal(policy.maxRequestsPerUser, 4);
+    assert.equal(policy.maxExitFeeBps, 0);
+    assert.isFalse(policy.paramsLocked);
+  });
+
+  it("Rejects a deposit that concentrates too much of the vault in one position", async () => {
+    const { vault: capVault } = await createVault();
+    await program.methods.setMaxUserShareBps(5_000).accounts({ vault: capVault, authority }).rpc();
+
+    // The bootstrap deposit holds everything and is exempt
+    const whale = await fundAndDeposit(capVault, user, 1_000_000, 2_000_000);
+    await fundAndDeposit(capVault, provider.wallet.payer, 1_000_000);
+
+    try {
+      await program.methods
+        .deposit(new anchor.BN(500_000))
+        .accounts(whale)
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "ConcentrationLimitExceeded");
+    }
+  });
+
+  it("Compounds management fee shares into the protocol position and withdraws them", async () => {
+    const { mint, vault: feeVault, vaultTokenAccount: feeTokenAccount } =
+      await createVault();
+    const treasury = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    await program.methods
+      .setFeeRecipient()
+      .accounts({ vault: feeVault, feeRecipient: treasury, authority })
+      .rpc();
+    await program.methods
+      .setManagementFee(1_000)
+      .accounts({ vault: feeVault, authority })
+      .rpc();
+    await fundAndDeposit(feeVault, user, 1_000_000_000_000);
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const [protocolPosition] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("protocol_position"), feeVault.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .compoundFeeShares()
+      .accounts({
+        vault: feeVault,
+        protocolPosition,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+    const compounded = await program.account.vault.fetch(feeVault);
+    assert.equal(compounded.unclaimedFeeShares.toNumber(), 0);
+    const position = await program.account.userShares.fetch(protocolPosition);
+    assert.isTrue(position.shares.gtn(0));
+
+    await program.methods
+      .withdrawProtocolFees()
+      .accounts({
+        vault: feeVault,
+        protocolPosition,
+        destination: treasury,
+        vaultTokenAccount: feeTokenAccount,
+        authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    assert.equal((await program.account.userShares.fetch(protocolPosition)).shares.toNumber(), 0);
+    assert.isTrue((await getAccount(provider.connection, treasury)).amount > BigInt(0));
+  });
+
+  it("Rebalances deployed assets to a target ratio in one call", async () => {
+    const { mint, vault: rebalanceVault, vaultTokenAccount: rebalanceTokenAccount } =
+      await createVault();
+    await fundAndDeposit(rebalanceVault, user, 1_000_000);
+
+    const strategyTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority,
+      anchor.web3.Keypair.generate()
+    );
+    await program.methods
+      .setStrategy()
+      .accounts({ vault: rebalanceVault, strategyTokenAccount, authority })
+      .rpc();
+
+    const rebalance = (targetBps: number) =>
+      program.methods
+        .rebalance(targetBps)
+        .accounts({
+          vault: rebalanceVault,
+          vaultTokenAccount: rebalanceTokenAccount,
+          strategyTokenAccount,
+          keeper: authority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+    await rebalance(6_000);
+    let vaultAccount = await program.account.vault.fetch(rebalanceVault);
+    assert.equal(vaultAccount.deployedAssets.toNumber(), 600_000);
+
+    await rebalance(2_000);
+    vaultAccount = await program.account.vault.fetch(rebalanceVault);
+    assert.equal(vaultAccount.deployedAssets.toNumber(), 200_000);
+    const strategyBalance = (await getAccount(provider.connection, strategyTokenAccount)).amount;
+    assert.equal(Number(strategyBalance), 200_000);
+  });
+
+  it("Drops deposit logs when silent and adds balances when verbose", async () => {
+    const { vault: logVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(logVault, user, 0, 2_000_000);
+    const depositLogs = async () => {
+      const sig = await program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+      const tx = await provider.connection.getTransaction(sig, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const logs = tx.meta.logMessages;
+      const parser = new anchor.EventParser(program.programId, program.coder);
+      return { logs, events: Array.from(parser.parseLogs(logs)).map((event) => event.name) };
+    };
+
+    await program.methods.setEventLevel(0).accounts({ vault: logVault, authority }).rpc();
+    const silent = await depositLogs();
+    assert.isFalse(silent.logs.some((line) => line.includes("Deposited")));
+    assert.include(silent.events, "DepositEvent");
+
+    await program.methods.setEventLevel(2).accounts({ vault: logVault, authority }).rpc();
+    const verbose = await depositLogs();
+    assert.isTrue(verbose.logs.some((line) => line.includes("Deposited")));
+    assert.include(verbose.events, "DepositEvent");
+    assert.include(verbose.events, "VaultBalances");
+  });
+
+  it("Refunds the part of a deposit past the cap", async () => {
+    const { vault: capVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(capVault, user, 0, 2_000_000);
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } = holderAccounts;
+    await program.methods
+      .setDepositCap(new anchor.BN(1_500_000), false)
+      .accounts({ vault: capVault, authority })
+      .rpc();
+
+    await program.methods
+      .depositOrRefund(new anchor.BN(2_000_000))
+      .accounts(holderAccounts)
+      .signers([user])
+      .rpc();
+
+    const vaultAccount = await program.account.vault.fetch(capVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 1_500_000);
+    const position = await program.account.userShares.fetch(holderShares);
+    assert.equal(position.shares.toNumber(), 1_500_000 * SHARE_SCALE);
+    const holderBalance = (await getAccount(provider.connection, holderTokenAccount)).amount;
+    assert.equal(Number(holderBalance), 500_000);
+  });
+
+  it("Tracks lifetime deposits and withdrawals exactly", async () => {
+    const { vault: flowVault, vaultTokenAccount: flowTokenAccount } = await createVault();
+    const holderAccounts = await fundAndDeposit(flowVault, user, 0, 2_000_000);
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } = holderAccounts;
+    const deposit = (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+    const withdraw = (shares: number) =>
+      program.methods
+        .withdraw(new anchor.BN(shares))
+        .accounts({
+          vault: flowVault,
+          userShares: holderShares,
+          userTokenAccount: holderTokenAccount,
+          vaultTokenAccount: flowTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    await deposit(1_000_000);
+    await withdraw(300_000 * SHARE_SCALE);
+    await deposit(500_000);
+    await withdraw(200_000 * SHARE_SCALE);
+
+    const info = await program.methods.vaultInfo().accounts({ vault: flowVault }).view();
+    assert.equal(info.cumulativeDeposits.toString(), "1500000");
+    assert.equal(info.cumulativeWithdrawals.toString(), "500000");
+  });
+
+  it("Counts same-day deposits against the daily limit in net mode", async () => {
+    const { vault: limitVault, vaultTokenAccount: limitTokenAccount } = await createVault();
+    const holderAccounts = await fundAndDeposit(limitVault, user, 0, 2_000_000);
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } = holderAccounts;
+    const deposit = (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+    const withdraw = (assets: number) =>
+      program.methods
+        .withdraw(new anchor.BN(assets * SHARE_SCALE))
+        .accounts({
+          vault: limitVault,
+          userShares: holderShares,
+          userTokenAccount: holderTokenAccount,
+          vaultTokenAccount: limitTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    // Today's 2M deposit offsets withdrawals in net mode
+    await deposit(2_000_000);
+    await program.methods
+      .setDailyWithdrawalLimit(new anchor.BN(300_000), 1)
+      .accounts({ vault: limitVault, authority })
+      .rpc();
+    await withdraw(900_000);
+
+    // Gross mode counts the 900k already paid out today
+    await program.methods
+      .setDailyWithdrawalLimit(new anchor.BN(1_000_000), 0)
+      .accounts({ vault: limitVault, authority })
+      .rpc();
+    try {
+      await withdraw(100_001);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "DailyLimitExceeded");
+    }
+    await withdraw(100_000);
+  });
+
+  it("Dry-runs a deposit and names the error it would hit", async () => {
+    const { vault: dryVault } = await createVault();
+    await program.methods
+      .setDepositCap(new anchor.BN(1_000_000), false)
+      .accounts({ vault: dryVault, authority })
+      .rpc();
+    const dryRun = (amount: number) =>
+      program.methods
+        .dryRunDeposit(new anchor.BN(amount), null)
+        .accounts({ vault: dryVault, user: user.publicKey, userShares: null, oracle: null })
+        .view();
+
+    const ok = await dryRun(500_000);
+    assert.isTrue(ok.success);
+    assert.equal(ok.shares.toNumber(), 500_000 * SHARE_SCALE);
+
+    const overCap = await dryRun(2_000_000);
+    assert.isFalse(overCap.success);
+    assert.equal(overCap.errorName, "GlobalCapExceeded");
+
+    const zero = await dryRun(0);
+    assert.equal(zero.errorName, "ZeroAmount");
+
+    // Nothing was written
+    assert.equal((await program.account.vault.fetch(dryVault)).totalShares.toNumber(), 0);
+  });
+
+  it("Closes deposits but not withdrawals once a shutdown is scheduled", async () => {
+    const { vault: closingVault, vaultTokenAccount: closingTokenAccount } = await createVault();
+    const holderAccounts = await fundAndDeposit(closingVault, user, 0, 2_000_000);
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } = holderAccounts;
+    const deposit = () =>
+      program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+    await deposit();
+
+    const shutdownAt = Math.floor(Date.now() / 1000) + 3_600;
+    await program.methods
+      .scheduleShutdown(new anchor.BN(shutdownAt))
+      .accounts({ vault: closingVault, authority })
+      .rpc();
+    const vaultAccount = await program.account.vault.fetch(closingVault);
+    assert.equal(vaultAccount.shutdownScheduledAt.toNumber(), shutdownAt);
+
+    try {
+      await deposit();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "ShutdownScheduled");
+    }
+
+    await program.methods
+      .withdraw(new anchor.BN(1_000_000 * SHARE_SCALE))
+      .accounts({
+        vault: closingVault,
+        userShares: holderShares,
+        userTokenAccount: holderTokenAccount,
+        vaultTokenAccount: closingTokenAccount,
+        user: user.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    const holderBalance = (await getAccount(provider.connection, holderTokenAccount)).amount;
+    assert.equal(Number(holderBalance), 2_000_000);
+  });
+
+  it("Boosts voting power by holding time", async () => {
+    const { vault: voteVault } = await createVault();
+    const { userShares: holderShares } = await fundAndDeposit(voteVault, user, 1_000_000);
+
+    const viewAccounts = { vault: voteVault, user: user.publicKey, userShares: holderShares };
+    const plain = await program.methods.votingPower().accounts(viewAccounts).view();
+    assert.equal(plain.toNumber(), 1_000_000 * SHARE_SCALE);
+
+    // Full 50% boost after holding for a second
+    await program.methods
+      .setVoteBoost(5_000, new anchor.BN(1))
+      .accounts({ vault: voteVault, authority })
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    const boosted = await program.methods.votingPower().accounts(viewAccounts).view();
+    assert.equal(boosted.toNumber(), 1_500_000 * SHARE_SCALE);
+
+    const newcomer = anchor.web3.Keypair.generate();
+    const none = await program.methods
+      .votingPower()
+      .accounts({ vault: voteVault, user: newcomer.publicKey, userShares: null })
+      .view();
+    assert.equal(none.toNumber(), 0);
+  });
+
+  it("Limits withdrawals during the bootstrap period until it lapses", async () => {
+    const { vault: launchVault, vaultTokenAccount: launchTokenAccount } =
+      await createVault(0, PPS_SCALE, 4);
+    await program.methods
+      .setBootstrapLimits(new anchor.BN(400_000), new anchor.BN(0), true)
+      .accounts({ vault: launchVault, authority })
+      .rpc();
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } =
+      await fundAndDeposit(launchVault, user, 1_000_000);
+    const withdraw = (assets: number) =>
+      program.methods
+        .withdraw(new anchor.BN(assets * SHARE_SCALE))
+        .accounts({
+          vault: launchVault,
+          userShares: holderShares,
+          userTokenAccount: holderTokenAccount,
+          vaultTokenAccount: launchTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    try {
+      await withdraw(500_000);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "BootstrapWithdrawLimitExceeded");
+    }
+    await withdraw(400_000);
+
+    await new Promise((resolve) => setTimeout(resolve, 5000));
+    await withdraw(600_000);
+    const vaultAccount = await program.account.vault.fetch(launchVault);
+    assert.equal(vaultAccount.bootstrapUntil.toNumber(), 0);
+    assert.equal(vaultAccount.totalShares.toNumber(), 0);
+  });
+
+  it("Re-prices shares to a keeper-reported NAV within the guardrails", async () => {
+    const { vault: navVault } = await createVault();
+    await fundAndDeposit(navVault, user, 1_000_000);
+
+    await program.methods
+      .setNavGuardrails(1_000, new anchor.BN(3_600))
+      .accounts({ vault: navVault, authority })
+      .rpc();
+    const updateNav = (totalAssets: number) =>
+      program.methods
+        .updateNav(new anchor.BN(totalAssets))
+        .accounts({ vault: navVault, keeper: authority })
+        .rpc();
+
+    try {
+      await updateNav(1_200_000);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "NavChangeTooLarge");
+    }
+
+    await updateNav(1_050_000);
+    const vaultAccount = await program.account.vault.fetch(navVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 1_050_000);
+    assert.equal(vaultAccount.deployedAssets.toNumber(), 50_000);
+
+    try {
+      await updateNav(1_060_000);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "NavUpdateTooSoon");
+    }
+  });
+
+  it("Compounds rewards paid in the underlying into new shares", async () => {
+    const { mint, vault: compoundVault, vaultTokenAccount: compoundTokenAccount } =
+      await createVault();
+    const rewardTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      compoundVault,
+      anchor.web3.Keypair.generate()
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      rewardTokenAccount,
+      authority,
+      1_000_000
+    );
+    const { userShares: holderShares } = await fundAndDeposit(compoundVault, user, 1_000_000);
+
+    // Reward funding must not mix with depositor principal
+    try {
+      await program.methods
+        .setRewardToken()
+        .accounts({
+          vault: compoundVault,
+          rewardMint: mint,
+          rewardTokenAccount: compoundTokenAccount,
+          authority,
+        })
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidParameter");
+    }
+    await program.methods
+      .setRewardToken()
+      .accounts({ vault: compoundVault, rewardMint: mint, rewardTokenAccount, authority })
+      .rpc();
+    await program.methods
+      .setRewardRate(new anchor.BN(1_000))
+      .accounts({ vault: compoundVault, authority })
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    await program.methods
+      .setRewardRate(new anchor.BN(0))
+      .accounts({ vault: compoundVault, authority })
+      .rpc();
+
+    const compound = () =>
+      program.methods
+        .claimAndCompound(null)
+        .accounts({
+          vault: compoundVault,
+          userShares: holderShares,
+          rewardTokenAccount,
+          vaultTokenAccount: compoundTokenAccount,
+          userRewardTokenAccount: null,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    // Compounding is a deposit, so the deposit cap holds it back
+    const admin = { vault: compoundVault, authority };
+    await program.methods.setDepositCap(new anchor.BN(1_000_000), false).accounts(admin).rpc();
+    try {
+      await compound();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "GlobalCapExceeded");
+    }
+    await program.methods.setDepositCap(new anchor.BN(0), false).accounts(admin).rpc();
+    await compound();
+
+    const pool = await getAccount(provider.connection, rewardTokenAccount);
+    const compounded = 1_000_000 - Number(pool.amount);
+    assert.isTrue(compounded > 0);
+    const vaultAccount = await program.account.vault.fetch(compoundVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 1_000_000 + compounded);
+    const position = await program.account.userShares.fetch(holderShares);
+    assert.equal(position.shares.toNumber(), (1_000_000 + compounded) * SHARE_SCALE);
+    assert.equal(position.pendingRewards.toNumber(), 0);
+  });
+
+  it("Blocks a same-slot deposit and withdrawal only when configured", async () => {
+    const { vault: slotVault, vaultTokenAccount: slotTokenAccount } = await createVault();
+    const holderAccounts = await fundAndDeposit(slotVault, user, 0, 1_000_000);
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } = holderAccounts;
+    // Deposit and withdraw in one transaction, so in one slot
+    const roundTrip = async () => {
+      const withdrawIx = await program.methods
+        .withdraw(new anchor.BN(1_000_000 * SHARE_SCALE))
+        .accounts({
+          vault: slotVault,
+          userShares: holderShares,
+          userTokenAccount: holderTokenAccount,
+          vaultTokenAccount: slotTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .instruction();
+      return program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts(holderAccounts)
+        .postInstructions([withdrawIx])
+        .signers([user])
+        .rpc();
+    };
+
+    await roundTrip();
+    let position = await program.account.userShares.fetch(holderShares);
+    assert.equal(position.shares.toNumber(), 0);
+
+    await program.methods
+      .setBlockSameSlot(true)
+      .accounts({ vault: slotVault, authority })
+      .rpc();
+    try {
+      await roundTrip();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "SameSlotActionBlocked");
+    }
+
+    // Exiting through a vested withdrawal is blocked the same way
+    const [vestedExit] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vested_exit"), slotVault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const vestIx = await program.methods
+      .startVestedWithdrawal(new anchor.BN(1_000_000 * SHARE_SCALE), new anchor.BN(60))
+      .accounts({
+        vault: slotVault,
+        userShares: holderShares,
+        vestedExit,
+        user: user.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .instruction();
+    try {
+      await program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts(holderAccounts)
+        .postInstructions([vestIx])
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "SameSlotActionBlocked");
+    }
+    position = await program.account.userShares.fetch(holderShares);
+    assert.equal(position.shares.toNumber(), 0);
+  });
+
+  it("Reports the smallest deposit that mints a share", async () => {
+    // Two assets per share unit
+    const { vault: pricedVault } = await createVault(0, PPS_SCALE * SHARE_SCALE * 2);
+    const empty = await program.methods.minDepositable().accounts({ vault: pricedVault }).view();
+    assert.equal(empty.toNumber(), 1);
+
+    const holderAccounts = await fundAndDeposit(pricedVault, user, 0, 1_000_010);
+    const deposit = (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+    await deposit(1_000_000);
+
+    const minimum = await program.methods.minDepositable().accounts({ vault: pricedVault }).view();
+    assert.equal(minimum.toNumber(), 2);
+    try {
+      await deposit(minimum.toNumber() - 1);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "ZeroSharesMinted");
+    }
+    await deposit(minimum.toNumber());
+  });
+
+  it("Charges the deposit fee of the highest tier reached", async () => {
+    const { vault: tierVault } = await createVault();
+    const admin = { vault: tierVault, authority };
+    try {
+      await program.methods
+        .setDepositFeeTiers([
+          { threshold: new anchor.BN(1_000_000), feeBps: 50 },
+          { threshold: new anchor.BN(0), feeBps: 100 },
+        ])
+        .accounts(admin)
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidFeeTiers");
+    }
+    await program.methods
+      .setDepositFeeTiers([
+        { threshold: new anchor.BN(0), feeBps: 100 },
+        { threshold: new anchor.BN(1_000_000), feeBps: 50 },
+      ])
+      .accounts(admin)
+      .rpc();
+
+    const holderAccounts = await fundAndDeposit(tierVault, user, 0, 1_100_000);
+    const { userShares: holderShares } = holderAccounts;
+    const deposit = (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+
+    // 1% below the whale tier, 0.5% from it
+    await deposit(100_000);
+    const quote = await program.methods
+      .quoteDeposit(new anchor.BN(1_000_000))
+      .accounts({ vault: tierVault, user: user.publicKey, userShares: holderShares })
+      .view();
+    assert.equal(quote.fee.toNumber(), 5_000);
+    await deposit(1_000_000);
+
+    const vaultAccount = await program.account.vault.fetch(tierVault);
+    assert.equal(vaultAccount.penaltyPool.toNumber(), 6_000);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 1_094_000);
+    const position = await program.account.userShares.fetch(holderShares);
+    assert.equal(position.shares.toNumber(), 1_094_000 * SHARE_SCALE);
+
+    // Once locked, moving the whale tier up would charge 1M-2M deposits 1%
+    await program.methods.lockParams().accounts(admin).rpc();
+    const setTiers = (whaleThreshold: number) =>
+      program.methods
+        .setDepositFeeTiers([
+          { threshold: new anchor.BN(0), feeBps: 100 },
+          { threshold: new anchor.BN(whaleThreshold), feeBps: 50 },
+        ])
+        .accounts(admin)
+        .rpc();
+    try {
+      await setTiers(2_000_000);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "ParamsLocked");
+    }
+    await setTiers(500_000);
+  });
+
+  it("Trips the circuit breaker on a PPS drop and recovers in one step", async () => {
+    const { vault: breakerVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(breakerVault, user, 0, 2_000_000);
+    const deposit = () =>
+      program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+    await deposit();
+
+    const admin = { vault: breakerVault, authority };
+    const updateNav = (totalAssets: number) =>
+      program.methods
+        .updateNav(new anchor.BN(totalAssets))
+        .accounts({ vault: breakerVault, keeper: authority })
+        .rpc();
+    await program.methods.setCircuitBreaker(500).accounts(admin).rpc();
+    await updateNav(1_100_000);
+    // A 9% drop trips the 5% breaker
+    await updateNav(1_000_000);
+    let vaultAccount = await program.account.vault.fetch(breakerVault);
+    assert.isTrue(vaultAccount.breakerTripped);
+    assert.isTrue(vaultAccount.paused);
+
+    try {
+      await program.methods
+        .unpause()
+        .accounts({ vault: breakerVault, operator: authority })
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "BreakerTripped");
+    }
+    try {
+      await deposit();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "VaultPaused");
+    }
+
+    await program.methods.recoverAndUnpause(new anchor.BN(1_050_000)).accounts(admin).rpc();
+    vaultAccount = await program.account.vault.fetch(breakerVault);
+    assert.isFalse(vaultAccount.breakerTripped);
+    assert.isFalse(vaultAccount.paused);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 1_050_000);
+    assert.equal(vaultAccount.deployedAssets.toNumber(), 50_000);
+    assert.equal(vaultAccount.lastPps.toNumber(), 1_050 * PPS_SCALE / 1_000);
+    await deposit();
+
+    try {
+      await program.methods.recoverAndUnpause(new anchor.BN(2_050_000)).accounts(admin).rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "BreakerNotTripped");
+    }
+  });
+
+  it("Mints shares at the configured share decimals", async () => {
+    // 6-decimal underlying, 9-decimal shares
+    const { vault: displayVault } = await createVault(0, PPS_SCALE, 0, 9);
+    await fundAndDeposit(displayVault, user, 1_000_000);
+
+    const vaultAccount = await program.account.vault.fetch(displayVault);
+    assert.equal(vaultAccount.shareDecimals, 9);
+    assert.equal(vaultAccount.totalShares.toNumber(), 1_000_000_000);
+    const info = await program.methods.vaultInfo().accounts({ vault: displayVault }).view();
+    assert.equal(info.pricePerShare.toNumber(), PPS_SCALE);
+
+    // Shares may not have fewer decimals than the underlying
+    try {
+      await createVault(0, PPS_SCALE, 0, 5);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidParameter");
+    }
+  });
+
+  it("Reports APY over a chosen window from the PPS checkpoints", async () => {
+    const { vault: apyVault } = await createVault();
+    await fundAndDeposit(apyVault, user, 1_000_000);
+
+    const [ppsHistory] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("pps_history"), apyVault.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .initPpsHistory()
+      .accounts({
+        vault: apyVault,
+        ppsHistory,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+    await program.methods
+      .recordPps()
+      .accounts({ vault: apyVault, ppsHistory, authority })
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    await program.methods
+      .updateNav(new anchor.BN(1_010_000))
+      .accounts({ vault: apyVault, keeper: authority })
+      .rpc();
+
+    const apyOver = (windowSeconds: number) =>
+      program.methods
+        .apyOver(new anchor.BN(windowSeconds))
+        .accounts({ vault: apyVault, ppsHistory })
+        .view();
+    assert.isTrue((await apyOver(1)) > 0);
+    // No checkpoint is a week old yet
+    assert.equal(await apyOver(7 * 86_400), 0);
+  });
+
+  it("Absorbs strategy losses from the insurance fund first", async () => {
+    const { mint, vault: insuredVault, vaultTokenAccount: insuredTokenAccount } =
+      await createVault();
+    const funderTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      funderTokenAccount,
+      authority,
+      1_000_000
+    );
+    await fundAndDeposit(insuredVault, user, 1_000_000);
+    // Book 100_000 as deployed so there is something to lose
+    await program.methods
+      .updateNav(new anchor.BN(1_100_000))
+      .accounts({ vault: insuredVault, keeper: authority })
+      .rpc();
+
+    await program.methods
+      .fundInsurance(new anchor.BN(30_000))
+      .accounts({
+        vault: insuredVault,
+        funderTokenAccount,
+        vaultTokenAccount: insuredTokenAccount,
+        funder: authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    const admin = { vault: insuredVault, authority };
+
+    // Fully covered: holders see no write-down
+    await program.methods.reportLoss(new anchor.BN(20_000)).accounts(admin).rpc();
+    let vaultAccount = await program.account.vault.fetch(insuredVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 1_100_000);
+    assert.equal(vaultAccount.insuranceFund.toNumber(), 10_000);
+    assert.equal(vaultAccount.deployedAssets.toNumber(), 80_000);
+
+    // Only the part beyond the fund is written down
+    await program.methods.reportLoss(new anchor.BN(50_000)).accounts(admin).rpc();
+    vaultAccount = await program.account.vault.fetch(insuredVault);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 1_060_000);
+    assert.equal(vaultAccount.insuranceFund.toNumber(), 0);
+  });
+
+  it("Throttles deposit size by vault age", async () => {
+    const { vault: youngVault } = await createVault();
+    await program.methods
+      .setDepositThrottle(new anchor.BN(500_000), new anchor.BN(100_000))
+      .accounts({ vault: youngVault, authority })
+      .rpc();
+    const holderAccounts = await fundAndDeposit(youngVault, user, 0, 1_000_000);
+    const deposit = (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+
+    // Day 0: only the base is open, the per-day allowance has not accrued
+    const maxDeposit = await program.methods
+      .maxDeposit()
+      .accounts({ vault: youngVault, user: user.publicKey, userShares: null })
+      .view();
+    assert.equal(maxDeposit.toNumber(), 500_000);
+    try {
+      await deposit(500_001);
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "ThrottleExceeded");
+    }
+    await deposit(500_000);
+  });
+
+  it("Rejects deposits against a stale PPS until it is refreshed", async () => {
+    const { vault: navVault } = await createVault();
+    const holderAccounts = await fundAndDeposit(navVault, user, 0, 1_000_000);
+    const deposit = () =>
+      program.methods
+        .deposit(new anchor.BN(500_000))
+        .accounts(holderAccounts)
+        .signers([user])
+        .rpc();
+
+    await program.methods
+      .setMaxPpsStaleness(new anchor.BN(2))
+      .accounts({ vault: navVault, authority })
+      .rpc();
+    // Let the update recorded at initialization age past the limit
+    await new Promise((resolve) => setTimeout(resolve, 4000));
+    try {
+      await deposit();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "StalePps");
+    }
+
+    await program.methods.accrueInterest().accounts({ vault: navVault }).rpc();
+    await deposit();
+  });
+
+  it("Discounts the deposit fee for governance token holders", async () => {
+    const { vault: govVault, vaultTokenAccount: discountTokenAccount } = await createVault();
+    const admin = { vault: govVault, authority };
+    const govMint = await createMint(
+      provider.connection,
+      provider.wallet.payer,
+      authority,
+      null,
+      6
+    );
+    await program.methods
+      .setDepositFeeTiers([{ threshold: new anchor.BN(0), feeBps: 100 }])
+      .accounts(admin)
+      .rpc();
+    // Full 50% discount at 1_000 gov tokens
+    await program.methods
+      .setFeeDiscount(govMint, 5_000, new anchor.BN(1_000))
+      .accounts(admin)
+      .rpc();
+
+    const holderAccounts = await fundAndDeposit(govVault, user, 0, 200_000);
+    const holderGovAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      govMint,
+      user.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      provider.wallet.payer,
+      govMint,
+      holderGovAccount,
+      authority,
+      500
+    );
+    const deposit = (amount: number, govTokenAccount: anchor.web3.PublicKey | null) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts({ ...holderAccounts, govTokenAccount })
+        .signers([user])
+        .rpc();
+
+    const quoted = await program.methods
+      .dryRunDeposit(new anchor.BN(100_000), null)
+      .accounts({
+        vault: govVault,
+        user: user.publicKey,
+        userShares: null,
+        oracle: null,
+        govTokenAccount: holderGovAccount,
+      })
+      .view();
+    assert.equal(quoted.shares.toNumber(), (100_000 - 750) * SHARE_SCALE);
+
+    // Full 1% fee without the gov account, then half of the 50% discount
+    await deposit(100_000, null);
+    await deposit(100_000, holderGovAccount);
+
+    const vaultAccount = await program.account.vault.fetch(govVault);
+    assert.equal(vaultAccount.penaltyPool.toNumber(), 1_000 + 750);
+    assert.equal(vaultAccount.totalAssets.toNumber(), 200_000 - 1_750);
+
+    // The exit fee is discounted the same way on the other withdraw paths
+    await program.methods
+      .setExitFee(100, new anchor.BN(1_000_000))
+      .accounts(admin)
+      .rpc();
+    const exitShares = new anchor.BN(50_000 * SHARE_SCALE);
+    const fullFee = Math.floor(
+      (vaultAccount.totalAssets.toNumber() * (50_000 * SHARE_SCALE)) /
+        vaultAccount.totalShares.toNumber() /
+        100
+    );
+    await program.methods
+      .withdrawTo(exitShares)
+      .accounts({
+        vault: govVault,
+        userShares: holderAccounts.userShares,
+        destination: holderAccounts.userTokenAccount,
+        approvedDestination: null,
+        vaultTokenAccount: discountTokenAccount,
+        user: user.publicKey,
+        oracle: null,
+        govTokenAccount: holderGovAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    const exitFee =
+      (await program.account.vault.fetch(govVault)).penaltyPool.toNumber() - 1_750;
+    assert.isAbove(exitFee, 0);
+    assert.isAtMost(exitFee, Math.ceil(fullFee * 0.75));
+  });
+
+  it("Reports solvency without failing", async () => {
+    const { vault: healthVault, vaultTokenAccount: healthTokenAccount } = await createVault();
+    const status = () =>
+      program.methods
+        .isSolvent()
+        .accounts({ vault: healthVault, vaultTokenAccount: healthTokenAccount })
+        .view();
+
+    let health = await status();
+    assert.isTrue(health.solvent);
+    assert.isTrue(health.supplyConsistent);
+    assert.equal(health.shortfall.toNumber(), 0);
+
+    await fundAndDeposit(healthVault, user, 50_000);
+
+    health = await status();
+    assert.isTrue(health.solvent);
+    assert.equal(health.shortfall.toNumber(), 0);
+  });
+
+  it("Converts shares to receipt tokens and back", async () => {
+    const { vault: receiptVault } = await createVault();
+    const [receiptMint] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("receipt_mint"), receiptVault.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .enableReceipts()
+      .accounts({
+        vault: receiptVault,
+        receiptMint,
+        authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+    const { userShares: holderShares } = await fundAndDeposit(receiptVault, user, 10_000);
+    const holderReceiptAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      receiptMint,
+      user.publicKey
+    );
+
+    const receiptAccounts = {
+      vault: receiptVault,
+      userShares: holderShares,
+      receiptMint,
+      userReceiptAccount: holderReceiptAccount,
+      user: user.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+    const total = 10_000 * SHARE_SCALE;
+    await program.methods
+      .mintReceipt(new anchor.BN(total / 4))
+      .accounts(receiptAccounts)
+      .signers([user])
+      .rpc();
+
+    // Position plus receipt supply always add up to the shares issued
+    let vaultAccount = await program.account.vault.fetch(receiptVault);
+    let position = await program.account.userShares.fetch(holderShares);
+    let receipts = await getAccount(provider.connection, holderReceiptAccount);
+    assert.equal(Number(receipts.amount), total / 4);
+    assert.equal(vaultAccount.receiptShares.toNumber(), total / 4);
+    assert.equal(position.shares.toNumber() + Number(receipts.amount), total);
+    assert.equal(vaultAccount.totalShares.toNumber(), total);
+
+    const redeem = () =>
+      program.methods
+        .redeemReceipt(new anchor.BN(total / 4), null)
+        .accounts({ ...receiptAccounts, systemProgram: anchor.web3.SystemProgram.programId })
+        .signers([user])
+        .rpc();
+
+    // Redeeming is held to the concentration limit like a deposit
+    const admin = { vault: receiptVault, authority };
+    await program.methods.setMaxUserShareBps(8_000).accounts(admin).rpc();
+    try {
+      await redeem();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "ConcentrationLimitExceeded");
+    }
+    await program.methods.setMaxUserShareBps(0).accounts(admin).rpc();
+    await redeem();
+
+    vaultAccount = await program.account.vault.fetch(receiptVault);
+    position = await program.account.userShares.fetch(holderShares);
+    receipts = await getAccount(provider.connection, holderReceiptAccount);
+    assert.equal(Number(receipts.amount), 0);
+    assert.equal(vaultAccount.receiptShares.toNumber(), 0);
+    assert.equal(position.shares.toNumber(), total);
+  });
+
+  it("Migrates a position into a vault of the registered deployment", async () => {
+    const { mint, vault: sourceVault, vaultTokenAccount: sourceTokenAccount } =
+      await createVault();
+    const [destinationVault] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), mint.toBuffer()],
+      MIGRATION_PROGRAM_ID
+    );
+    const destinationTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      destinationVault,
+      anchor.web3.Keypair.generate()
+    );
+    await migrationProgram.methods
+      .initialize(0, new anchor.BN(PPS_SCALE), new anchor.BN(0), null)
+      .accounts({
+        vault: destinationVault,
+        tokenMint: mint,
+        vaultTokenAccount: destinationTokenAccount,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } =
+      await fundAndDeposit(sourceVault, user, 10_000);
+
+    const [destinationShares] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), destinationVault.toBuffer(), user.publicKey.toBuffer()],
+      MIGRATION_PROGRAM_ID
+    );
+    const migrate = () =>
+      program.methods
+        .migratePosition()
+        .accounts({
+          sourceVault,
+          sourceUserShares: holderShares,
+          sourceVaultTokenAccount: sourceTokenAccount,
+          destinationVault,
+          destinationUserShares: destinationShares,
+          destinationVaultTokenAccount: destinationTokenAccount,
+          userTokenAccount: holderTokenAccount,
+          user: user.publicKey,
+          sourceOracle: null,
+          destinationProgram: MIGRATION_PROGRAM_ID,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+    // Only the deployment the admin registered is a valid destination
+    try {
+      await migrate();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "MigrationProgramMismatch");
+    }
+    await program.methods
+      .setMigrationProgram(MIGRATION_PROGRAM_ID)
+      .accounts({ vault: sourceVault, authority })
+      .rpc();
+    await migrate();
+
+    const source = await program.account.vault.fetch(sourceVault);
+    assert.equal(source.totalAssets.toNumber(), 0);
+    assert.equal(source.totalShares.toNumber(), 0);
+    const sourcePosition = await program.account.userShares.fetch(holderShares);
+    assert.equal(sourcePosition.shares.toNumber(), 0);
+
+    const destination = await migrationProgram.account.vault.fetch(destinationVault);
+    assert.equal(destination.totalAssets.toNumber(), 10_000);
+    const destinationPosition = await migrationProgram.account.userShares.fetch(
+      destinationShares
+    );
+    assert.equal(destinationPosition.shares.toNumber(), 10_000 * SHARE_SCALE);
+    const sourceBalance = await getAccount(provider.connection, sourceTokenAccount);
+    assert.equal(Number(sourceBalance.amount), 0);
+  });
+
+  it("Applies the bootstrap withdraw limit to queued and vested exits", async () => {
+    const { vault: launchVault, vaultTokenAccount: launchTokenAccount } =
+      await createVault(0, PPS_SCALE, 600);
+    await program.methods
+      .setBootstrapLimits(new anchor.BN(400_000), new anchor.BN(0), false)
+      .accounts({ vault: launchVault, authority })
+      .rpc();
+    const { userTokenAccount: holderTokenAccount, userShares: holderShares } =
+      await fundAndDeposit(launchVault, user, 1_000_000);
+
+    // A zero-cooldown request is claimable at once, but the claim is capped
+    const [withdrawalRequest] = anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("withdrawal_request"),
+        launchVault.toBuffer(),
+        new anchor.BN(0).toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+    await program.methods
+      .requestWithdrawal(new anchor.BN(500_000 * SHARE_SCALE))
+      .accounts({
+        vault: launchVault,
+        userShares: holderShares,
+        withdrawalRequest,
+        user: user.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+    try {
+      await program.methods
+        .claimWithdrawal()
+        .accounts({
+          vault: launchVault,
+          userShares: holderShares,
+          withdrawalRequest,
+          userTokenAccount: holderTokenAccount,
+          vaultTokenAccount: launchTokenAccount,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "BootstrapWithdrawLimitExceeded");
+    }
+
+    const [vestedExit] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vested_exit"), launchVault.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    try {
+      await program.methods
+        .startVestedWithdrawal(new anchor.BN(500_000 * SHARE_SCALE), new anchor.BN(60))
+        .accounts({
+          vault: launchVault,
+          userShares: holderShares,
+          vestedExit,
+          user: user.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have thrown");
+    } catch (err) {
+      assert.include(err.toString(), "BootstrapWithdrawLimitExceeded");
+    }
+  });
+
+  it("Charges the loss-window penalty on a migration", async () => {
+    const { mint, vault: sourceVault, vaultTokenAccount: sourceTokenAccount } =
+      await createVault();
+    const [destinationVault] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), mint.toBuffer()],
+      MIGRATION_PROGRAM_ID
+    );
+    const destinationTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      destinationVault,
+      anchor.web3.Keypair.generate()
+    );
+    await migrationProgram.methods
+      .initialize(0, new anchor.BN(PPS_SCALE), new anchor.BN(0), null)
+      .accounts({
+        vault: destinationVault,
+        tokenMint: mint,
+        vaultTokenAccount: destinationTokenAccount,
+        authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+    const admin = { vault: sourceVault, authority };
+    await program.methods.setMigrationProgram(MIGRATION_PROGRAM_ID).accounts(admin).rpc();
+
+    // The user and the authority hold half of the source vault each
+    const holder = await fundAndDeposit(sourceVault, user, 500_000);
+    await fundAndDeposit(sourceVault, provider.wallet.payer, 500_000);
+
+    const strategyTokenAccount = await createAccount(
+      provider.connection,
+      provider.wallet.payer,
+      mint,
+      authority,
+      anchor.web3.Keypair.generate()
+    );
+    await program.methods
+      .setStrategy()
+      .accounts({ vault: sourceVault, strategyTokenAccount, authority })
+      .rpc();
+    await program.methods
+      .allocateToStrategy(new anchor.BN(300_000))
+      .accounts({
+        vault: sourceVault,
+        vaultTokenAccount: sourceTokenAccount,
+        strategyTokenAccount,
+        keeper: authority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    await program.methods.setLossWindow(new anchor.BN(3_600), 500).accounts(admin).rpc();
+    await program.methods.reportLoss(new anchor.BN(100_000)).accounts(admin).rpc();
+
+    const [destinationShares] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user_shares"), destinationVault.toBuffer(), user.publicKey.toBuffer()],
+      MIGRATION_PROGRAM_ID
+    );
+    await program.methods
+      .migratePosition()
+      .accounts({
+        sourceVault,
+        sourceUserShares: holder.userShares,
+        sourceVaultTokenAccount: sourceTokenAccount,
+        destinationVault,
+        destinationUserShares: destinationShares,
+        destinationVaultTokenAccount: destinationTokenAccount,
+        userTokenAccount: holder.userTokenAccount,
+        user: user.publicKey,
+        sourceOracle: null,
+        destinationProgram: MIGRATION_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    // Half of the 900k left is 450k; 5% of it stays with the authority
+    const destination = await migrationProgram.account.vault.fetch(destinationVault);
+    assert.equal(destination.totalAssets.toNumber(), 427_500);
+    const source = await program.account.vault.fetch(sourceVault);
+    assert.equal(source.totalAssets.toNumber(), 900_000 - 427_500);
+  });
 });